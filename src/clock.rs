@@ -5,6 +5,8 @@ use crate::riscv::register::{ time, timeh,  };
 
 pub static mut TICK: usize = 0;
 static TIMEBASE: u64 = 100000;
+// qemu virt 的 timebase-frequency 为 10MHz ，每 TIMEBASE 个周期产生一次时钟中断
+pub const TICKS_PER_SEC: usize = 100;
 
 pub fn init() {
     println!("++++setup timer ok!++++");
@@ -38,6 +40,11 @@ pub fn init() {
     println!("end")
 }
 
+// 自开启时钟中断以来经过的 tick 数
+pub fn ticks() -> usize {
+    unsafe { TICK }
+}
+
 // 设置下一次时钟中断触发的时间。riscv 不支持直接设置时钟中断的间隔，只能在每次触发时钟中断的时候，设置下一次时钟中断的时间。
 // TIMEBASE 是时间间隔，其数值一般约为 cpu 频率的 1% ，防止时钟中断占用过多的 cpu 资源。
 pub fn clock_set_next_event() {
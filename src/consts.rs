@@ -6,6 +6,36 @@ pub const MEMORY_END: usize = 0x8800_0000;
 pub const PAGE_SIZE: usize = 4096;
 pub const KERNEL_OFFSET: usize = 0xC000_0000;
 pub const STACK_SIZE: usize = 0x8000;
+// 在磁盘驱动就绪之前，用物理内存末尾的这一段充当交换区
+pub const SWAP_SIZE: usize = 0x40_0000;
+
+// qemu virt 上 virtio-mmio 设备的寄存器从这里开始，共 8 个槽，每个槽占一页，第 i 个槽的中断号为 1 + i
+pub const VIRTIO_MMIO_BASE: usize = 0x1000_1000;
+pub const VIRTIO_MMIO_SLOTS: usize = 8;
+pub const VIRTIO_IRQ_BASE: usize = 1;
+// qemu virt 上 PLIC 的寄存器从这里开始
+pub const PLIC_BASE: usize = 0x0c00_0000;
+// qemu virt 上串口的寄存器地址和中断号
+pub const UART_BASE: usize = 0x1000_0000;
+pub const UART_IRQ: usize = 10;
 
 pub const USER_STACK_OFFSET: usize = 0x80000000 - USER_STACK_SIZE;
-pub const USER_STACK_SIZE: usize = 0x10000;
\ No newline at end of file
+pub const USER_STACK_SIZE: usize = 0x10000;
+// 用户栈最多向下增长到这么大
+pub const USER_STACK_LIMIT: usize = 0x80_0000;
+// 栈可能增长到的最低地址，程序的段、堆和 mmap 的区域都在这之下
+pub const USER_MAP_LIMIT: usize = USER_STACK_OFFSET + USER_STACK_SIZE - USER_STACK_LIMIT;
+// 没有指定地址的 mmap 从这里开始寻找空闲的区域
+pub const USER_MMAP_BASE: usize = 0x4000_0000;
+
+pub const MAX_THREAD_NUM: usize = 64;
+// 每个进程最多同时打开的文件数
+pub const MAX_FILE_NUM: usize = 64;
+// 挂载在 /tmp 的 tmpfs 最多占用的内存
+pub const TMPFS_SIZE: usize = 0x40_0000;
+// 每个块设备的缓存最多占用的页帧数
+pub const BLOCK_CACHE_SIZE: usize = 64;
+// 块缓存写回线程的运行间隔，以时钟周期为单位，每秒 100 个周期
+pub const CACHE_FLUSH_INTERVAL: usize = 500;
+// 帧缓冲控制台刷新屏幕的间隔，以时钟周期为单位
+pub const CONSOLE_FLUSH_INTERVAL: usize = 5;
@@ -3,7 +3,7 @@ use core::mem::zeroed;
 use crate::riscv::register::sstatus;
 
 #[repr(C)] // 表示对这个结构体按 C 语言标准 进行内存布局
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TrapFrame {
     pub x: [usize; 32], // General registers
     pub sstatus: usize, // Supervisor Status Register
@@ -16,6 +16,19 @@ impl TrapFrame {
     pub fn increase_sepc(self: &mut Self) {
         self.sepc = self.sepc + 4;
     }
+
+    // 从 entry 开始、以 ustack_top 为栈顶在用户态运行的中断帧
+    pub fn new_user(entry: usize, ustack_top: usize) -> TrapFrame {
+        let mut tf: TrapFrame = unsafe { zeroed() };
+        tf.x[2] = ustack_top; // 用户栈
+        tf.sepc = entry; // sret 之后跳转到用户程序入口
+        let mut sstatus_ = sstatus::read();
+        sstatus_.set_spp(sstatus::SPP::User); // sret 之后进入 U 态
+        sstatus_.set_spie(true); // sret 之后开启中断
+        sstatus_.set_sie(false);
+        tf.sstatus = sstatus_.bits();
+        tf
+    }
 }
 
 #[repr(C)]
@@ -55,6 +68,33 @@ impl Context {
         ContextContent::new_kernel_thread(entry, arg, kstack_top, satp).push_at(kstack_top)
     }
 
+    // 用户线程第一次被调度时，switch 返回到 __trapret ，从内核栈顶的 TrapFrame 中恢复寄存器后
+    // 通过 sret 进入用户态，从 entry 处开始执行
+    pub unsafe fn new_user_thread(
+        entry: usize,
+        ustack_top: usize,
+        kstack_top: usize,
+        satp: usize ) -> Context {
+        ContextContent::new_user_thread(entry, ustack_top, satp).push_at(kstack_top)
+    }
+
+    // clone 出的线程在同一地址空间中从 entry 开始运行，arg 作为第一个参数
+    pub unsafe fn new_clone(
+        entry: usize,
+        ustack_top: usize,
+        arg: usize,
+        kstack_top: usize,
+        satp: usize ) -> Context {
+        let mut content = ContextContent::new_user_thread(entry, ustack_top, satp);
+        content.tf.x[10] = arg;
+        content.push_at(kstack_top)
+    }
+
+    // fork 出的子线程与父线程从同一个中断帧返回用户态，区别只在于子线程的返回值为 0
+    pub unsafe fn new_fork(tf: &TrapFrame, kstack_top: usize, satp: usize) -> Context {
+        ContextContent::new_fork(tf, satp).push_at(kstack_top)
+    }
+
     // 由于我们要完全手写汇编实现 switch 函数，因此需要给编译器一些特殊标记
     #[naked] // 表示不希望编译器产生多余的汇编代码。这里最重要的是 extern "C" 修饰，
     // 这表示该函数使用 C 语言的 ABI ，所以规范中所有调用者保存的寄存器（caller-saved）都会保存在栈上。
@@ -81,6 +121,28 @@ impl ContextContent {
         content
     }
 
+    fn new_user_thread(entry: usize, ustack_top: usize, satp: usize) -> ContextContent {
+        ContextContent {
+            ra: __trapret as usize,
+            satp,
+            s: [0; 12],
+            tf: TrapFrame::new_user(entry, ustack_top),
+        }
+    }
+
+    fn new_fork(tf: &TrapFrame, satp: usize) -> ContextContent {
+        ContextContent {
+            ra: __trapret as usize,
+            satp,
+            s: [0; 12],
+            tf: {
+                let mut tf = tf.clone();
+                tf.x[10] = 0; // 子线程中 fork 的返回值
+                tf
+            },
+        }
+    }
+
     // TODO
     unsafe fn push_at(self, stack_top: usize) -> Context {
         let ptr = (stack_top as *mut ContextContent).sub(1);
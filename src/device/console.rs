@@ -0,0 +1,169 @@
+//! 帧缓冲上的文本控制台
+//!
+//! 找到 virtio-gpu 之后，print! 的输出除了送到串口，还会画到屏幕上。每个字符占一格，
+//! 字形在两个方向上都放大两倍，1280x800 的屏幕上有 80 列 50 行。写满最后一行时整个屏幕向上滚动一行。
+//! 输出只修改帧缓冲并记下修改过的行，不等待设备。刷新屏幕要等待设备完成两条命令，
+//! 由 `spawn_flusher` 启动的线程定期进行，终端在回显和等待输入之前也会调用 `flush` 立即刷新。
+//! 刷新时不持有控制台的锁，不会关闭中断。刷新失败说明设备已经不可用，此后不再使用这个控制台。
+//! 没有图形界面时可以让 qemu 以 -display none 运行，在监视器中用 screendump 命令保存屏幕的内容。
+
+use super::font::{self, GLYPH_WIDTH, GLYPH_HEIGHT};
+use super::virtio::gpu::VirtIOGpu;
+use crate::consts::CONSOLE_FLUSH_INTERVAL;
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::sync::Arc;
+use core::slice;
+use lazy_static::*;
+
+const SCALE: usize = 2;
+const CELL_WIDTH: usize = GLYPH_WIDTH * SCALE;
+const CELL_HEIGHT: usize = GLYPH_HEIGHT * SCALE;
+const TAB_WIDTH: usize = 8;
+
+const FOREGROUND: u32 = 0x00c0_c0c0;
+const BACKGROUND: u32 = 0x0000_0000;
+
+lazy_static! {
+    static ref CONSOLE: Mutex<Option<Console>> = Mutex::new(None);
+}
+
+struct Console {
+    // 刷新屏幕的线程也持有设备，帧缓冲只在持有控制台的锁时修改
+    gpu: Arc<VirtIOGpu>,
+    cols: usize,
+    rows: usize,
+    // 下一个字符的位置，col 等于 cols 时，到下一个可打印字符才换行
+    col: usize,
+    row: usize,
+    // 修改过、还没有刷新到屏幕上的行 [begin, end)
+    dirty: Option<(usize, usize)>,
+}
+
+impl Console {
+    fn new(gpu: VirtIOGpu) -> Option<Console> {
+        let cols = gpu.width() / CELL_WIDTH;
+        let rows = gpu.height() / CELL_HEIGHT;
+        if cols == 0 || rows == 0 {
+            return None;
+        }
+        Some(Console { gpu: Arc::new(gpu), cols, rows, col: 0, row: 0, dirty: None })
+    }
+
+    fn framebuffer(&mut self) -> &mut [u32] {
+        unsafe { slice::from_raw_parts_mut(self.gpu.framebuffer(), self.gpu.width() * self.gpu.height()) }
+    }
+
+    fn mark_dirty(&mut self, begin: usize, end: usize) {
+        self.dirty = match self.dirty {
+            Some((b, e)) => Some((b.min(begin), e.max(end))),
+            None => Some((begin, end)),
+        };
+    }
+
+    fn draw(&mut self, ch: u8) {
+        let glyph = font::glyph(ch);
+        let width = self.gpu.width();
+        let (x, y) = (self.col * CELL_WIDTH, self.row * CELL_HEIGHT);
+        let fb = self.framebuffer();
+        for dy in 0..CELL_HEIGHT {
+            let bits = glyph[dy / SCALE];
+            let line = &mut fb[(y + dy) * width + x..(y + dy) * width + x + CELL_WIDTH];
+            for (dx, pixel) in line.iter_mut().enumerate() {
+                *pixel = if bits & (0x80 >> (dx / SCALE)) != 0 { FOREGROUND } else { BACKGROUND };
+            }
+        }
+        self.mark_dirty(self.row, self.row + 1);
+    }
+
+    fn new_line(&mut self) {
+        self.col = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+            return;
+        }
+        // 向上滚动一行，清空最后一行
+        let line = CELL_HEIGHT * self.gpu.width();
+        let end = self.rows * line;
+        let fb = self.framebuffer();
+        fb.copy_within(line..end, 0);
+        fb[end - line..end].iter_mut().for_each(|pixel| *pixel = BACKGROUND);
+        self.mark_dirty(0, self.rows);
+    }
+
+    fn put(&mut self, ch: u8) {
+        match ch {
+            b'\n' => self.new_line(),
+            b'\r' => self.col = 0,
+            // 退格只移动光标，擦除字符由调用者输出 "\b \b"
+            0x08 => self.col = self.col.saturating_sub(1),
+            b'\t' => loop {
+                self.put(b' ');
+                if self.col % TAB_WIDTH == 0 {
+                    break;
+                }
+            },
+            _ => {
+                if self.col == self.cols {
+                    self.new_line();
+                }
+                self.draw(ch);
+                self.col += 1;
+            }
+        }
+    }
+}
+
+/// 用 gpu 的帧缓冲作为控制台，已经有控制台时忽略之后找到的设备
+pub fn init(gpu: VirtIOGpu) {
+    let mut console = CONSOLE.lock();
+    if console.is_none() {
+        *console = Console::new(gpu);
+    }
+}
+
+/// 把 s 画到屏幕上，还没有控制台时什么也不做
+pub fn puts(s: &str) {
+    write(s.as_bytes());
+}
+
+/// 把 buf 中的字节依次画到屏幕上，不可打印的字节显示为 '?' 。只修改帧缓冲，可以在中断处理函数中调用
+pub fn write(buf: &[u8]) {
+    // 控制台内部 panic 时不能再次加锁，这时的输出只送到串口
+    if let Some(mut console) = CONSOLE.try_lock() {
+        if let Some(console) = console.as_mut() {
+            for &ch in buf {
+                console.put(ch);
+            }
+        }
+    }
+}
+
+/// 把还没有显示的修改刷新到屏幕上。发送命令时可能睡眠，只能在线程上下文中调用
+pub fn flush() {
+    let (gpu, begin, end) = match CONSOLE.try_lock() {
+        Some(mut console) => match console.as_mut() {
+            Some(console) => match console.dirty.take() {
+                Some((begin, end)) => (console.gpu.clone(), begin, end),
+                None => return,
+            },
+            None => return,
+        },
+        None => return,
+    };
+    // 刷新期间新画上的内容会再次标记为修改过，由下一次刷新显示
+    if gpu.flush(0, begin * CELL_HEIGHT, gpu.width(), (end - begin) * CELL_HEIGHT).is_err() {
+        // 设备已经被重置，丢弃控制台，之后的输出只送到串口
+        let mut console = CONSOLE.lock();
+        if console.as_ref().map_or(false, |console| Arc::ptr_eq(&console.gpu, &gpu)) {
+            *console = None;
+        }
+    }
+}
+
+/// 启动刷新屏幕的线程，每隔 CONSOLE_FLUSH_INTERVAL 个时钟周期把修改过的行显示出来
+pub fn spawn_flusher() {
+    crate::process::spawn(|| loop {
+        crate::process::sleep(CONSOLE_FLUSH_INTERVAL);
+        flush();
+    });
+}
@@ -0,0 +1,196 @@
+//! 输入子系统
+//!
+//! 驱动通过 `register` 登记输入设备，再把设备产生的事件交给 `InputDevice::push` 。
+//! 每个设备有自己的事件队列，用户程序可以从 /dev/input/eventN 读出，每个事件 8 字节，格式与 `InputEvent` 相同。
+//! 按键事件同时经过键盘映射转换成字符交给终端，就像从串口输入的一样。
+
+use super::keymap::Keymap;
+use crate::fs::{INode, FsError, FileType, Metadata, Result};
+use crate::process::{self, WaitQueue};
+use crate::tty::TTY;
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::mem::size_of;
+use core::slice;
+use lazy_static::*;
+
+// 事件类型
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+pub const EV_ABS: u16 = 0x03;
+
+// 每个设备最多缓存的事件数，用户程序读得太慢时丢弃最早的事件
+const MAX_EVENTS: usize = 256;
+// 一次 read 最多读出的事件数
+const MAX_READ: usize = 32;
+
+// /dev/input 的 inode 编号，eventN 为 INPUT_INODE + 1 + N
+const INPUT_INODE: usize = 6;
+
+/// 一个输入事件，与 virtio-input 设备写入的格式相同
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InputEvent {
+    pub type_: u16,
+    pub code: u16,  // 按键编码，或者相对、绝对坐标的轴
+    pub value: i32, // 按键的状态，或者坐标的变化量、坐标值
+}
+
+pub struct InputDevice {
+    id: usize,
+    name: String,
+    events: Mutex<VecDeque<InputEvent>>,
+    pushed: WaitQueue,
+}
+
+lazy_static! {
+    static ref DEVICES: Mutex<Vec<Arc<InputDevice>>> = Mutex::new(Vec::new());
+    static ref KEYMAP: Mutex<Keymap> = Mutex::new(Keymap::new());
+}
+
+/// 登记一个名为 name 的输入设备，它的事件可以从 /dev/input/eventN 读出，N 是登记的顺序
+pub fn register(name: &str) -> Arc<InputDevice> {
+    let mut devices = DEVICES.lock();
+    let device = Arc::new(InputDevice {
+        id: devices.len(),
+        name: String::from(name),
+        events: Mutex::new(VecDeque::new()),
+        pushed: WaitQueue::new(),
+    });
+    devices.push(device.clone());
+    device
+}
+
+impl InputDevice {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// 加入一个设备产生的事件，可以在中断处理函数中调用
+    pub fn push(&self, event: InputEvent) {
+        if event.type_ == EV_KEY {
+            if let Some(ch) = KEYMAP.lock().key(event.code, event.value) {
+                TTY.input(ch);
+            }
+        }
+        let mut events = self.events.lock();
+        if events.len() == MAX_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(event);
+        drop(events);
+        self.pushed.notify_all();
+    }
+}
+
+impl INode for InputDevice {
+    // 读出整数个事件，一次最多 MAX_READ 个，至少读到一个事件才返回，没有事件时睡眠等待。等待期间当前进程收到信号时返回 Interrupted
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let size = size_of::<InputEvent>();
+        if buf.len() < size {
+            return Err(FsError::InvalidParam);
+        }
+        let mut events = loop {
+            // 检查信号需要获取进程的锁，不能在持有事件队列的锁时进行。发送信号时会唤醒进程的所有线程
+            if process::signal_pending() {
+                return Err(FsError::Interrupted);
+            }
+            let events = self.events.lock();
+            if !events.is_empty() {
+                break events;
+            }
+            self.pushed.wait_unlock(events);
+        };
+        // buf 是用户的缓冲区，写入时可能发生缺页，不能在持有关闭中断的锁时写入。先把事件取到栈上
+        let mut local = [InputEvent::default(); MAX_READ];
+        let mut n = 0;
+        while n < local.len() && (n + 1) * size <= buf.len() {
+            match events.pop_front() {
+                Some(event) => {
+                    local[n] = event;
+                    n += 1;
+                }
+                None => break,
+            }
+        }
+        drop(events);
+        let bytes = unsafe { slice::from_raw_parts(local.as_ptr() as *const u8, n * size) };
+        buf[..bytes.len()].copy_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            dev: 0,
+            inode: INPUT_INODE + 1 + self.id,
+            size: 0,
+            blk_size: 0,
+            blocks: 0,
+            type_: FileType::CharDevice,
+            mode: 0o444,
+            nlinks: 1,
+        })
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// /dev/input 目录，其中依次是每个输入设备的 eventN
+pub struct InputDir;
+
+impl INode for InputDir {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            dev: 0,
+            inode: INPUT_INODE,
+            size: DEVICES.lock().len(),
+            blk_size: 0,
+            blocks: 0,
+            type_: FileType::Dir,
+            mode: 0o755,
+            nlinks: 2,
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn INode>> {
+        let devices = DEVICES.lock();
+        let device = devices.iter().find(|device| format!("event{}", device.id) == name);
+        match device {
+            Some(device) => Ok(device.clone()),
+            None => Err(FsError::EntryNotFound),
+        }
+    }
+
+    fn readdir(&self, index: usize) -> Result<String> {
+        match index {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            index if index - 2 < DEVICES.lock().len() => Ok(format!("event{}", index - 2)),
+            _ => Err(FsError::EntryNotFound),
+        }
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
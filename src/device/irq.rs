@@ -0,0 +1,47 @@
+//! 外部中断的分发
+//!
+//! 驱动用 `register` 为自己的中断号登记处理函数，同时在 PLIC 中打开这个中断。
+//! 外部中断到来时，`handle` 从 PLIC 取出所有待处理的中断，依次调用登记的处理函数。
+
+use super::plic;
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use lazy_static::*;
+
+/// 中断处理函数，在关闭中断的情况下运行，不能睡眠
+pub type Handler = Arc<dyn Fn() + Send + Sync>;
+
+lazy_static! {
+    static ref HANDLERS: Mutex<BTreeMap<usize, Handler>> = Mutex::new(BTreeMap::new());
+}
+
+/// 为中断号 irq 登记处理函数，替换之前登记的处理函数
+pub fn register(irq: usize, handler: Handler) {
+    HANDLERS.lock().insert(irq, handler);
+    plic::set_priority(irq, 1);
+    plic::enable(irq);
+}
+
+/// 注销中断号 irq 的处理函数，并在 PLIC 中关闭这个中断
+pub fn unregister(irq: usize) {
+    plic::disable(irq);
+    HANDLERS.lock().remove(&irq);
+}
+
+/// 处理所有待处理的外部中断，在 rust_trap 中调用
+pub fn handle() {
+    loop {
+        let irq = plic::claim();
+        if irq == 0 {
+            break;
+        }
+        // 调用处理函数时不持有锁，处理函数中可以登记其他中断
+        let handler = HANDLERS.lock().get(&irq).cloned();
+        match handler {
+            Some(handler) => handler(),
+            None => println!("irq: no handler for irq {}", irq),
+        }
+        plic::complete(irq);
+    }
+}
@@ -0,0 +1,62 @@
+//! 键盘映射
+//!
+//! 把键盘发出的按键编码（与 Linux 的 KEY_* 相同）按美式键盘布局转换成字符。
+//! 回车键产生 '\r' ，退格键产生 0x7f ，与串口终端发送的字符一致；按住 Ctrl 时字母键产生 0x01 到 0x1a 。
+
+const KEY_LEFTCTRL: u16 = 29;
+const KEY_LEFTSHIFT: u16 = 42;
+const KEY_RIGHTSHIFT: u16 = 54;
+const KEY_CAPSLOCK: u16 = 58;
+const KEY_RIGHTCTRL: u16 = 97;
+
+// 编码为下标，0 表示这个键不产生字符
+const NORMAL: [u8; 58] = [
+    0, 0x1b, b'1', b'2', b'3', b'4', b'5', b'6', b'7', b'8', b'9', b'0', b'-', b'=', 0x7f, b'\t',
+    b'q', b'w', b'e', b'r', b't', b'y', b'u', b'i', b'o', b'p', b'[', b']', b'\r', 0,
+    b'a', b's', b'd', b'f', b'g', b'h', b'j', b'k', b'l', b';', b'\'', b'`', 0, b'\\',
+    b'z', b'x', b'c', b'v', b'b', b'n', b'm', b',', b'.', b'/', 0, b'*', 0, b' ',
+];
+
+const SHIFTED: [u8; 58] = [
+    0, 0x1b, b'!', b'@', b'#', b'$', b'%', b'^', b'&', b'*', b'(', b')', b'_', b'+', 0x7f, b'\t',
+    b'Q', b'W', b'E', b'R', b'T', b'Y', b'U', b'I', b'O', b'P', b'{', b'}', b'\r', 0,
+    b'A', b'S', b'D', b'F', b'G', b'H', b'J', b'K', b'L', b':', b'"', b'~', 0, b'|',
+    b'Z', b'X', b'C', b'V', b'B', b'N', b'M', b'<', b'>', b'?', 0, b'*', 0, b' ',
+];
+
+pub struct Keymap {
+    shift: bool,
+    ctrl: bool,
+    caps_lock: bool,
+}
+
+impl Keymap {
+    pub fn new() -> Keymap {
+        Keymap { shift: false, ctrl: false, caps_lock: false }
+    }
+
+    /// 处理一个按键事件，返回这次按键产生的字符。
+    /// value 为 1 表示按下，2 表示按住不放时的自动重复，0 表示松开
+    pub fn key(&mut self, code: u16, value: i32) -> Option<u8> {
+        let pressed = value != 0;
+        match code {
+            KEY_LEFTSHIFT | KEY_RIGHTSHIFT => self.shift = pressed,
+            KEY_LEFTCTRL | KEY_RIGHTCTRL => self.ctrl = pressed,
+            KEY_CAPSLOCK if value == 1 => self.caps_lock = !self.caps_lock,
+            _ if pressed && (code as usize) < NORMAL.len() => {
+                let mut ch = if self.shift { SHIFTED[code as usize] } else { NORMAL[code as usize] };
+                if ch.is_ascii_alphabetic() && self.caps_lock {
+                    ch ^= 0x20;
+                }
+                if ch.is_ascii_alphabetic() && self.ctrl {
+                    ch &= 0x1f;
+                }
+                if ch != 0 {
+                    return Some(ch);
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+}
@@ -1 +1,45 @@
-pub mod uart;
\ No newline at end of file
+//! 设备驱动
+//!
+//! 驱动在 `init` 中探测并初始化设备，找到的块设备登记在这里，由文件系统挂载；
+//! 找到的显示设备成为 `console` ，显示 print! 的输出；输入设备的事件经过 `input` 送到终端和 /dev/input ；
+//! 串口收到的字符在中断处理函数中交给终端。
+
+pub mod uart;
+pub mod plic;
+pub mod irq;
+pub mod virtio;
+pub mod font;
+pub mod console;
+pub mod keymap;
+pub mod input;
+
+use crate::fs::BlockDevice;
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+lazy_static! {
+    static ref BLOCK_DEVICES: Mutex<Vec<Arc<dyn BlockDevice>>> = Mutex::new(Vec::new());
+}
+
+pub fn init() {
+    plic::init();
+    virtio::probe();
+    irq::register(crate::consts::UART_IRQ, Arc::new(uart::handle_interrupt));
+    crate::fs::devfs::register("input", Arc::new(input::InputDir));
+}
+
+/// 调度器即将开始运行，此后只有线程会等待设备，可以睡眠等待中断而不是轮询
+pub fn enable_interrupts() {
+    virtio::enable_interrupts();
+}
+
+pub fn add_block_device(device: Arc<dyn BlockDevice>) {
+    BLOCK_DEVICES.lock().push(device);
+}
+
+/// 探测到的所有块设备，按发现的顺序排列
+pub fn block_devices() -> Vec<Arc<dyn BlockDevice>> {
+    BLOCK_DEVICES.lock().clone()
+}
@@ -0,0 +1,68 @@
+//! PLIC 平台级中断控制器
+//!
+//! 每个外部中断源有一个编号和优先级。PLIC 为每个 hart 的每个特权级提供一个上下文，
+//! 上下文有自己的中断使能位和优先级阈值，只有被使能并且优先级高于阈值的中断才会送到这个上下文。
+//! hart 收到外部中断后从上下文的 claim 寄存器读出中断号，处理完成后再把它写回同一个寄存器，表示 complete 。
+
+use crate::consts::PLIC_BASE;
+use core::ptr::{read_volatile, write_volatile};
+use riscv::register::sie;
+
+// 寄存器的偏移
+const PRIORITY: usize = 0x00_0000;      // 每个中断源 4 字节
+const ENABLE: usize = 0x00_2000;        // 每个上下文 0x80 字节，每个中断源 1 位
+const ENABLE_STRIDE: usize = 0x80;
+const THRESHOLD: usize = 0x20_0000;     // 每个上下文 0x1000 字节
+const CLAIM: usize = 0x20_0004;
+const CONTEXT_STRIDE: usize = 0x1000;
+
+// 内核只在 0 号 hart 上运行，hart i 的 M 态和 S 态上下文编号分别为 2i 和 2i + 1
+const HART: usize = 0;
+
+fn context() -> usize {
+    2 * HART + 1
+}
+
+fn read(offset: usize) -> u32 {
+    unsafe { read_volatile((PLIC_BASE + offset) as *const u32) }
+}
+
+fn write(offset: usize, value: u32) {
+    unsafe { write_volatile((PLIC_BASE + offset) as *mut u32, value) }
+}
+
+/// 接受所有优先级大于 0 的中断，并打开 sie 中的外部中断使能位
+pub fn init() {
+    set_threshold(0);
+    unsafe { sie::set_sext() };
+}
+
+/// 设置中断源 irq 的优先级，0 表示永远不会送达
+pub fn set_priority(irq: usize, priority: u32) {
+    write(PRIORITY + 4 * irq, priority);
+}
+
+/// 只把优先级大于 threshold 的中断送到当前 hart
+pub fn set_threshold(threshold: u32) {
+    write(THRESHOLD + CONTEXT_STRIDE * context(), threshold);
+}
+
+pub fn enable(irq: usize) {
+    let offset = ENABLE + ENABLE_STRIDE * context() + irq / 32 * 4;
+    write(offset, read(offset) | 1 << (irq % 32));
+}
+
+pub fn disable(irq: usize) {
+    let offset = ENABLE + ENABLE_STRIDE * context() + irq / 32 * 4;
+    write(offset, read(offset) & !(1 << (irq % 32)));
+}
+
+/// 取出优先级最高的待处理中断，没有时返回 0
+pub fn claim() -> usize {
+    read(CLAIM + CONTEXT_STRIDE * context()) as usize
+}
+
+/// 通知 PLIC 中断 irq 已经处理完成，之后它才能再次送达
+pub fn complete(irq: usize) {
+    write(CLAIM + CONTEXT_STRIDE * context(), irq as u32);
+}
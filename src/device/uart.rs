@@ -122,6 +122,14 @@ impl Uart {
     }
 }
 
+/// 串口的接收中断，把已经到达的字符全部交给终端
+pub fn handle_interrupt() {
+    let mut uart = Uart::new(crate::consts::UART_BASE);
+    while let Some(ch) = uart.get() {
+        crate::tty::TTY.input(ch);
+    }
+}
+
 #[inline(always)]
 pub fn write<T>(addr: usize, content: T) {
     let cell = (addr) as *mut T;
@@ -0,0 +1,175 @@
+//! virtio-blk 块设备
+//!
+//! 每个请求由三个描述符组成：设备读取的请求头、数据缓冲区和设备写入的状态字节。
+//! 请求锁保证同一时刻队列中只有一个请求。刚启动时没有中断，驱动轮询已用环等待请求完成；
+//! 调用 enable_interrupt 之后，等待请求的线程在等待队列上睡眠，由中断处理函数唤醒。
+
+use super::VirtIOHeader;
+use super::queue::VirtQueue;
+use crate::fs::{BlockDevice, FsError, Result};
+use crate::fs::block::SECTOR_SIZE;
+use crate::process::WaitQueue;
+use crate::sync::{Mutex, SpinNoIrqLock};
+use core::mem::size_of;
+use core::ptr::read_volatile;
+use core::slice;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+const QUEUE_SIZE: u16 = 16;
+
+// 设备特性
+const F_RO: u64 = 1 << 5;
+const F_FLUSH: u64 = 1 << 9;
+
+// 请求类型
+const T_IN: u32 = 0;
+const T_OUT: u32 = 1;
+const T_FLUSH: u32 = 4;
+
+const S_OK: u8 = 0;
+
+#[repr(C)]
+struct RequestHeader {
+    type_: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+// 请求的数据部分
+enum Buffer<'a> {
+    ToDevice(&'a [u8]),
+    FromDevice(&'a mut [u8]),
+    None,
+}
+
+pub struct VirtIOBlk {
+    header: VirtIOHeader,
+    queue: SpinNoIrqLock<VirtQueue>,
+    request: Mutex<()>,
+    capacity: usize, // 扇区数
+    read_only: bool,
+    flush: bool,     // 设备有自己的写缓存，需要 flush 请求才能写回
+    interrupt: AtomicBool,
+    completion: WaitQueue,
+    // 设备出错后被重置，之后的请求都直接失败
+    broken: AtomicBool,
+}
+
+impl VirtIOBlk {
+    pub fn new(header: VirtIOHeader) -> core::result::Result<VirtIOBlk, &'static str> {
+        let features = header.begin_init(F_RO | F_FLUSH)?;
+        let queue = VirtQueue::new(QUEUE_SIZE)?;
+        header.set_queue(0, &queue)?;
+        // 配置空间的第一个字段是以 512 字节为单位的容量
+        let capacity = header.config::<u32>(0) as usize;
+        if header.config::<u32>(4) != 0 {
+            println!("virtio-blk: only the first 2 TiB is used");
+        }
+        header.finish_init();
+        Ok(VirtIOBlk {
+            header,
+            queue: SpinNoIrqLock::new(queue),
+            request: Mutex::new(()),
+            capacity,
+            read_only: features & F_RO != 0,
+            flush: features & F_FLUSH != 0,
+            interrupt: AtomicBool::new(false),
+            completion: WaitQueue::new(),
+            broken: AtomicBool::new(false),
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn irq(&self) -> usize {
+        self.header.irq()
+    }
+
+    /// 改为由中断通知请求完成，调用者需要先把 handle_interrupt 注册为这个设备的中断处理函数
+    pub fn enable_interrupt(&self) {
+        self.interrupt.store(true, Ordering::SeqCst);
+    }
+
+    /// 设备的中断处理函数，唤醒等待请求完成的线程
+    pub fn handle_interrupt(&self) {
+        self.header.ack_interrupt();
+        self.completion.notify_all();
+    }
+
+    fn request(&self, type_: u32, sector: usize, buffer: Buffer) -> Result<()> {
+        let header = RequestHeader { type_, reserved: 0, sector: sector as u64 };
+        let header = unsafe {
+            slice::from_raw_parts(&header as *const RequestHeader as *const u8, size_of::<RequestHeader>())
+        };
+        let mut status = [0xffu8];
+        let _request = self.request.lock();
+        if self.broken.load(Ordering::SeqCst) {
+            return Err(FsError::DeviceError);
+        }
+        let head = {
+            let mut queue = self.queue.lock();
+            let head = match buffer {
+                Buffer::ToDevice(data) => queue.add(&[header, data], &[&mut status[..]]),
+                Buffer::FromDevice(data) => queue.add(&[header], &[data, &mut status[..]]),
+                Buffer::None => queue.add(&[header], &[&mut status[..]]),
+            };
+            let head = head.map_err(|_| FsError::DeviceError)?;
+            self.header.notify(0);
+            head
+        };
+        loop {
+            let mut queue = self.queue.lock();
+            if let Some((id, _)) = queue.pop_used() {
+                if id != head {
+                    // 同一时刻只有一个请求，设备出了问题。重置后它不会再写入栈上的缓冲区
+                    println!("virtio-blk: unexpected request completed, device disabled");
+                    self.header.reset();
+                    self.broken.store(true, Ordering::SeqCst);
+                    return Err(FsError::DeviceError);
+                }
+                break;
+            }
+            if self.interrupt.load(Ordering::SeqCst) {
+                // 队列锁持有期间关闭了中断，中断处理函数只能在线程睡眠之后运行，不会错过唤醒
+                self.completion.wait_unlock(queue);
+            }
+        }
+        // 状态字节由设备写入，编译器不知道它被修改过
+        match unsafe { read_volatile(&status[0]) } {
+            S_OK => Ok(()),
+            _ => Err(FsError::DeviceError),
+        }
+    }
+}
+
+impl BlockDevice for VirtIOBlk {
+    fn sector_count(&self) -> usize {
+        self.capacity
+    }
+
+    fn read_sector(&self, id: usize, buf: &mut [u8]) -> Result<()> {
+        if id >= self.capacity || buf.len() != SECTOR_SIZE {
+            return Err(FsError::InvalidParam);
+        }
+        self.request(T_IN, id, Buffer::FromDevice(buf))
+    }
+
+    fn write_sector(&self, id: usize, buf: &[u8]) -> Result<()> {
+        if self.read_only {
+            return Err(FsError::ReadOnly);
+        }
+        if id >= self.capacity || buf.len() != SECTOR_SIZE {
+            return Err(FsError::InvalidParam);
+        }
+        self.request(T_OUT, id, Buffer::ToDevice(buf))
+    }
+
+    fn flush(&self) -> Result<()> {
+        if !self.flush {
+            return Ok(());
+        }
+        self.request(T_FLUSH, 0, Buffer::None)
+    }
+}
@@ -0,0 +1,264 @@
+//! virtio-gpu 2D 设备
+//!
+//! 驱动在内存中分配一块和屏幕一样大的帧缓冲，作为设备上一个 2D 资源的后备存储，并让 0 号屏幕显示这个资源。
+//! 修改帧缓冲之后，需要先用 transfer 把修改的区域复制到设备中的资源，再用 flush 让屏幕刷新这一区域。
+//! 所有命令都通过 0 号 virtqueue 发送，驱动轮询等待设备的回复。轮询有次数上限，
+//! 设备没有及时回复时驱动重置设备并返回错误，之后这个设备不能再使用。
+//! 队列由睡眠锁保护，发送命令时不关闭中断，只能在线程上下文中进行。
+
+use super::VirtIOHeader;
+use super::queue::VirtQueue;
+use crate::consts::PAGE_SIZE;
+use crate::new_memory::{Frame, access_pa_via_va, frame_allocator};
+use crate::sync::Mutex;
+use core::mem::{size_of, zeroed};
+use core::ptr::{read_volatile, write_bytes};
+use core::slice;
+
+const QUEUE_SIZE: u16 = 16;
+// 等待一条命令的回复时最多轮询的次数，在 qemu 上远大于正常需要的次数
+const MAX_POLLS: usize = 0x100_0000;
+
+// 命令和回复的类型
+const CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+const CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+const CMD_SET_SCANOUT: u32 = 0x0103;
+const CMD_RESOURCE_FLUSH: u32 = 0x0104;
+const CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+const CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+const RESP_OK_NODATA: u32 = 0x1100;
+const RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+
+// 每个像素 4 字节，内存中依次为蓝、绿、红和不使用的一个字节，作为 u32 读写时就是 0x00RRGGBB
+const FORMAT_B8G8R8X8_UNORM: u32 = 2;
+
+const MAX_SCANOUTS: usize = 16;
+const RESOURCE_ID: u32 = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CtrlHeader {
+    type_: u32,
+    flags: u32,
+    fence_id: u64,
+    ctx_id: u32,
+    padding: u32,
+}
+
+impl CtrlHeader {
+    fn new(type_: u32) -> CtrlHeader {
+        CtrlHeader { type_, flags: 0, fence_id: 0, ctx_id: 0, padding: 0 }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Rect {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DisplayOne {
+    rect: Rect,
+    enabled: u32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RespDisplayInfo {
+    header: CtrlHeader,
+    pmodes: [DisplayOne; MAX_SCANOUTS],
+}
+
+#[repr(C)]
+struct ResourceCreate2D {
+    header: CtrlHeader,
+    resource_id: u32,
+    format: u32,
+    width: u32,
+    height: u32,
+}
+
+// 只有一段后备存储的 attach backing 命令
+#[repr(C)]
+struct ResourceAttachBacking {
+    header: CtrlHeader,
+    resource_id: u32,
+    nr_entries: u32,
+    addr: u64,
+    length: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+struct SetScanout {
+    header: CtrlHeader,
+    rect: Rect,
+    scanout_id: u32,
+    resource_id: u32,
+}
+
+#[repr(C)]
+struct TransferToHost2D {
+    header: CtrlHeader,
+    rect: Rect,
+    offset: u64,
+    resource_id: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+struct ResourceFlush {
+    header: CtrlHeader,
+    rect: Rect,
+    resource_id: u32,
+    padding: u32,
+}
+
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe { slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+fn as_bytes_mut<T>(value: &mut T) -> &mut [u8] {
+    unsafe { slice::from_raw_parts_mut(value as *mut T as *mut u8, size_of::<T>()) }
+}
+
+// 发送一条命令并等待回复，回复的类型为 Rsp 。超时后重置设备，设备不会再写入栈上的 response
+fn request<Req, Rsp: Copy>(header: &VirtIOHeader, queue: &mut VirtQueue, req: &Req) -> Result<Rsp, &'static str> {
+    let mut response: Rsp = unsafe { zeroed() };
+    let head = queue.add(&[as_bytes(req)], &[as_bytes_mut(&mut response)])?;
+    header.notify(0);
+    for _ in 0..MAX_POLLS {
+        if let Some((id, _)) = queue.pop_used() {
+            if id != head {
+                header.reset();
+                return Err("unexpected request completed");
+            }
+            // 回复由设备写入，编译器不知道它被修改过
+            return Ok(unsafe { read_volatile(&response) });
+        }
+    }
+    header.reset();
+    Err("device not responding")
+}
+
+pub struct VirtIOGpu {
+    header: VirtIOHeader,
+    queue: Mutex<VirtQueue>,
+    width: usize,
+    height: usize,
+    // 帧缓冲占用的页帧。伙伴分配器按 2 的幂分配，pages 是实际分配的页数，回收时原样交还
+    frame: Frame,
+    pages: usize,
+}
+
+impl VirtIOGpu {
+    pub fn new(header: VirtIOHeader) -> Result<VirtIOGpu, &'static str> {
+        header.begin_init(0)?;
+        let mut queue = VirtQueue::new(QUEUE_SIZE)?;
+        header.set_queue(0, &queue)?;
+        header.finish_init();
+
+        let info: RespDisplayInfo = request(&header, &mut queue, &CtrlHeader::new(CMD_GET_DISPLAY_INFO))?;
+        if info.header.type_ != RESP_OK_DISPLAY_INFO {
+            return Err("failed to get display info");
+        }
+        let display = info.pmodes[0];
+        if display.enabled == 0 || display.rect.width == 0 || display.rect.height == 0 {
+            return Err("scanout 0 is not enabled");
+        }
+        let (width, height) = (display.rect.width, display.rect.height);
+        let size = width as usize * height as usize * 4;
+        let pages = ((size + PAGE_SIZE - 1) / PAGE_SIZE).next_power_of_two();
+        let frame = frame_allocator::alloc_frames(pages).ok_or("out of memory for framebuffer")?;
+        let gpu = VirtIOGpu {
+            header,
+            queue: Mutex::new(queue),
+            width: width as usize,
+            height: height as usize,
+            frame,
+            pages,
+        };
+        unsafe {
+            write_bytes(gpu.framebuffer(), 0, gpu.width * gpu.height);
+        }
+
+        gpu.command(&ResourceCreate2D {
+            header: CtrlHeader::new(CMD_RESOURCE_CREATE_2D),
+            resource_id: RESOURCE_ID,
+            format: FORMAT_B8G8R8X8_UNORM,
+            width,
+            height,
+        })?;
+        gpu.command(&ResourceAttachBacking {
+            header: CtrlHeader::new(CMD_RESOURCE_ATTACH_BACKING),
+            resource_id: RESOURCE_ID,
+            nr_entries: 1,
+            addr: frame.start_address() as u64,
+            length: size as u32,
+            padding: 0,
+        })?;
+        gpu.command(&SetScanout {
+            header: CtrlHeader::new(CMD_SET_SCANOUT),
+            rect: Rect { x: 0, y: 0, width, height },
+            scanout_id: 0,
+            resource_id: RESOURCE_ID,
+        })?;
+        gpu.flush(0, 0, gpu.width, gpu.height)?;
+        Ok(gpu)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// 按行排列的帧缓冲的起始地址，共 width * height 个像素，每个像素是一个 0x00RRGGBB 形式的 u32 。
+    /// 设备只在 flush 时读取帧缓冲，修改它的一方需要自己保证同一时刻只有一处在写
+    pub fn framebuffer(&self) -> *mut u32 {
+        access_pa_via_va(self.frame.start_address()) as *mut u32
+    }
+
+    /// 把帧缓冲中的一个矩形区域显示到屏幕上
+    pub fn flush(&self, x: usize, y: usize, width: usize, height: usize) -> Result<(), &'static str> {
+        let rect = Rect { x: x as u32, y: y as u32, width: width as u32, height: height as u32 };
+        self.command(&TransferToHost2D {
+            header: CtrlHeader::new(CMD_TRANSFER_TO_HOST_2D),
+            rect,
+            offset: ((y * self.width + x) * 4) as u64,
+            resource_id: RESOURCE_ID,
+            padding: 0,
+        })?;
+        self.command(&ResourceFlush {
+            header: CtrlHeader::new(CMD_RESOURCE_FLUSH),
+            rect,
+            resource_id: RESOURCE_ID,
+            padding: 0,
+        })
+    }
+
+    // 发送一条只需要确认成功的命令
+    fn command<Req>(&self, req: &Req) -> Result<(), &'static str> {
+        let response: CtrlHeader = request(&self.header, &mut self.queue.lock(), req)?;
+        if response.type_ != RESP_OK_NODATA {
+            return Err("command failed");
+        }
+        Ok(())
+    }
+}
+
+impl Drop for VirtIOGpu {
+    fn drop(&mut self) {
+        // 先让设备停止访问帧缓冲，再回收它
+        self.header.reset();
+        frame_allocator::dealloc_frames(self.frame, self.pages);
+    }
+}
@@ -0,0 +1,114 @@
+//! virtio-input 设备
+//!
+//! 键盘、鼠标和触摸板都表现为 virtio-input 设备。驱动预先在 0 号 virtqueue 中放好一组只能容纳一个事件的缓冲区，
+//! 设备把事件写进这些缓冲区，驱动取出事件交给输入子系统，再把缓冲区放回队列。
+//! 1 号 virtqueue 用来设置键盘指示灯等状态，这里不使用。
+
+use super::VirtIOHeader;
+use super::queue::VirtQueue;
+use crate::device::input::{self, InputDevice, InputEvent};
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::ptr::read_volatile;
+use core::slice;
+
+const QUEUE_SIZE: u16 = 32;
+
+// 配置空间：select 和 subsel 选择要查询的信息，之后 size 给出它的长度，内容从偏移 8 开始
+const CFG_SELECT: usize = 0;
+const CFG_SUBSEL: usize = 1;
+const CFG_SIZE: usize = 2;
+const CFG_DATA: usize = 8;
+const CFG_ID_NAME: u8 = 0x01;
+
+pub struct VirtIOInput {
+    header: VirtIOHeader,
+    inner: Mutex<Inner>,
+    device: Arc<InputDevice>,
+}
+
+struct Inner {
+    queue: VirtQueue,
+    // 第 i 个缓冲区放在第 i 个描述符上。每个缓冲区只占一个描述符，取出之后立即放回，总会回到原来的描述符上
+    events: Vec<InputEvent>,
+}
+
+fn event_bytes(event: &mut InputEvent) -> &mut [u8] {
+    unsafe { slice::from_raw_parts_mut(event as *mut InputEvent as *mut u8, size_of::<InputEvent>()) }
+}
+
+impl VirtIOInput {
+    pub fn new(header: VirtIOHeader) -> Result<VirtIOInput, &'static str> {
+        header.begin_init(0)?;
+        let mut queue = VirtQueue::new(QUEUE_SIZE)?;
+        header.set_queue(0, &queue)?;
+        let mut events = vec![InputEvent::default(); QUEUE_SIZE as usize];
+        for (i, event) in events.iter_mut().enumerate() {
+            let head = queue.add(&[], &[event_bytes(event)])?;
+            assert_eq!(head as usize, i);
+        }
+        header.finish_init();
+        header.notify(0);
+
+        header.set_config::<u8>(CFG_SELECT, CFG_ID_NAME);
+        header.set_config::<u8>(CFG_SUBSEL, 0);
+        let size = header.config::<u8>(CFG_SIZE) as usize;
+        let name: String = (0..size).map(|i| header.config::<u8>(CFG_DATA + i) as char).collect();
+        Ok(VirtIOInput {
+            header,
+            inner: Mutex::new(Inner { queue, events }),
+            device: input::register(&name),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        self.device.name()
+    }
+
+    pub fn irq(&self) -> usize {
+        self.header.irq()
+    }
+
+    // 取出设备写好的所有事件，并把缓冲区放回队列。设备返回了不存在或者不是刚取出的描述符时返回 Err
+    fn poll(&self) -> Result<(), &'static str> {
+        let mut inner = self.inner.lock();
+        let Inner { queue, events } = &mut *inner;
+        let mut refilled = false;
+        let mut result = Ok(());
+        while let Some((head, _)) = queue.pop_used() {
+            let event = match events.get_mut(head as usize) {
+                Some(event) => event,
+                None => {
+                    result = Err("invalid descriptor used");
+                    break;
+                }
+            };
+            // 事件由设备写入，编译器不知道它被修改过
+            self.device.push(unsafe { read_volatile(&*event) });
+            match queue.add(&[], &[event_bytes(event)]) {
+                Ok(new_head) if new_head == head => refilled = true,
+                _ => {
+                    result = Err("queue out of sync");
+                    break;
+                }
+            }
+        }
+        if refilled && result.is_ok() {
+            self.header.notify(0);
+        }
+        result
+    }
+
+    /// 设备的中断处理函数，把新的事件交给输入子系统。设备出错时将其重置，此后不再产生事件
+    pub fn handle_interrupt(&self) {
+        self.header.ack_interrupt();
+        if let Err(err) = self.poll() {
+            println!("virtio-input: {}, device disabled", err);
+            self.header.reset();
+        }
+    }
+}
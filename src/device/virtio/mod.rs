@@ -0,0 +1,251 @@
+//! virtio-mmio 设备
+//!
+//! qemu virt 在 VIRTIO_MMIO_BASE 开始的 8 个槽中放置 virtio 设备，每个槽是一组 MMIO 寄存器，
+//! 没有设备的槽 DeviceID 为 0 。`probe` 依次检查每个槽，按设备类型交给对应的驱动初始化。
+//! 同时支持 legacy（version 1）和 version 2 两种 MMIO 接口，
+//! 区别在于特性协商是否需要 FEATURES_OK ，以及告诉设备 virtqueue 地址的方式。
+
+pub mod queue;
+pub mod blk;
+pub mod gpu;
+pub mod input;
+
+use self::queue::VirtQueue;
+use super::irq;
+use crate::consts::{PAGE_SIZE, VIRTIO_MMIO_BASE, VIRTIO_MMIO_SLOTS, VIRTIO_IRQ_BASE};
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ptr::{read_volatile, write_volatile};
+use lazy_static::*;
+
+const MAGIC: u32 = 0x7472_6976; // "virt"
+
+// MMIO 寄存器的偏移
+const REG_MAGIC: usize = 0x000;
+const REG_VERSION: usize = 0x004;
+const REG_DEVICE_ID: usize = 0x008;
+const REG_DEVICE_FEATURES: usize = 0x010;
+const REG_DEVICE_FEATURES_SEL: usize = 0x014;
+const REG_DRIVER_FEATURES: usize = 0x020;
+const REG_DRIVER_FEATURES_SEL: usize = 0x024;
+const REG_GUEST_PAGE_SIZE: usize = 0x028; // legacy
+const REG_QUEUE_SEL: usize = 0x030;
+const REG_QUEUE_NUM_MAX: usize = 0x034;
+const REG_QUEUE_NUM: usize = 0x038;
+const REG_QUEUE_ALIGN: usize = 0x03c; // legacy
+const REG_QUEUE_PFN: usize = 0x040; // legacy
+const REG_QUEUE_READY: usize = 0x044;
+const REG_QUEUE_NOTIFY: usize = 0x050;
+const REG_INTERRUPT_STATUS: usize = 0x060;
+const REG_INTERRUPT_ACK: usize = 0x064;
+const REG_STATUS: usize = 0x070;
+const REG_QUEUE_DESC_LOW: usize = 0x080;
+const REG_QUEUE_DESC_HIGH: usize = 0x084;
+const REG_QUEUE_DRIVER_LOW: usize = 0x090;
+const REG_QUEUE_DRIVER_HIGH: usize = 0x094;
+const REG_QUEUE_DEVICE_LOW: usize = 0x0a0;
+const REG_QUEUE_DEVICE_HIGH: usize = 0x0a4;
+// 设备相关的配置空间
+const REG_CONFIG: usize = 0x100;
+
+// 设备状态
+const STATUS_ACKNOWLEDGE: u32 = 1;
+const STATUS_DRIVER: u32 = 2;
+const STATUS_DRIVER_OK: u32 = 4;
+const STATUS_FEATURES_OK: u32 = 8;
+const STATUS_FAILED: u32 = 128;
+
+// version 2 的设备要求驱动接受这一特性
+const F_VERSION_1: u64 = 1 << 32;
+
+pub const DEVICE_BLOCK: u32 = 2;
+pub const DEVICE_GPU: u32 = 16;
+pub const DEVICE_INPUT: u32 = 18;
+
+lazy_static! {
+    static ref BLOCK_DEVICES: Mutex<Vec<Arc<blk::VirtIOBlk>>> = Mutex::new(Vec::new());
+}
+
+/// 一个 virtio-mmio 槽的寄存器
+pub struct VirtIOHeader {
+    base: usize,
+    irq: usize,
+}
+
+impl VirtIOHeader {
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base + offset) as *const u32) }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base + offset) as *mut u32, value) }
+    }
+
+    pub fn version(&self) -> u32 {
+        self.read(REG_VERSION)
+    }
+
+    pub fn device_id(&self) -> u32 {
+        self.read(REG_DEVICE_ID)
+    }
+
+    /// 设备的中断号
+    pub fn irq(&self) -> usize {
+        self.irq
+    }
+
+    /// 重置设备并协商特性，返回双方都支持的特性。之后驱动设置 virtqueue ，再调用 finish_init
+    pub fn begin_init(&self, supported: u64) -> Result<u64, &'static str> {
+        self.write(REG_STATUS, 0);
+        let mut status = STATUS_ACKNOWLEDGE;
+        self.write(REG_STATUS, status);
+        status |= STATUS_DRIVER;
+        self.write(REG_STATUS, status);
+
+        let legacy = self.version() == 1;
+        self.write(REG_DEVICE_FEATURES_SEL, 0);
+        let mut device = self.read(REG_DEVICE_FEATURES) as u64;
+        if !legacy {
+            self.write(REG_DEVICE_FEATURES_SEL, 1);
+            device |= (self.read(REG_DEVICE_FEATURES) as u64) << 32;
+        }
+        let mut features = device & supported;
+        if !legacy {
+            if device & F_VERSION_1 == 0 {
+                self.write(REG_STATUS, STATUS_FAILED);
+                return Err("device does not offer VIRTIO_F_VERSION_1");
+            }
+            features |= F_VERSION_1;
+        }
+        self.write(REG_DRIVER_FEATURES_SEL, 0);
+        self.write(REG_DRIVER_FEATURES, features as u32);
+        if legacy {
+            self.write(REG_GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        } else {
+            self.write(REG_DRIVER_FEATURES_SEL, 1);
+            self.write(REG_DRIVER_FEATURES, (features >> 32) as u32);
+            status |= STATUS_FEATURES_OK;
+            self.write(REG_STATUS, status);
+            if self.read(REG_STATUS) & STATUS_FEATURES_OK == 0 {
+                self.write(REG_STATUS, STATUS_FAILED);
+                return Err("device rejected the features");
+            }
+        }
+        Ok(features)
+    }
+
+    /// 设备初始化完成，开始工作
+    pub fn finish_init(&self) {
+        let status = self.read(REG_STATUS);
+        self.write(REG_STATUS, status | STATUS_DRIVER_OK);
+    }
+
+    /// 告诉设备第 index 个 virtqueue 的位置
+    pub fn set_queue(&self, index: usize, queue: &VirtQueue) -> Result<(), &'static str> {
+        let legacy = self.version() == 1;
+        self.write(REG_QUEUE_SEL, index as u32);
+        let in_use = if legacy { self.read(REG_QUEUE_PFN) } else { self.read(REG_QUEUE_READY) };
+        if in_use != 0 {
+            return Err("virtqueue is already in use");
+        }
+        let max = self.read(REG_QUEUE_NUM_MAX);
+        if max == 0 {
+            return Err("virtqueue is not available");
+        }
+        if (queue.size() as u32) > max {
+            return Err("virtqueue is too large");
+        }
+        self.write(REG_QUEUE_NUM, queue.size() as u32);
+        if legacy {
+            self.write(REG_QUEUE_ALIGN, PAGE_SIZE as u32);
+            self.write(REG_QUEUE_PFN, (queue.desc_paddr() / PAGE_SIZE) as u32);
+        } else {
+            self.write(REG_QUEUE_DESC_LOW, queue.desc_paddr() as u32);
+            self.write(REG_QUEUE_DESC_HIGH, 0);
+            self.write(REG_QUEUE_DRIVER_LOW, queue.avail_paddr() as u32);
+            self.write(REG_QUEUE_DRIVER_HIGH, 0);
+            self.write(REG_QUEUE_DEVICE_LOW, queue.used_paddr() as u32);
+            self.write(REG_QUEUE_DEVICE_HIGH, 0);
+            self.write(REG_QUEUE_READY, 1);
+        }
+        Ok(())
+    }
+
+    /// 重置设备，此后设备不再访问任何 virtqueue
+    pub fn reset(&self) {
+        self.write(REG_STATUS, 0);
+    }
+
+    /// 通知设备第 index 个 virtqueue 中有新的请求
+    pub fn notify(&self, index: usize) {
+        self.write(REG_QUEUE_NOTIFY, index as u32);
+    }
+
+    /// 确认设备发出的中断，返回中断的原因
+    pub fn ack_interrupt(&self) -> u32 {
+        let status = self.read(REG_INTERRUPT_STATUS);
+        self.write(REG_INTERRUPT_ACK, status);
+        status
+    }
+
+    /// 读取配置空间中偏移为 offset 的字段
+    pub fn config<T: Copy>(&self, offset: usize) -> T {
+        unsafe { read_volatile((self.base + REG_CONFIG + offset) as *const T) }
+    }
+
+    /// 写入配置空间中偏移为 offset 的字段
+    pub fn set_config<T: Copy>(&self, offset: usize, value: T) {
+        unsafe { write_volatile((self.base + REG_CONFIG + offset) as *mut T, value) }
+    }
+}
+
+/// 调度器开始运行之后，等待 virtio-blk 请求完成的线程改为睡眠，由中断唤醒
+pub fn enable_interrupts() {
+    for blk in BLOCK_DEVICES.lock().iter() {
+        blk.enable_interrupt();
+    }
+}
+
+/// 检查所有 virtio-mmio 槽，初始化其中的设备，并登记它们的中断处理函数
+pub fn probe() {
+    for slot in 0..VIRTIO_MMIO_SLOTS {
+        let header = VirtIOHeader {
+            base: VIRTIO_MMIO_BASE + slot * PAGE_SIZE,
+            irq: VIRTIO_IRQ_BASE + slot,
+        };
+        if header.read(REG_MAGIC) != MAGIC {
+            continue;
+        }
+        match header.device_id() {
+            0 => {}
+            DEVICE_BLOCK => match blk::VirtIOBlk::new(header) {
+                Ok(blk) => {
+                    println!("virtio-blk: {} sectors at slot {}", blk.capacity(), slot);
+                    let blk = Arc::new(blk);
+                    let handler = blk.clone();
+                    irq::register(blk.irq(), Arc::new(move || handler.handle_interrupt()));
+                    BLOCK_DEVICES.lock().push(blk.clone());
+                    super::add_block_device(blk);
+                }
+                Err(err) => println!("virtio-blk: {}", err),
+            },
+            DEVICE_GPU => match gpu::VirtIOGpu::new(header) {
+                Ok(gpu) => {
+                    println!("virtio-gpu: {}x{} framebuffer at slot {}", gpu.width(), gpu.height(), slot);
+                    super::console::init(gpu);
+                }
+                Err(err) => println!("virtio-gpu: {}", err),
+            },
+            DEVICE_INPUT => match input::VirtIOInput::new(header) {
+                Ok(input) => {
+                    println!("virtio-input: {} at slot {}", input.name(), slot);
+                    let input = Arc::new(input);
+                    irq::register(input.irq(), Arc::new(move || input.handle_interrupt()));
+                }
+                Err(err) => println!("virtio-input: {}", err),
+            },
+            id => println!("virtio: unsupported device {} at slot {}", id, slot),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! split virtqueue
+//!
+//! 描述符表、可用环和已用环放在从页帧分配器分配的一段连续物理页帧中，按 legacy 接口要求的方式排列：
+//! 描述符表和可用环在前，已用环从下一个页边界开始。version 2 的设备也接受这种布局，只是要分别告诉它三部分的地址。
+//! 空闲的描述符通过 next 串成一个链表，每个请求占用一条描述符链，完成后整条链归还到空闲链表中。
+
+use crate::consts::PAGE_SIZE;
+use crate::new_memory::{Frame, access_pa_via_va, virt_to_phys, frame_allocator};
+use core::mem::size_of;
+use core::ptr::{self, read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2; // 设备写入这个缓冲区
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+// 已用环中的一项
+#[repr(C)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+pub struct VirtQueue {
+    frame: Frame,
+    pages: usize,
+    size: u16,
+    // 三部分的内核虚拟地址
+    desc: usize,
+    avail: usize,
+    used: usize,
+    free_head: u16,
+    num_free: u16,
+    avail_idx: u16,
+    last_used_idx: u16,
+}
+
+fn page_ceil(size: usize) -> usize {
+    (size + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE
+}
+
+impl VirtQueue {
+    /// 新建一个有 size 个描述符的 virtqueue ，size 必须是 2 的幂
+    pub fn new(size: u16) -> Result<VirtQueue, &'static str> {
+        if !size.is_power_of_two() {
+            return Err("virtqueue size must be a power of two");
+        }
+        let n = size as usize;
+        // 可用环：flags 、idx 、ring[size] 、used_event ；已用环：flags 、idx 、ring[size] 、avail_event
+        let used_offset = page_ceil(size_of::<Descriptor>() * n + 6 + 2 * n);
+        // 伙伴分配器按 2 的幂分配，记下实际分配的页数，回收时原样交还
+        let pages = ((used_offset + page_ceil(6 + size_of::<UsedElem>() * n)) / PAGE_SIZE).next_power_of_two();
+        let frame = frame_allocator::alloc_frames(pages).ok_or("out of memory for virtqueue")?;
+        let base = access_pa_via_va(frame.start_address());
+        unsafe { ptr::write_bytes(base as *mut u8, 0, pages * PAGE_SIZE) };
+        let queue = VirtQueue {
+            frame,
+            pages,
+            size,
+            desc: base,
+            avail: base + size_of::<Descriptor>() * n,
+            used: base + used_offset,
+            free_head: 0,
+            num_free: size,
+            avail_idx: 0,
+            last_used_idx: 0,
+        };
+        for i in 0..size - 1 {
+            unsafe { (*queue.desc(i)).next = i + 1 };
+        }
+        Ok(queue)
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn desc_paddr(&self) -> usize {
+        virt_to_phys(self.desc)
+    }
+
+    pub fn avail_paddr(&self) -> usize {
+        virt_to_phys(self.avail)
+    }
+
+    pub fn used_paddr(&self) -> usize {
+        virt_to_phys(self.used)
+    }
+
+    fn desc(&self, index: u16) -> *mut Descriptor {
+        (self.desc + index as usize * size_of::<Descriptor>()) as *mut Descriptor
+    }
+
+    /// 把一个请求放入可用环，返回描述符链表头的下标。
+    /// inputs 是设备读取的缓冲区，outputs 是设备写入的缓冲区，它们都必须位于内核的线性映射中，
+    /// 并且在请求完成之前保持有效
+    pub fn add(&mut self, inputs: &[&[u8]], outputs: &[&mut [u8]]) -> Result<u16, &'static str> {
+        let count = inputs.len() + outputs.len();
+        if count == 0 || count > self.num_free as usize {
+            return Err("virtqueue is full");
+        }
+        let buffers = inputs.iter()
+            .map(|buf| (buf.as_ptr() as usize, buf.len(), 0))
+            .chain(outputs.iter().map(|buf| (buf.as_ptr() as usize, buf.len(), DESC_F_WRITE)));
+        let head = self.free_head;
+        for (i, (addr, len, flags)) in buffers.enumerate() {
+            let index = self.free_head;
+            let desc = unsafe { &mut *self.desc(index) };
+            desc.addr = virt_to_phys(addr) as u64;
+            desc.len = len as u32;
+            desc.flags = if i + 1 < count { flags | DESC_F_NEXT } else { flags };
+            self.free_head = desc.next;
+        }
+        self.num_free -= count as u16;
+
+        // 先写好可用环中的一项，再增加 idx ，设备看到新的 idx 时这一项一定已经就绪
+        let slot = self.avail_idx % self.size;
+        unsafe { write_volatile((self.avail + 4 + 2 * slot as usize) as *mut u16, head) };
+        fence(Ordering::SeqCst);
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        unsafe { write_volatile((self.avail + 2) as *mut u16, self.avail_idx) };
+        fence(Ordering::SeqCst);
+        Ok(head)
+    }
+
+    /// 设备是否完成了新的请求
+    pub fn can_pop(&self) -> bool {
+        let used_idx = unsafe { read_volatile((self.used + 2) as *const u16) };
+        used_idx != self.last_used_idx
+    }
+
+    /// 取出一个已经完成的请求，返回描述符链表头的下标和设备写入的字节数，并回收它的描述符
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.can_pop() {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let slot = self.last_used_idx % self.size;
+        let elem = unsafe { read_volatile((self.used + 4 + size_of::<UsedElem>() * slot as usize) as *const UsedElem) };
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+
+        // 把整条链放回空闲链表的头部
+        let head = elem.id as u16;
+        let mut index = head;
+        loop {
+            self.num_free += 1;
+            let desc = unsafe { &mut *self.desc(index) };
+            if desc.flags & DESC_F_NEXT == 0 {
+                desc.next = self.free_head;
+                break;
+            }
+            index = desc.next;
+        }
+        self.free_head = head;
+        Some((head, elem.len))
+    }
+}
+
+impl Drop for VirtQueue {
+    fn drop(&mut self) {
+        frame_allocator::dealloc_frames(self.frame, self.pages);
+    }
+}
@@ -0,0 +1,133 @@
+//! 块设备
+//!
+//! 磁盘类的文件系统都通过 `BlockDevice` 访问存储设备，设备以固定大小的扇区为单位读写。
+//! `read_at` 和 `write_at` 在此之上提供按字节访问，不对齐的部分先读出整个扇区再修改。
+
+use super::vfs::{FsError, Result};
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::vec;
+use core::slice;
+
+pub const SECTOR_SIZE: usize = 512;
+
+pub trait BlockDevice: Send + Sync {
+    // 设备的扇区数
+    fn sector_count(&self) -> usize;
+    // 读取第 id 个扇区，buf 的长度为 SECTOR_SIZE
+    fn read_sector(&self, id: usize, buf: &mut [u8]) -> Result<()>;
+    // 写入第 id 个扇区，buf 的长度为 SECTOR_SIZE
+    fn write_sector(&self, id: usize, buf: &[u8]) -> Result<()>;
+    // 将设备自己缓存的数据写回存储介质
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl dyn BlockDevice {
+    /// 从字节偏移 offset 处读取 buf.len() 个字节
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        let mut sector = vec![0u8; SECTOR_SIZE];
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let in_sector = pos % SECTOR_SIZE;
+            let n = (SECTOR_SIZE - in_sector).min(buf.len() - done);
+            if n == SECTOR_SIZE {
+                self.read_sector(pos / SECTOR_SIZE, &mut buf[done..done + n])?;
+            } else {
+                self.read_sector(pos / SECTOR_SIZE, &mut sector)?;
+                buf[done..done + n].copy_from_slice(&sector[in_sector..in_sector + n]);
+            }
+            done += n;
+        }
+        Ok(())
+    }
+
+    /// 从字节偏移 offset 处写入 buf
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> Result<()> {
+        let mut sector = vec![0u8; SECTOR_SIZE];
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let in_sector = pos % SECTOR_SIZE;
+            let n = (SECTOR_SIZE - in_sector).min(buf.len() - done);
+            if n == SECTOR_SIZE {
+                self.write_sector(pos / SECTOR_SIZE, &buf[done..done + n])?;
+            } else {
+                self.read_sector(pos / SECTOR_SIZE, &mut sector)?;
+                sector[in_sector..in_sector + n].copy_from_slice(&buf[done..done + n]);
+                self.write_sector(pos / SECTOR_SIZE, &sector)?;
+            }
+            done += n;
+        }
+        Ok(())
+    }
+}
+
+/// 链接进内核镜像的磁盘镜像，位于 .data 段中，可以作为 RamDisk 读写
+pub fn disk_image() -> (usize, usize) {
+    let start = _disk_image_start as usize;
+    (start, _disk_image_end as usize - start)
+}
+
+/// 以一段内存作为存储介质的块设备
+pub struct RamDisk {
+    base: usize,
+    sectors: usize,
+    lock: Mutex<()>,
+}
+
+impl RamDisk {
+    /// [base, base + size) 必须是内核可以读写的内存，并且在整个运行期间都属于这个设备
+    pub unsafe fn new(base: usize, size: usize) -> RamDisk {
+        RamDisk {
+            base,
+            sectors: size / SECTOR_SIZE,
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn sector(&self, id: usize) -> Result<&mut [u8]> {
+        if id >= self.sectors {
+            return Err(FsError::DeviceError);
+        }
+        Ok(unsafe { slice::from_raw_parts_mut((self.base + id * SECTOR_SIZE) as *mut u8, SECTOR_SIZE) })
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn sector_count(&self) -> usize {
+        self.sectors
+    }
+
+    fn read_sector(&self, id: usize, buf: &mut [u8]) -> Result<()> {
+        let _lock = self.lock.lock();
+        buf.copy_from_slice(self.sector(id)?);
+        Ok(())
+    }
+
+    fn write_sector(&self, id: usize, buf: &[u8]) -> Result<()> {
+        let _lock = self.lock.lock();
+        self.sector(id)?.copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+global_asm!(concat!(
+    r#"
+    .section .data
+    .global _disk_image_start
+    .global _disk_image_end
+    .align 12
+_disk_image_start:
+    .incbin ""#,
+    env!("sfsimg"),
+    r#""
+_disk_image_end:
+"#
+));
+
+extern "C" {
+    fn _disk_image_start();
+    fn _disk_image_end();
+}
@@ -0,0 +1,270 @@
+//! 块缓存
+//!
+//! `BlockCache` 包装另一个块设备，自己也实现 `BlockDevice` ，文件系统感觉不到它的存在。
+//! 缓存以 CACHE_BLOCK_SIZE 字节为单位，每块占用一个页帧，块数达到上限后换出最久没有访问的块。
+//! 写入只修改缓存中的块并把它标记为脏，脏块在被换出、flush 或者写回线程周期性运行时才写回设备。
+//! 所有块缓存都登记在 CACHES 中，写回线程和 /dev/cachestat 通过它找到每一个缓存。
+
+use super::block::{BlockDevice, SECTOR_SIZE};
+use super::vfs::{INode, FsError, FileType, Metadata, Result};
+use crate::consts::{PAGE_SIZE, CACHE_FLUSH_INTERVAL};
+use crate::new_memory::{Frame, access_pa_via_va, frame_allocator};
+use crate::sync::{Mutex, SpinNoIrqLock};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::any::Any;
+use core::slice;
+use lazy_static::*;
+
+pub const CACHE_BLOCK_SIZE: usize = PAGE_SIZE;
+const SECTORS_PER_BLOCK: usize = CACHE_BLOCK_SIZE / SECTOR_SIZE;
+
+lazy_static! {
+    static ref CACHES: SpinNoIrqLock<Vec<Weak<BlockCache>>> = SpinNoIrqLock::new(Vec::new());
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub writebacks: usize, // 写回设备的块数
+}
+
+pub struct BlockCache {
+    name: String,
+    device: Arc<dyn BlockDevice>,
+    capacity: usize,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    entries: Vec<Entry>,
+    index: BTreeMap<usize, usize>, // 块号到 entries 中下标的映射
+    clock: u64,                    // 每次访问加一，作为 LRU 的时间
+    stats: CacheStats,
+}
+
+struct Entry {
+    block: usize,
+    frame: Frame,
+    dirty: bool,
+    last_used: u64,
+}
+
+impl Entry {
+    fn data(&self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(access_pa_via_va(self.frame.start_address()) as *mut u8, CACHE_BLOCK_SIZE) }
+    }
+}
+
+impl BlockCache {
+    /// 在 device 之上建立一个最多缓存 capacity 块的缓存，name 用于在 /dev/cachestat 中区分各个缓存
+    pub fn new(name: &str, device: Arc<dyn BlockDevice>, capacity: usize) -> Arc<BlockCache> {
+        assert!(capacity > 0, "block cache must hold at least one block");
+        let cache = Arc::new(BlockCache {
+            name: String::from(name),
+            device,
+            capacity,
+            inner: Mutex::new(Inner {
+                entries: Vec::new(),
+                index: BTreeMap::new(),
+                clock: 0,
+                stats: CacheStats::default(),
+            }),
+        });
+        let mut caches = CACHES.lock();
+        caches.retain(|cache| cache.upgrade().is_some());
+        caches.push(Arc::downgrade(&cache));
+        cache
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.inner.lock().stats
+    }
+
+    // 第 block 块中实际存在的扇区数，只有设备的最后一块可能不满
+    fn sectors_in(&self, block: usize) -> usize {
+        SECTORS_PER_BLOCK.min(self.device.sector_count() - block * SECTORS_PER_BLOCK)
+    }
+
+    fn write_back(&self, entry: &mut Entry, stats: &mut CacheStats) -> Result<()> {
+        let data = entry.data();
+        for i in 0..self.sectors_in(entry.block) {
+            let sector = entry.block * SECTORS_PER_BLOCK + i;
+            self.device.write_sector(sector, &data[i * SECTOR_SIZE..(i + 1) * SECTOR_SIZE])?;
+        }
+        entry.dirty = false;
+        stats.writebacks += 1;
+        Ok(())
+    }
+
+    // 第 block 块在 entries 中的下标，不在缓存中时先读入，必要时换出最久没有访问的块
+    fn get(&self, inner: &mut Inner, block: usize) -> Result<usize> {
+        inner.clock += 1;
+        let now = inner.clock;
+        if let Some(&i) = inner.index.get(&block) {
+            inner.stats.hits += 1;
+            inner.entries[i].last_used = now;
+            return Ok(i);
+        }
+        inner.stats.misses += 1;
+        let frame = if inner.entries.len() < self.capacity {
+            frame_allocator::alloc_frame()
+        } else {
+            None
+        };
+        let i = match frame {
+            Some(frame) => {
+                inner.entries.push(Entry { block, frame, dirty: false, last_used: now });
+                inner.entries.len() - 1
+            }
+            None => {
+                let victim = (0..inner.entries.len())
+                    .min_by_key(|&i| inner.entries[i].last_used)
+                    .ok_or(FsError::NoDeviceSpace)?;
+                let Inner { entries, index, stats, .. } = &mut *inner;
+                let entry = &mut entries[victim];
+                if entry.dirty {
+                    self.write_back(entry, stats)?;
+                }
+                // 读入失败的块不在 index 中，不能删掉其他块的映射
+                if index.get(&entry.block) == Some(&victim) {
+                    index.remove(&entry.block);
+                }
+                victim
+            }
+        };
+        let entry = &mut inner.entries[i];
+        entry.block = block;
+        entry.last_used = now;
+        let data = entry.data();
+        for s in 0..self.sectors_in(block) {
+            self.device.read_sector(block * SECTORS_PER_BLOCK + s, &mut data[s * SECTOR_SIZE..(s + 1) * SECTOR_SIZE])?;
+        }
+        inner.index.insert(block, i);
+        Ok(i)
+    }
+
+    // 写回所有脏块，不要求底层设备写回它自己的缓存
+    fn write_back_all(&self) -> Result<()> {
+        let mut inner = self.inner.lock();
+        let Inner { entries, stats, .. } = &mut *inner;
+        for entry in entries.iter_mut().filter(|entry| entry.dirty) {
+            self.write_back(entry, stats)?;
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for BlockCache {
+    fn sector_count(&self) -> usize {
+        self.device.sector_count()
+    }
+
+    fn read_sector(&self, id: usize, buf: &mut [u8]) -> Result<()> {
+        if id >= self.sector_count() {
+            return Err(FsError::DeviceError);
+        }
+        let mut inner = self.inner.lock();
+        let i = self.get(&mut inner, id / SECTORS_PER_BLOCK)?;
+        let offset = id % SECTORS_PER_BLOCK * SECTOR_SIZE;
+        buf.copy_from_slice(&inner.entries[i].data()[offset..offset + SECTOR_SIZE]);
+        Ok(())
+    }
+
+    fn write_sector(&self, id: usize, buf: &[u8]) -> Result<()> {
+        if id >= self.sector_count() {
+            return Err(FsError::DeviceError);
+        }
+        let mut inner = self.inner.lock();
+        let i = self.get(&mut inner, id / SECTORS_PER_BLOCK)?;
+        let offset = id % SECTORS_PER_BLOCK * SECTOR_SIZE;
+        let entry = &mut inner.entries[i];
+        entry.data()[offset..offset + SECTOR_SIZE].copy_from_slice(buf);
+        entry.dirty = true;
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.write_back_all()?;
+        self.device.flush()
+    }
+}
+
+impl Drop for BlockCache {
+    fn drop(&mut self) {
+        if self.flush().is_err() {
+            println!("cache {}: failed to write back dirty blocks", self.name);
+        }
+        for entry in self.inner.lock().entries.drain(..) {
+            frame_allocator::dealloc_frame(entry.frame);
+        }
+    }
+}
+
+// 当前存在的所有块缓存
+fn caches() -> Vec<Arc<BlockCache>> {
+    CACHES.lock().iter().filter_map(Weak::upgrade).collect()
+}
+
+/// 写回所有块缓存中的脏块
+pub fn flush_all() -> Result<()> {
+    for cache in caches() {
+        cache.flush()?;
+    }
+    Ok(())
+}
+
+/// 启动写回线程，每隔 CACHE_FLUSH_INTERVAL 个时钟周期写回一次所有脏块
+pub fn spawn_flusher() {
+    crate::process::spawn(|| loop {
+        crate::process::sleep(CACHE_FLUSH_INTERVAL);
+        if let Err(err) = flush_all() {
+            println!("cache: write back failed: {:?}", err);
+        }
+    });
+}
+
+/// /dev/cachestat ，每个块缓存一行，依次是名字、容量（块数）、命中次数、缺失次数和写回的块数
+pub struct CacheStatFile;
+
+impl INode for CacheStatFile {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let mut text = String::new();
+        for cache in caches() {
+            let stats = cache.stats();
+            text += &format!("{} {} {} {} {}\n", cache.name, cache.capacity, stats.hits, stats.misses, stats.writebacks);
+        }
+        let text = text.as_bytes();
+        if offset >= text.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(text.len() - offset);
+        buf[..len].copy_from_slice(&text[offset..offset + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::NotSupported)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            dev: 0,
+            inode: 5,
+            size: 0,
+            blk_size: 0,
+            blocks: 0,
+            type_: FileType::CharDevice,
+            mode: 0o444,
+            nlinks: 1,
+        })
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
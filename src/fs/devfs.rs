@@ -0,0 +1,179 @@
+//! 设备文件系统，挂载在 /dev
+//!
+//! 只有一层目录，其中每一项都是一个设备。驱动初始化时通过 `register` 加入自己的设备文件。
+
+use super::vfs::{INode, FileSystem, FsError, FileType, Metadata, Result};
+use crate::tty::TTY;
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::any::Any;
+use lazy_static::*;
+
+lazy_static! {
+    static ref DEVICES: Mutex<BTreeMap<String, Arc<dyn INode>>> = {
+        let mut devices: BTreeMap<String, Arc<dyn INode>> = BTreeMap::new();
+        devices.insert(String::from("console"), CONSOLE.clone());
+        devices.insert(String::from("null"), Arc::new(Null));
+        devices.insert(String::from("zero"), Arc::new(Zero));
+        Mutex::new(devices)
+    };
+    static ref CONSOLE: Arc<dyn INode> = Arc::new(Console);
+}
+
+// 根目录的 inode 编号，console 、null 和 zero 依次为 2 、3 、4
+const ROOT_INODE: usize = 1;
+
+/// 在 /dev 下加入一个名为 name 的设备文件
+pub fn register(name: &str, device: Arc<dyn INode>) {
+    DEVICES.lock().insert(String::from(name), device);
+}
+
+/// 控制台，进程的标准输入、标准输出和标准错误都指向它
+pub fn console() -> Arc<dyn INode> {
+    CONSOLE.clone()
+}
+
+pub struct DevFs;
+
+impl DevFs {
+    pub fn new() -> DevFs {
+        DevFs
+    }
+}
+
+impl FileSystem for DevFs {
+    fn root_inode(&self) -> Arc<dyn INode> {
+        Arc::new(DevRoot)
+    }
+}
+
+struct DevRoot;
+
+impl INode for DevRoot {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::IsDir)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            dev: 0,
+            inode: ROOT_INODE,
+            size: DEVICES.lock().len(),
+            blk_size: 0,
+            blocks: 0,
+            type_: FileType::Dir,
+            mode: 0o755,
+            nlinks: 2,
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn INode>> {
+        DEVICES.lock().get(name).cloned().ok_or(FsError::EntryNotFound)
+    }
+
+    fn create(&self, _name: &str, _type: FileType, _mode: u32) -> Result<Arc<dyn INode>> {
+        Err(FsError::NotSupported)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+
+    fn readdir(&self, index: usize) -> Result<String> {
+        match index {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            index => DEVICES.lock().keys().nth(index - 2).cloned().ok_or(FsError::EntryNotFound),
+        }
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+fn char_device(inode: usize) -> Metadata {
+    Metadata {
+        dev: 0,
+        inode,
+        size: 0,
+        blk_size: 0,
+        blocks: 0,
+        type_: FileType::CharDevice,
+        mode: 0o666,
+        nlinks: 1,
+    }
+}
+
+pub struct Console;
+
+impl INode for Console {
+    // 经过终端的行规程，没有可读的输入时睡眠等待
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        TTY.read(buf)
+    }
+
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        TTY.write(buf);
+        Ok(buf.len())
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(char_device(2))
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+// 读取时总是到达文件末尾，写入的数据被丢弃
+pub struct Null;
+
+impl INode for Null {
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(char_device(3))
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+// 读出无穷多个 0 ，写入的数据被丢弃
+pub struct Zero;
+
+impl INode for Zero {
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        for byte in buf.iter_mut() {
+            *byte = 0;
+        }
+        Ok(buf.len())
+    }
+
+    fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(char_device(4))
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,1035 @@
+//! ext2：读写 mke2fs 生成的标准 ext2 镜像，不支持日志
+//!
+//! 设备从 1024 字节处开始是超级块，块按块组划分，每个块组有自己的块位图、inode 位图和 inode 表，
+//! 块组描述符表紧跟在超级块所在的块之后。
+//! inode 的 i_block 中前 12 项是直接块，之后依次是一级、二级和三级间接块，值为 0 的块是空洞，读出来是 0 。
+//! 目录的内容是一串变长的目录项，每项的 rec_len 一直延伸到下一项，删除时并入前一项，
+//! 块中的第一项被删除时只把它的 inode 号清零。
+//!
+//! 遇到不认识的 incompat 特性时拒绝挂载，遇到不认识的 ro_compat 特性时以只读方式挂载。
+//! 所有修改都直接写到设备上，sync 只需要让设备写回它自己的缓存。
+
+use super::block::BlockDevice;
+use super::vfs::{INode, FileSystem, FsError, FileType, Metadata, Result};
+use crate::sync::Mutex;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::str;
+
+const SUPER_BLOCK_OFFSET: usize = 1024;
+const MAGIC: u16 = 0xef53;
+const ROOT_INO: usize = 2;
+const GOOD_OLD_INODE_SIZE: usize = 128;
+const GOOD_OLD_FIRST_INO: usize = 11;
+const GROUP_DESC_SIZE: usize = 32;
+
+const NDIRECT: usize = 12;
+// i_block 中一级、二级、三级间接块的下标
+const IND_BLOCK: usize = 12;
+
+const INCOMPAT_FILETYPE: u32 = 0x0002;
+const RO_COMPAT_SPARSE_SUPER: u32 = 0x0001;
+const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+// 目录使用了 htree 索引，修改目录之后索引失效，需要清除这个标志
+const INDEX_FL: u32 = 0x1000;
+
+const S_IFMT: u16 = 0xf000;
+const S_IFREG: u16 = 0x8000;
+const S_IFDIR: u16 = 0x4000;
+const S_IFLNK: u16 = 0xa000;
+const S_IFCHR: u16 = 0x2000;
+const S_IFBLK: u16 = 0x6000;
+
+// 目录项中的文件类型
+const FT_UNKNOWN: u8 = 0;
+const FT_REG_FILE: u8 = 1;
+const FT_DIR: u8 = 2;
+const FT_CHRDEV: u8 = 3;
+const FT_BLKDEV: u8 = 4;
+const FT_SYMLINK: u8 = 7;
+
+const MAX_NAME_LEN: usize = 255;
+
+pub struct Ext2 {
+    root: Arc<Ext2INode>,
+}
+
+impl Ext2 {
+    /// 打开 device 上的 ext2 ，不是 ext2 或者使用了不支持的特性时返回 DeviceError 。
+    /// 块大小最大支持 4 KiB ，更大的块中一个目录项的 rec_len 可能达到 65536 ，放不进 16 位
+    pub fn open(device: Arc<dyn BlockDevice>) -> Result<Ext2> {
+        let mut sb = [0u8; 1024];
+        device.read_at(SUPER_BLOCK_OFFSET, &mut sb)?;
+        if get_u16(&sb, 56) != MAGIC || get_u32(&sb, 24) > 2 {
+            return Err(FsError::DeviceError);
+        }
+        let block_size = 1024 << get_u32(&sb, 24);
+        let rev_level = get_u32(&sb, 76);
+        let (inode_size, first_ino, incompat, ro_compat) = if rev_level == 0 {
+            (GOOD_OLD_INODE_SIZE, GOOD_OLD_FIRST_INO, 0, 0)
+        } else {
+            (get_u16(&sb, 88) as usize, get_u32(&sb, 84) as usize, get_u32(&sb, 96), get_u32(&sb, 100))
+        };
+        if incompat & !INCOMPAT_FILETYPE != 0 {
+            println!("ext2: unsupported incompat features {:#x}", incompat);
+            return Err(FsError::DeviceError);
+        }
+        let read_only = ro_compat & !(RO_COMPAT_SPARSE_SUPER | RO_COMPAT_LARGE_FILE) != 0;
+        if read_only {
+            println!("ext2: unsupported ro_compat features {:#x}, mounted read-only", ro_compat);
+        }
+        let blocks_count = get_u32(&sb, 4) as usize;
+        let first_data_block = get_u32(&sb, 20) as usize;
+        let blocks_per_group = get_u32(&sb, 32) as usize;
+        let inodes_per_group = get_u32(&sb, 40) as usize;
+        if blocks_per_group == 0 || inodes_per_group == 0 || inode_size < GOOD_OLD_INODE_SIZE
+            || blocks_count <= first_data_block
+        {
+            return Err(FsError::DeviceError);
+        }
+        let group_count = (blocks_count - first_data_block + blocks_per_group - 1) / blocks_per_group;
+        let gdt_pos = (first_data_block + 1) * block_size;
+        let mut descs = vec![0u8; group_count * GROUP_DESC_SIZE];
+        device.read_at(gdt_pos, &mut descs)?;
+        let shared = Arc::new(Shared {
+            device,
+            block_size,
+            blocks_count,
+            first_data_block,
+            blocks_per_group,
+            inodes_per_group,
+            inodes_count: get_u32(&sb, 0) as usize,
+            // 没有实时时钟，用超级块中最后一次写入的时间作为时间戳。
+            // 被删除的 inode 的 dtime 小于 inode 总数时会被当作孤儿 inode 链表的一部分，所以不能太小
+            time: get_u32(&sb, 48).max(get_u32(&sb, 0) + 1),
+            inode_size,
+            first_ino,
+            filetype: incompat & INCOMPAT_FILETYPE != 0,
+            read_only,
+            gdt_pos,
+            groups: Mutex::new(descs),
+            inodes: Mutex::new(BTreeMap::new()),
+            namespace: Mutex::new(()),
+        });
+        let root = Shared::get_inode(&shared, ROOT_INO)?;
+        if !root.is_dir() {
+            return Err(FsError::DeviceError);
+        }
+        Ok(Ext2 { root })
+    }
+}
+
+impl FileSystem for Ext2 {
+    fn root_inode(&self) -> Arc<dyn INode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.root.fs.device.flush()
+    }
+}
+
+// 同一个 ext2 中所有 inode 共享的状态
+struct Shared {
+    device: Arc<dyn BlockDevice>,
+    block_size: usize,
+    blocks_count: usize,
+    first_data_block: usize,
+    blocks_per_group: usize,
+    inodes_per_group: usize,
+    inodes_count: usize,
+    time: u32,
+    inode_size: usize,
+    first_ino: usize, // 第一个可以分配的 inode 号，之前的都是保留的
+    filetype: bool,   // 目录项中是否记录了文件类型
+    read_only: bool,
+    gdt_pos: usize,
+    // 所有块组描述符，分配和释放块或者 inode 时持有
+    groups: Mutex<Vec<u8>>,
+    // 已经读入内存的 inode ，保证同一个 inode 在内存中只有一份
+    inodes: Mutex<BTreeMap<usize, Weak<Ext2INode>>>,
+    namespace: Mutex<()>, // 修改目录结构时持有
+}
+
+// 位图的种类，对应块组描述符和超级块中的字段
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Bitmap {
+    Block,
+    Inode,
+}
+
+impl Shared {
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(FsError::ReadOnly);
+        }
+        Ok(())
+    }
+
+    fn group_count(&self) -> usize {
+        (self.blocks_count - self.first_data_block + self.blocks_per_group - 1) / self.blocks_per_group
+    }
+
+    // 第 group 个块组中位图的位数
+    fn bits_in_group(&self, kind: Bitmap, group: usize) -> usize {
+        match kind {
+            Bitmap::Block => self.blocks_per_group
+                .min(self.blocks_count - self.first_data_block - group * self.blocks_per_group),
+            Bitmap::Inode => self.inodes_per_group,
+        }
+    }
+
+    // 修改块组描述符和超级块中的空闲计数，以及块组中的目录数
+    fn update_counts(&self, groups: &mut [u8], kind: Bitmap, group: usize, delta: i32, dirs: i32) -> Result<()> {
+        let desc = group * GROUP_DESC_SIZE;
+        let (desc_field, sb_field) = match kind {
+            Bitmap::Block => (12, 12),
+            Bitmap::Inode => (14, 16),
+        };
+        let free = (get_u16(groups, desc + desc_field) as i32 + delta) as u16;
+        put_u16(groups, desc + desc_field, free);
+        let used_dirs = (get_u16(groups, desc + 16) as i32 + dirs) as u16;
+        put_u16(groups, desc + 16, used_dirs);
+        self.device.write_at(self.gdt_pos + desc + 12, &groups[desc + 12..desc + 18])?;
+        let mut buf = [0u8; 4];
+        self.device.read_at(SUPER_BLOCK_OFFSET + sb_field, &mut buf)?;
+        let total = (get_u32(&buf, 0) as i64 + delta as i64) as u32;
+        self.device.write_at(SUPER_BLOCK_OFFSET + sb_field, &total.to_le_bytes())
+    }
+
+    // 在位图中分配一位，从块组 goal 开始查找，返回 (块组, 组内序号)
+    fn alloc_bit(&self, kind: Bitmap, goal: usize, dir: bool) -> Result<(usize, usize)> {
+        let mut groups = self.groups.lock();
+        let group_count = self.group_count();
+        let mut bitmap = vec![0u8; self.block_size];
+        for i in 0..group_count {
+            let group = (goal + i) % group_count;
+            let desc = group * GROUP_DESC_SIZE;
+            let (bitmap_field, free_field) = match kind {
+                Bitmap::Block => (0, 12),
+                Bitmap::Inode => (4, 14),
+            };
+            if get_u16(&groups, desc + free_field) == 0 {
+                continue;
+            }
+            let bitmap_pos = get_u32(&groups, desc + bitmap_field) as usize * self.block_size;
+            self.device.read_at(bitmap_pos, &mut bitmap)?;
+            // 第 0 组中编号小于 first_ino 的 inode 是保留的
+            let start = if kind == Bitmap::Inode && group == 0 { self.first_ino - 1 } else { 0 };
+            let found = (start..self.bits_in_group(kind, group))
+                .find(|&bit| bitmap[bit / 8] & (1 << (bit % 8)) == 0);
+            if let Some(bit) = found {
+                bitmap[bit / 8] |= 1 << (bit % 8);
+                self.device.write_at(bitmap_pos + bit / 8, &bitmap[bit / 8..bit / 8 + 1])?;
+                self.update_counts(&mut groups, kind, group, -1, if dir { 1 } else { 0 })?;
+                return Ok((group, bit));
+            }
+        }
+        Err(FsError::NoDeviceSpace)
+    }
+
+    // 释放位图中的一位。位不存在或者本来就是空闲的说明文件系统已经损坏
+    fn free_bit(&self, kind: Bitmap, group: usize, bit: usize, dir: bool) -> Result<()> {
+        if group >= self.group_count() || bit >= self.bits_in_group(kind, group) {
+            return Err(FsError::DeviceError);
+        }
+        let mut groups = self.groups.lock();
+        let desc = group * GROUP_DESC_SIZE;
+        let bitmap_field = if kind == Bitmap::Block { 0 } else { 4 };
+        let pos = get_u32(&groups, desc + bitmap_field) as usize * self.block_size + bit / 8;
+        let mut byte = [0u8];
+        self.device.read_at(pos, &mut byte)?;
+        if byte[0] & (1 << (bit % 8)) == 0 {
+            return Err(FsError::DeviceError);
+        }
+        byte[0] &= !(1 << (bit % 8));
+        self.device.write_at(pos, &byte)?;
+        self.update_counts(&mut groups, kind, group, 1, if dir { -1 } else { 0 })
+    }
+
+    // 分配一个清零的块，尽量位于块组 goal 中
+    fn alloc_block(&self, goal: usize) -> Result<u32> {
+        let (group, bit) = self.alloc_bit(Bitmap::Block, goal, false)?;
+        let block = self.first_data_block + group * self.blocks_per_group + bit;
+        self.device.write_at(block * self.block_size, &vec![0u8; self.block_size])?;
+        Ok(block as u32)
+    }
+
+    fn free_block(&self, block: u32) -> Result<()> {
+        if (block as usize) < self.first_data_block || block as usize >= self.blocks_count {
+            return Err(FsError::DeviceError);
+        }
+        let index = block as usize - self.first_data_block;
+        self.free_bit(Bitmap::Block, index / self.blocks_per_group, index % self.blocks_per_group, false)
+    }
+
+    // inode 在设备上的字节偏移，超级块中的 inode 总数可能多于块组能容纳的数量
+    fn inode_pos(&self, ino: usize) -> Result<usize> {
+        let group = (ino - 1) / self.inodes_per_group;
+        let index = (ino - 1) % self.inodes_per_group;
+        if group >= self.group_count() {
+            return Err(FsError::DeviceError);
+        }
+        let table = get_u32(&self.groups.lock(), group * GROUP_DESC_SIZE + 8) as usize;
+        Ok(table * self.block_size + index * self.inode_size)
+    }
+
+    // 取得编号为 ino 的 inode ，不在内存中时从磁盘读入
+    fn get_inode(this: &Arc<Self>, ino: usize) -> Result<Arc<Ext2INode>> {
+        if ino == 0 || ino > this.inodes_count {
+            return Err(FsError::DeviceError);
+        }
+        let mut inodes = this.inodes.lock();
+        if let Some(inode) = inodes.get(&ino).and_then(Weak::upgrade) {
+            return Ok(inode);
+        }
+        let mut raw = [0u8; GOOD_OLD_INODE_SIZE];
+        this.device.read_at(this.inode_pos(ino)?, &mut raw)?;
+        let inode = Arc::new(Ext2INode {
+            ino,
+            fs: this.clone(),
+            raw: Mutex::new(raw),
+        });
+        inodes.insert(ino, Arc::downgrade(&inode));
+        Ok(inode)
+    }
+
+    // 在块组 goal 附近分配并初始化一个新的 inode
+    fn new_inode(this: &Arc<Self>, goal: usize, mode: u16) -> Result<Arc<Ext2INode>> {
+        let is_dir = mode & S_IFMT == S_IFDIR;
+        let (group, bit) = this.alloc_bit(Bitmap::Inode, goal, is_dir)?;
+        let ino = group * this.inodes_per_group + bit + 1;
+        let mut raw = [0u8; GOOD_OLD_INODE_SIZE];
+        put_u16(&mut raw, 0, mode);
+        // atime 、ctime 和 mtime
+        for &offset in [8, 12, 16].iter() {
+            put_u32(&mut raw, offset, this.time);
+        }
+        // 目录被父目录中的目录项和自己的 "." 引用
+        put_u16(&mut raw, 26, if is_dir { 2 } else { 1 });
+        // inode 表中可能残留着之前被删除的 inode ，整个清零之后再写入
+        let pos = this.inode_pos(ino)?;
+        let written = this.device.write_at(pos, &vec![0u8; this.inode_size])
+            .and_then(|_| this.device.write_at(pos, &raw));
+        if let Err(err) = written {
+            this.free_bit(Bitmap::Inode, group, bit, is_dir)?;
+            return Err(err);
+        }
+        let inode = Arc::new(Ext2INode {
+            ino,
+            fs: this.clone(),
+            raw: Mutex::new(raw),
+        });
+        this.inodes.lock().insert(ino, Arc::downgrade(&inode));
+        Ok(inode)
+    }
+}
+
+// 解析出的一个目录项
+struct DirEntry {
+    ino: usize,
+    pos: usize, // 在目录文件中的偏移
+    rec_len: usize,
+    name: String,
+    prev: Option<usize>, // 同一块中前一项的偏移
+}
+
+pub struct Ext2INode {
+    ino: usize,
+    fs: Arc<Shared>,
+    // 磁盘上 inode 的前 128 字节，修改之后立即写回
+    raw: Mutex<[u8; GOOD_OLD_INODE_SIZE]>,
+}
+
+impl Ext2INode {
+    fn mode(raw: &[u8]) -> u16 {
+        get_u16(raw, 0)
+    }
+
+    fn size(raw: &[u8]) -> usize {
+        get_u32(raw, 4) as usize
+    }
+
+    fn links(raw: &[u8]) -> u16 {
+        get_u16(raw, 26)
+    }
+
+    fn is_dir(&self) -> bool {
+        Self::mode(&*self.raw.lock()) & S_IFMT == S_IFDIR
+    }
+
+    fn group(&self) -> usize {
+        (self.ino - 1) / self.fs.inodes_per_group
+    }
+
+    fn sync_raw(&self, raw: &[u8]) -> Result<()> {
+        self.fs.device.write_at(self.fs.inode_pos(self.ino)?, raw)
+    }
+
+    // i_blocks 以 512 字节为单位，包括间接块
+    fn add_blocks(&self, raw: &mut [u8], delta: i32) {
+        let sectors = (self.fs.block_size / 512) as i64;
+        let blocks = (get_u32(raw, 28) as i64 + delta as i64 * sectors) as u32;
+        put_u32(raw, 28, blocks);
+    }
+
+    // 没有数据块的短符号链接，内容保存在 i_block 中
+    fn is_fast_symlink(&self, raw: &[u8]) -> bool {
+        let acl_sectors = if get_u32(raw, 104) != 0 { self.fs.block_size / 512 } else { 0 };
+        Self::mode(raw) & S_IFMT == S_IFLNK && get_u32(raw, 28) as usize == acl_sectors
+    }
+
+    // 第 index 个数据块在 i_block 中的下标，以及每一级间接块中的下标
+    fn block_path(&self, index: usize) -> Result<(usize, Vec<usize>)> {
+        let per_block = self.fs.block_size / 4;
+        if index < NDIRECT {
+            return Ok((index, Vec::new()));
+        }
+        let mut index = index - NDIRECT;
+        let mut span = per_block;
+        for level in 1..=3 {
+            if index < span {
+                let mut path = vec![0; level];
+                for offset in path.iter_mut().rev() {
+                    *offset = index % per_block;
+                    index /= per_block;
+                }
+                return Ok((IND_BLOCK + level - 1, path));
+            }
+            index -= span;
+            span = span.checked_mul(per_block).ok_or(FsError::NoDeviceSpace)?;
+        }
+        Err(FsError::NoDeviceSpace)
+    }
+
+    // 第 index 个数据块的块号，空洞返回 0 。create 为真时为空洞和缺少的间接块分配新块
+    fn get_block(&self, raw: &mut [u8], index: usize, create: bool) -> Result<u32> {
+        let (slot, path) = self.block_path(index)?;
+        let mut block = get_u32(raw, 40 + slot * 4);
+        if block == 0 {
+            if !create {
+                return Ok(0);
+            }
+            block = self.fs.alloc_block(self.group())?;
+            put_u32(raw, 40 + slot * 4, block);
+            self.add_blocks(raw, 1);
+        }
+        for offset in path {
+            let pos = block as usize * self.fs.block_size + offset * 4;
+            let mut buf = [0u8; 4];
+            self.fs.device.read_at(pos, &mut buf)?;
+            let mut next = get_u32(&buf, 0);
+            if next == 0 {
+                if !create {
+                    return Ok(0);
+                }
+                next = self.fs.alloc_block(self.group())?;
+                self.fs.device.write_at(pos, &next.to_le_bytes())?;
+                self.add_blocks(raw, 1);
+            }
+            block = next;
+        }
+        Ok(block)
+    }
+
+    // 释放以间接块 block 为根的子树中序号不小于 keep 的数据块，level 为 1 时 block 中直接是数据块号。
+    // keep 为 0 时调用者负责释放 block 本身
+    fn free_tree(&self, raw: &mut [u8], block: u32, level: u32, keep: usize) -> Result<()> {
+        let per_block = self.fs.block_size / 4;
+        let span = per_block.pow(level - 1);
+        let pos = block as usize * self.fs.block_size;
+        let mut entries = vec![0u8; self.fs.block_size];
+        self.fs.device.read_at(pos, &mut entries)?;
+        for i in 0..per_block {
+            let child = get_u32(&entries, i * 4);
+            if child == 0 || (i + 1) * span <= keep {
+                continue;
+            }
+            let child_keep = keep.saturating_sub(i * span);
+            if level > 1 {
+                self.free_tree(raw, child, level - 1, child_keep)?;
+            }
+            if child_keep == 0 {
+                self.fs.free_block(child)?;
+                self.add_blocks(raw, -1);
+                put_u32(&mut entries, i * 4, 0);
+            }
+        }
+        if keep > 0 {
+            self.fs.device.write_at(pos, &entries)?;
+        }
+        Ok(())
+    }
+
+    // 释放第 keep 个之后的所有数据块，以及不再需要的间接块
+    fn free_blocks_from(&self, raw: &mut [u8], keep: usize) -> Result<()> {
+        for slot in keep.min(NDIRECT)..NDIRECT {
+            let block = get_u32(raw, 40 + slot * 4);
+            if block != 0 {
+                self.fs.free_block(block)?;
+                self.add_blocks(raw, -1);
+                put_u32(raw, 40 + slot * 4, 0);
+            }
+        }
+        let per_block = self.fs.block_size / 4;
+        let mut base = NDIRECT;
+        for level in 1..=3u32 {
+            let slot = IND_BLOCK + level as usize - 1;
+            let block = get_u32(raw, 40 + slot * 4);
+            let span = per_block.pow(level);
+            if block != 0 && keep < base + span {
+                let child_keep = keep.saturating_sub(base);
+                self.free_tree(raw, block, level, child_keep)?;
+                if child_keep == 0 {
+                    self.fs.free_block(block)?;
+                    self.add_blocks(raw, -1);
+                    put_u32(raw, 40 + slot * 4, 0);
+                }
+            }
+            base += span;
+        }
+        Ok(())
+    }
+
+    // 修改文件大小。扩展时只修改大小，之后读到的是空洞。
+    // 截断时把最后一块中文件末尾之后的部分清零，之后再扩展时读到的就是 0
+    fn resize_locked(&self, raw: &mut [u8], len: usize) -> Result<()> {
+        if len > u32::max_value() as usize {
+            return Err(FsError::InvalidParam);
+        }
+        let block_size = self.fs.block_size;
+        if len < Self::size(raw) {
+            self.free_blocks_from(raw, (len + block_size - 1) / block_size)?;
+            if len % block_size != 0 {
+                let block = self.get_block(raw, len / block_size, false)?;
+                if block != 0 {
+                    let tail = len % block_size;
+                    self.fs.device.write_at(block as usize * block_size + tail, &vec![0u8; block_size - tail])?;
+                }
+            }
+        }
+        put_u32(raw, 4, len as u32);
+        self.sync_raw(raw)
+    }
+
+    // 读取 [offset, offset + buf.len()) ，调用者保证它在文件范围内
+    fn read_locked(&self, raw: &mut [u8], offset: usize, buf: &mut [u8]) -> Result<()> {
+        if self.is_fast_symlink(raw) {
+            // 目标路径保存在 60 字节的 i_block 中，大小超出说明 inode 已经损坏
+            if offset + buf.len() > 60 {
+                return Err(FsError::DeviceError);
+            }
+            buf.copy_from_slice(&raw[40 + offset..40 + offset + buf.len()]);
+            return Ok(());
+        }
+        let block_size = self.fs.block_size;
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let in_block = pos % block_size;
+            let n = (block_size - in_block).min(buf.len() - done);
+            match self.get_block(raw, pos / block_size, false)? {
+                0 => {
+                    for byte in buf[done..done + n].iter_mut() {
+                        *byte = 0;
+                    }
+                }
+                block => self.fs.device.read_at(block as usize * block_size + in_block, &mut buf[done..done + n])?,
+            }
+            done += n;
+        }
+        Ok(())
+    }
+
+    // 写入 [offset, offset + buf.len()) ，必要时分配数据块并扩展文件。
+    // 空间不足时返回已经写入的字节数，一个字节也没有写入时返回错误
+    fn write_locked(&self, raw: &mut [u8], offset: usize, buf: &[u8]) -> Result<usize> {
+        let block_size = self.fs.block_size;
+        let mut done = 0;
+        let mut result = Ok(());
+        while done < buf.len() {
+            let pos = offset + done;
+            let in_block = pos % block_size;
+            let n = (block_size - in_block).min(buf.len() - done);
+            let written = self.get_block(raw, pos / block_size, true).and_then(|block| {
+                self.fs.device.write_at(block as usize * block_size + in_block, &buf[done..done + n])
+            });
+            if let Err(err) = written {
+                result = Err(err);
+                break;
+            }
+            done += n;
+        }
+        if offset + done > Self::size(raw) {
+            put_u32(raw, 4, (offset + done) as u32);
+        }
+        self.sync_raw(raw)?;
+        match result {
+            Err(err) if done == 0 => Err(err),
+            _ => Ok(done),
+        }
+    }
+
+    // 读出目录中所有的项，包括 inode 号为 0 的空闲项
+    fn dir_entries(&self, raw: &mut [u8]) -> Result<Vec<DirEntry>> {
+        if Self::mode(raw) & S_IFMT != S_IFDIR {
+            return Err(FsError::NotDir);
+        }
+        let block_size = self.fs.block_size;
+        let mut data = vec![0u8; Self::size(raw)];
+        self.read_locked(raw, 0, &mut data)?;
+        let mut entries = Vec::new();
+        for (i, block) in data.chunks(block_size).enumerate() {
+            let mut offset = 0;
+            let mut prev = None;
+            while offset < block.len() {
+                let rec_len = get_u16(block, offset + 4) as usize;
+                let name_len = block[offset + 6] as usize;
+                if rec_len < 8 || rec_len % 4 != 0 || offset + rec_len > block.len() || 8 + name_len > rec_len {
+                    return Err(FsError::DeviceError);
+                }
+                let name = str::from_utf8(&block[offset + 8..offset + 8 + name_len]).unwrap_or("");
+                entries.push(DirEntry {
+                    ino: get_u32(block, offset) as usize,
+                    pos: i * block_size + offset,
+                    rec_len,
+                    name: String::from(name),
+                    prev,
+                });
+                prev = Some(i * block_size + offset);
+                offset += rec_len;
+            }
+        }
+        Ok(entries)
+    }
+
+    fn find_entry(&self, raw: &mut [u8], name: &str) -> Result<DirEntry> {
+        self.dir_entries(raw)?
+            .into_iter()
+            .find(|entry| entry.ino != 0 && entry.name == name)
+            .ok_or(FsError::EntryNotFound)
+    }
+
+    fn get_entry(&self, name: &str) -> Result<Arc<Ext2INode>> {
+        let ino = self.find_entry(&mut *self.raw.lock(), name)?.ino;
+        Shared::get_inode(&self.fs, ino)
+    }
+
+    // 写入一个目录项
+    fn write_entry(&self, raw: &mut [u8], pos: usize, ino: usize, rec_len: usize, name: &str, type_: u8) -> Result<()> {
+        let mut entry = vec![0u8; 8 + name.len()];
+        put_u32(&mut entry, 0, ino as u32);
+        put_u16(&mut entry, 4, rec_len as u16);
+        entry[6] = name.len() as u8;
+        // 没有 filetype 特性时这一字节是名字长度的高 8 位，名字不超过 255 字节，所以总是 0
+        entry[7] = if self.fs.filetype { type_ } else { 0 };
+        entry[8..].copy_from_slice(name.as_bytes());
+        self.write_locked(raw, pos, &entry).map(|_| ())
+    }
+
+    // 在目录中加入指向 inode 的目录项，inode 是目录时增加这个目录的链接数
+    fn insert_entry(&self, name: &str, inode: &Ext2INode) -> Result<()> {
+        let (is_dir, type_) = {
+            let raw = inode.raw.lock();
+            (Self::mode(&*raw) & S_IFMT == S_IFDIR, file_type(Self::mode(&*raw)))
+        };
+        let mut raw = self.raw.lock();
+        let needed = entry_size(name.len());
+        let entries = self.dir_entries(&mut *raw)?;
+        let free = entries.iter().find_map(|entry| {
+            let used = if entry.ino == 0 { 0 } else { entry_size(entry.name.len()) };
+            if entry.rec_len - used >= needed {
+                Some((entry, used))
+            } else {
+                None
+            }
+        });
+        match free {
+            Some((entry, 0)) => self.write_entry(&mut *raw, entry.pos, inode.ino, entry.rec_len, name, type_)?,
+            Some((entry, used)) => {
+                // 把已有的一项截短，新的项放在它的剩余空间中
+                let mut rec_len = [0u8; 2];
+                put_u16(&mut rec_len, 0, used as u16);
+                self.write_locked(&mut *raw, entry.pos + 4, &rec_len)?;
+                self.write_entry(&mut *raw, entry.pos + used, inode.ino, entry.rec_len - used, name, type_)?;
+            }
+            None => {
+                // 在目录末尾增加一块，新分配的块已经清零，只需写入目录项并把大小扩展到整块
+                let size = Self::size(&*raw);
+                let block_size = self.fs.block_size;
+                self.write_entry(&mut *raw, size, inode.ino, block_size, name, type_)?;
+                put_u32(&mut *raw, 4, (size + block_size) as u32);
+            }
+        }
+        let flags = get_u32(&*raw, 32);
+        put_u32(&mut *raw, 32, flags & !INDEX_FL);
+        if is_dir {
+            // 子目录中的 ".." 引用了这个目录
+            let links = Self::links(&*raw);
+            put_u16(&mut *raw, 26, links + 1);
+        }
+        self.sync_raw(&*raw)
+    }
+
+    // 删除名为 name 的目录项，is_dir 时减少这个目录的链接数
+    fn remove_entry(&self, name: &str, is_dir: bool) -> Result<()> {
+        let mut raw = self.raw.lock();
+        let entries = self.dir_entries(&mut *raw)?;
+        let entry = entries.iter()
+            .find(|entry| entry.ino != 0 && entry.name == name)
+            .ok_or(FsError::EntryNotFound)?;
+        match entry.prev {
+            Some(prev) => {
+                // 并入前一项
+                let prev_len = entries.iter().find(|e| e.pos == prev).unwrap().rec_len;
+                let mut rec_len = [0u8; 2];
+                put_u16(&mut rec_len, 0, (prev_len + entry.rec_len) as u16);
+                self.write_locked(&mut *raw, prev + 4, &rec_len)?;
+            }
+            None => {
+                self.write_locked(&mut *raw, entry.pos, &[0u8; 4])?;
+            }
+        }
+        let flags = get_u32(&*raw, 32);
+        put_u32(&mut *raw, 32, flags & !INDEX_FL);
+        if is_dir {
+            let links = Self::links(&*raw).checked_sub(1).ok_or(FsError::DeviceError)?;
+            put_u16(&mut *raw, 26, links);
+        }
+        self.sync_raw(&*raw)
+    }
+
+    // 目录移动到 parent 中之后，修改它的 ".."
+    fn set_parent(&self, parent: usize) -> Result<()> {
+        let mut raw = self.raw.lock();
+        let entry = self.find_entry(&mut *raw, "..")?;
+        self.write_locked(&mut *raw, entry.pos, &(parent as u32).to_le_bytes())?;
+        Ok(())
+    }
+
+    fn is_empty_dir(&self) -> Result<bool> {
+        let mut raw = self.raw.lock();
+        Ok(self.dir_entries(&mut *raw)?
+            .iter()
+            .all(|entry| entry.ino == 0 || entry.name == "." || entry.name == ".."))
+    }
+
+    // 目录项被删除后减少链接数，目录被删除后它自己的 "." 也不再存在
+    fn drop_link(&self) -> Result<()> {
+        let mut raw = self.raw.lock();
+        let links = if Self::mode(&*raw) & S_IFMT == S_IFDIR {
+            0
+        } else {
+            Self::links(&*raw).checked_sub(1).ok_or(FsError::DeviceError)?
+        };
+        put_u16(&mut *raw, 26, links);
+        self.sync_raw(&*raw)
+    }
+
+    // self 是否就是 target ，或者 target 位于以 self 为根的子树中
+    fn contains(&self, target: &Ext2INode) -> Result<bool> {
+        if self.ino == target.ino {
+            return Ok(true);
+        }
+        let children: Vec<usize> = {
+            let mut raw = self.raw.lock();
+            if Self::mode(&*raw) & S_IFMT != S_IFDIR {
+                return Ok(false);
+            }
+            self.dir_entries(&mut *raw)?
+                .into_iter()
+                .filter(|entry| entry.ino != 0 && entry.name != "." && entry.name != "..")
+                .map(|entry| entry.ino)
+                .collect()
+        };
+        for ino in children {
+            if Shared::get_inode(&self.fs, ino)?.contains(target)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // 从 &Arc<dyn INode> 中取出同一文件系统中的 Ext2INode
+    fn same_fs(&self, other: &Arc<dyn INode>) -> Result<Arc<Ext2INode>> {
+        match other.downcast_ref::<Ext2INode>() {
+            Some(inode) if Arc::ptr_eq(&inode.fs, &self.fs) => {
+                // 已经确认了具体类型，可以把胖指针转换回 Arc<Ext2INode>
+                Ok(unsafe { Arc::from_raw(Arc::into_raw(other.clone()) as *const Ext2INode) })
+            }
+            _ => Err(FsError::NotSameFs),
+        }
+    }
+}
+
+impl Drop for Ext2INode {
+    fn drop(&mut self) {
+        {
+            let mut inodes = self.fs.inodes.lock();
+            // 这期间可能已经有人重新读入了这个 inode ，这时不能删掉新的缓存项
+            if inodes.get(&self.ino).map_or(false, |inode| inode.upgrade().is_none()) {
+                inodes.remove(&self.ino);
+            }
+        }
+        // 最后一个引用消失时，文件已经被删除并且不再被打开，归还它占用的块和 inode
+        let mut raw = self.raw.lock();
+        if Self::links(&*raw) != 0 || self.fs.read_only {
+            return;
+        }
+        let is_dir = Self::mode(&*raw) & S_IFMT == S_IFDIR;
+        let fast_symlink = self.is_fast_symlink(&*raw);
+        let freed = if fast_symlink { Ok(()) } else { self.free_blocks_from(&mut *raw, 0) };
+        put_u32(&mut *raw, 20, self.fs.time);
+        let index = self.ino - 1;
+        let freed = freed
+            .and_then(|_| self.sync_raw(&*raw))
+            .and_then(|_| self.fs.free_bit(Bitmap::Inode, index / self.fs.inodes_per_group,
+                                           index % self.fs.inodes_per_group, is_dir));
+        if freed.is_err() {
+            println!("ext2: failed to free inode {}", self.ino);
+        }
+    }
+}
+
+impl INode for Ext2INode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let mut raw = self.raw.lock();
+        match Self::mode(&*raw) & S_IFMT {
+            S_IFREG | S_IFLNK => {}
+            S_IFDIR => return Err(FsError::IsDir),
+            _ => return Err(FsError::NotSupported),
+        }
+        let size = Self::size(&*raw);
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min(size - offset);
+        self.read_locked(&mut *raw, offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        self.fs.check_writable()?;
+        let mut raw = self.raw.lock();
+        offset.checked_add(buf.len())
+            .filter(|&end| end <= u32::max_value() as usize)
+            .ok_or(FsError::InvalidParam)?;
+        match Self::mode(&*raw) & S_IFMT {
+            S_IFREG => {}
+            S_IFDIR => return Err(FsError::IsDir),
+            _ => return Err(FsError::NotSupported),
+        }
+        self.write_locked(&mut *raw, offset, buf)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        let raw = self.raw.lock();
+        let mode = Self::mode(&*raw);
+        let type_ = match mode & S_IFMT {
+            S_IFDIR => FileType::Dir,
+            S_IFLNK => FileType::SymLink,
+            S_IFCHR => FileType::CharDevice,
+            S_IFBLK => FileType::BlockDevice,
+            _ => FileType::File,
+        };
+        Ok(Metadata {
+            dev: 0,
+            inode: self.ino,
+            size: Self::size(&*raw),
+            blk_size: self.fs.block_size,
+            blocks: get_u32(&*raw, 28) as usize * 512 / self.fs.block_size,
+            type_,
+            mode: mode & 0o7777,
+            nlinks: Self::links(&*raw) as usize,
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn INode>> {
+        Ok(self.get_entry(name)?)
+    }
+
+    fn create(&self, name: &str, type_: FileType, mode: u32) -> Result<Arc<dyn INode>> {
+        check_name(name)?;
+        self.fs.check_writable()?;
+        let type_bits = match type_ {
+            FileType::File => S_IFREG,
+            FileType::Dir => S_IFDIR,
+            _ => return Err(FsError::NotSupported),
+        };
+        let _namespace = self.fs.namespace.lock();
+        match self.get_entry(name) {
+            Ok(_) => return Err(FsError::EntryExist),
+            Err(FsError::EntryNotFound) => {}
+            Err(err) => return Err(err),
+        }
+        let inode = Shared::new_inode(&self.fs, self.group(), type_bits | (mode & 0o7777) as u16)?;
+        let initialized = if type_ == FileType::Dir {
+            // 新目录中只有 "." 和 ".."
+            let mut raw = inode.raw.lock();
+            let block_size = self.fs.block_size;
+            inode.write_entry(&mut *raw, 0, inode.ino, 12, ".", FT_DIR)
+                .and_then(|_| inode.write_entry(&mut *raw, 12, self.ino, block_size - 12, "..", FT_DIR))
+                .and_then(|_| {
+                    put_u32(&mut *raw, 4, block_size as u32);
+                    inode.sync_raw(&*raw)
+                })
+        } else {
+            Ok(())
+        };
+        if let Err(err) = initialized.and_then(|_| self.insert_entry(name, &inode)) {
+            // 没有目录项引用这个 inode ，最后一个引用消失时归还它
+            let mut raw = inode.raw.lock();
+            put_u16(&mut *raw, 26, 0);
+            return Err(err);
+        }
+        Ok(inode)
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        check_name(name)?;
+        self.fs.check_writable()?;
+        let _namespace = self.fs.namespace.lock();
+        let inode = self.get_entry(name)?;
+        let is_dir = inode.is_dir();
+        if is_dir && !inode.is_empty_dir()? {
+            return Err(FsError::DirNotEmpty);
+        }
+        self.remove_entry(name, is_dir)?;
+        inode.drop_link()
+    }
+
+    fn link(&self, name: &str, other: &Arc<dyn INode>) -> Result<()> {
+        check_name(name)?;
+        self.fs.check_writable()?;
+        let other = self.same_fs(other)?;
+        if other.is_dir() {
+            return Err(FsError::IsDir);
+        }
+        let _namespace = self.fs.namespace.lock();
+        match self.get_entry(name) {
+            Ok(_) => return Err(FsError::EntryExist),
+            Err(FsError::EntryNotFound) => {}
+            Err(err) => return Err(err),
+        }
+        self.insert_entry(name, &other)?;
+        let mut raw = other.raw.lock();
+        let links = Self::links(&*raw);
+        put_u16(&mut *raw, 26, links + 1);
+        other.sync_raw(&*raw)
+    }
+
+    fn rename(&self, old_name: &str, target: &Arc<dyn INode>, new_name: &str) -> Result<()> {
+        check_name(old_name)?;
+        check_name(new_name)?;
+        self.fs.check_writable()?;
+        let target = self.same_fs(target)?;
+        let _namespace = self.fs.namespace.lock();
+        let inode = self.get_entry(old_name)?;
+        if !target.is_dir() {
+            return Err(FsError::NotDir);
+        }
+        let is_dir = inode.is_dir();
+        // 不能把目录移动到它自己的子树中
+        if is_dir && inode.contains(&target)? {
+            return Err(FsError::InvalidParam);
+        }
+        match target.get_entry(new_name) {
+            Ok(existing) => {
+                if existing.ino == inode.ino {
+                    // 新旧名字是同一个文件的两个链接，什么也不做
+                    return Ok(());
+                }
+                match (is_dir, existing.is_dir()) {
+                    (true, true) if !existing.is_empty_dir()? => return Err(FsError::DirNotEmpty),
+                    (true, false) => return Err(FsError::NotDir),
+                    (false, true) => return Err(FsError::IsDir),
+                    _ => {}
+                }
+                target.remove_entry(new_name, is_dir)?;
+                existing.drop_link()?;
+            }
+            Err(FsError::EntryNotFound) => {}
+            Err(err) => return Err(err),
+        }
+        target.insert_entry(new_name, &inode)?;
+        self.remove_entry(old_name, is_dir)?;
+        if is_dir && target.ino != self.ino {
+            inode.set_parent(target.ino)?;
+        }
+        Ok(())
+    }
+
+    fn readdir(&self, index: usize) -> Result<String> {
+        // "." 和 ".." 保存在磁盘上，总是目录中的前两项
+        self.dir_entries(&mut *self.raw.lock())?
+            .into_iter()
+            .filter(|entry| entry.ino != 0)
+            .nth(index)
+            .map(|entry| entry.name)
+            .ok_or(FsError::EntryNotFound)
+    }
+
+    fn resize(&self, len: usize) -> Result<()> {
+        self.fs.check_writable()?;
+        let mut raw = self.raw.lock();
+        match Self::mode(&*raw) & S_IFMT {
+            S_IFREG => self.resize_locked(&mut *raw, len),
+            S_IFDIR => Err(FsError::IsDir),
+            _ => Err(FsError::NotSupported),
+        }
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.fs.device.flush()
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+fn get_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn get_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+// 名字长度为 name_len 的目录项至少占用的字节数
+fn entry_size(name_len: usize) -> usize {
+    (8 + name_len + 3) & !3
+}
+
+fn file_type(mode: u16) -> u8 {
+    match mode & S_IFMT {
+        S_IFREG => FT_REG_FILE,
+        S_IFDIR => FT_DIR,
+        S_IFCHR => FT_CHRDEV,
+        S_IFBLK => FT_BLKDEV,
+        S_IFLNK => FT_SYMLINK,
+        _ => FT_UNKNOWN,
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\0') {
+        return Err(FsError::InvalidParam);
+    }
+    if name.len() > MAX_NAME_LEN {
+        return Err(FsError::InvalidParam);
+    }
+    Ok(())
+}
@@ -0,0 +1,955 @@
+//! FAT32：与宿主机交换数据用的磁盘文件系统，可以读写 mkfs.vfat 或者 mtools 生成的镜像
+//!
+//! 磁盘开头的保留扇区中是 BPB ，之后是若干份相同的 FAT 表，再之后是数据区。
+//! 数据区按簇分配，FAT 表中第 n 项记录了簇链中第 n 簇的下一簇。
+//! 目录的内容是 32 字节的目录项，每个文件有一个 8.3 格式的短目录项，
+//! 长文件名以 UTF-16 存放在短目录项之前的若干个长目录项中。
+//!
+//! FAT 没有 inode ，这里用短目录项在设备上的位置标识一个文件，根目录没有目录项，位置记为 0 。
+//! 因此不支持硬链接。新建、删除和重命名由一把文件系统级的锁串行执行。
+
+use super::block::BlockDevice;
+use super::vfs::{INode, FileSystem, FsError, FileType, Metadata, Result};
+use crate::sync::Mutex;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::char;
+
+const ENTRY_SIZE: usize = 32;
+const FAT_ENTRY_MASK: u32 = 0x0fff_ffff;
+const FAT_EOC: u32 = 0x0fff_ffff; // 簇链结束
+const FAT_MIN_EOC: u32 = 0x0fff_fff8; // 不小于它的值都表示簇链结束
+const FSINFO_FREE_COUNT: usize = 488;
+const FSINFO_UNKNOWN: u32 = 0xffff_ffff;
+
+const ATTR_READ_ONLY: u8 = 0x01;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_LONG_NAME: u8 = 0x0f;
+
+const DELETED: u8 = 0xe5;
+const LAST_LONG_ENTRY: u8 = 0x40;
+const LONG_NAME_CHARS: usize = 13; // 每个长目录项中的 UTF-16 字符数
+const MAX_NAME_LEN: usize = 255;
+// 短目录项中 NTRes 字段的标志，表示主名或者扩展名显示为小写
+const NTRES_LOWER_BASE: u8 = 0x08;
+const NTRES_LOWER_EXT: u8 = 0x10;
+// 没有实时时钟，时间戳统一写为 1980-01-01 00:00
+const DEFAULT_DATE: u16 = 0x0021;
+
+pub struct Fat32 {
+    root: Arc<FatINode>,
+}
+
+impl Fat32 {
+    /// 打开 device 上的 FAT32 ，不是 FAT32 时返回 DeviceError
+    pub fn open(device: Arc<dyn BlockDevice>) -> Result<Fat32> {
+        let mut bpb = [0u8; 512];
+        device.read_at(0, &mut bpb)?;
+        let bytes_per_sector = get_u16(&bpb, 11) as usize;
+        let sectors_per_cluster = bpb[13] as usize;
+        let reserved_sectors = get_u16(&bpb, 14) as usize;
+        let num_fats = bpb[16] as usize;
+        let root_entries = get_u16(&bpb, 17);
+        let total_sectors = match get_u16(&bpb, 19) {
+            0 => get_u32(&bpb, 32) as usize,
+            n => n as usize,
+        };
+        let fat_size_16 = get_u16(&bpb, 22);
+        let fat_size = get_u32(&bpb, 36) as usize;
+        let root_cluster = get_u32(&bpb, 44);
+        let fsinfo_sector = get_u16(&bpb, 48) as usize;
+        // FAT12 和 FAT16 的根目录区域固定，FAT32 的这两个字段必须为 0
+        if get_u16(&bpb, 510) != 0xaa55
+            || !bytes_per_sector.is_power_of_two()
+            || bytes_per_sector < 512
+            || !sectors_per_cluster.is_power_of_two()
+            || num_fats == 0
+            || root_entries != 0
+            || fat_size_16 != 0
+            || fat_size == 0
+        {
+            return Err(FsError::DeviceError);
+        }
+        let data_start = (reserved_sectors + num_fats * fat_size) * bytes_per_sector;
+        let cluster_size = sectors_per_cluster * bytes_per_sector;
+        if total_sectors * bytes_per_sector <= data_start {
+            return Err(FsError::DeviceError);
+        }
+        let cluster_count = ((total_sectors * bytes_per_sector - data_start) / cluster_size)
+            .min(fat_size * bytes_per_sector / 4 - 2);
+        if root_cluster < 2 || root_cluster as usize >= cluster_count + 2 {
+            return Err(FsError::DeviceError);
+        }
+        let shared = Arc::new(Shared {
+            device,
+            fat_start: reserved_sectors * bytes_per_sector,
+            fat_bytes: fat_size * bytes_per_sector,
+            num_fats,
+            data_start,
+            cluster_size,
+            cluster_count,
+            root_cluster,
+            fsinfo: match fsinfo_sector {
+                0 | 0xffff => None,
+                sector => Some(sector * bytes_per_sector),
+            },
+            fat: Mutex::new(FatState {
+                next_free: 2,
+                fsinfo_valid: true,
+            }),
+            inodes: Mutex::new(BTreeMap::new()),
+            namespace: Mutex::new(()),
+        });
+        let clusters = shared.read_chain(root_cluster)?;
+        let root = Arc::new(FatINode {
+            fs: shared.clone(),
+            inner: Mutex::new(Inner {
+                pos: 0,
+                attr: ATTR_DIRECTORY,
+                size: 0,
+                clusters,
+                removed: false,
+            }),
+        });
+        shared.inodes.lock().insert(0, Arc::downgrade(&root));
+        Ok(Fat32 { root })
+    }
+}
+
+impl FileSystem for Fat32 {
+    fn root_inode(&self) -> Arc<dyn INode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.root.fs.device.flush()
+    }
+}
+
+struct FatState {
+    next_free: u32, // 下一次从这里开始查找空闲簇
+    // FSInfo 中的空闲簇数是否还可信，第一次修改 FAT 时将它标记为未知
+    fsinfo_valid: bool,
+}
+
+// 同一个 FAT32 中所有 inode 共享的状态
+struct Shared {
+    device: Arc<dyn BlockDevice>,
+    fat_start: usize, // 第一份 FAT 的字节偏移
+    fat_bytes: usize, // 每份 FAT 的字节数
+    num_fats: usize,
+    data_start: usize, // 2 号簇的字节偏移
+    cluster_size: usize,
+    cluster_count: usize, // 簇号的范围是 [2, cluster_count + 2)
+    root_cluster: u32,
+    fsinfo: Option<usize>, // FSInfo 扇区的字节偏移
+    fat: Mutex<FatState>,
+    // 已经读入内存的 inode ，以目录项的位置为键，保证同一个文件在内存中只有一份
+    inodes: Mutex<BTreeMap<usize, Weak<FatINode>>>,
+    namespace: Mutex<()>, // 修改目录结构时持有
+}
+
+impl Shared {
+    fn cluster_pos(&self, cluster: u32) -> usize {
+        self.data_start + (cluster as usize - 2) * self.cluster_size
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && (cluster as usize) < self.cluster_count + 2
+    }
+
+    fn fat_get(&self, cluster: u32) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        self.device.read_at(self.fat_start + cluster as usize * 4, &mut buf)?;
+        Ok(get_u32(&buf, 0) & FAT_ENTRY_MASK)
+    }
+
+    // 修改所有 FAT 副本中的同一项，保留最高的 4 位
+    fn fat_set(&self, cluster: u32, value: u32) -> Result<()> {
+        for i in 0..self.num_fats {
+            let pos = self.fat_start + i * self.fat_bytes + cluster as usize * 4;
+            let mut buf = [0u8; 4];
+            self.device.read_at(pos, &mut buf)?;
+            let value = (get_u32(&buf, 0) & !FAT_ENTRY_MASK) | (value & FAT_ENTRY_MASK);
+            self.device.write_at(pos, &value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn invalidate_fsinfo(&self, state: &mut FatState) -> Result<()> {
+        if state.fsinfo_valid {
+            if let Some(pos) = self.fsinfo {
+                self.device.write_at(pos + FSINFO_FREE_COUNT, &FSINFO_UNKNOWN.to_le_bytes())?;
+            }
+            state.fsinfo_valid = false;
+        }
+        Ok(())
+    }
+
+    // 分配一个清零的簇，接在 prev 之后
+    fn alloc_cluster(&self, prev: Option<u32>) -> Result<u32> {
+        let cluster = {
+            let mut state = self.fat.lock();
+            self.invalidate_fsinfo(&mut state)?;
+            let end = self.cluster_count as u32 + 2;
+            // 从上次分配的位置开始按扇区读取 FAT 查找空闲项
+            let mut buf = [0u8; 512];
+            let mut buf_pos = None;
+            let mut cluster = state.next_free;
+            let mut found = None;
+            for _ in 0..self.cluster_count {
+                if cluster >= end {
+                    cluster = 2;
+                }
+                let pos = self.fat_start + cluster as usize * 4;
+                if buf_pos != Some(pos / 512) {
+                    self.device.read_at(pos / 512 * 512, &mut buf)?;
+                    buf_pos = Some(pos / 512);
+                }
+                if get_u32(&buf, pos % 512) & FAT_ENTRY_MASK == 0 {
+                    found = Some(cluster);
+                    break;
+                }
+                cluster += 1;
+            }
+            let cluster = found.ok_or(FsError::NoDeviceSpace)?;
+            self.fat_set(cluster, FAT_EOC)?;
+            state.next_free = cluster + 1;
+            cluster
+        };
+        let zeroed = self.device.write_at(self.cluster_pos(cluster), &vec![0u8; self.cluster_size]);
+        let linked = zeroed.and_then(|_| match prev {
+            Some(prev) => self.fat_set(prev, cluster),
+            None => Ok(()),
+        });
+        if let Err(err) = linked {
+            self.fat_set(cluster, 0)?;
+            return Err(err);
+        }
+        Ok(cluster)
+    }
+
+    fn free_cluster(&self, cluster: u32) -> Result<()> {
+        let mut state = self.fat.lock();
+        self.invalidate_fsinfo(&mut state)?;
+        self.fat_set(cluster, 0)?;
+        state.next_free = state.next_free.min(cluster);
+        Ok(())
+    }
+
+    // 从 first 开始的簇链，first 为 0 表示空文件
+    fn read_chain(&self, first: u32) -> Result<Vec<u32>> {
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+        while cluster != 0 && cluster < FAT_MIN_EOC {
+            // 簇号越界或者簇链成环都说明文件系统已经损坏
+            if !self.is_valid_cluster(cluster) || clusters.len() >= self.cluster_count {
+                return Err(FsError::DeviceError);
+            }
+            clusters.push(cluster);
+            cluster = self.fat_get(cluster)?;
+        }
+        Ok(clusters)
+    }
+
+    // 取得位于 pos 的目录项所描述的文件，不在内存中时根据目录项新建
+    fn get_inode(this: &Arc<Self>, pos: usize, raw: &[u8]) -> Result<Arc<FatINode>> {
+        let mut inodes = this.inodes.lock();
+        if let Some(inode) = inodes.get(&pos).and_then(Weak::upgrade) {
+            return Ok(inode);
+        }
+        let attr = raw[11];
+        let clusters = this.read_chain(first_cluster(raw))?;
+        let size = get_u32(raw, 28) as usize;
+        // 目录项中的大小超出簇链的容量说明文件系统已经损坏，目录的大小字段不使用
+        if attr & ATTR_DIRECTORY == 0 && size > clusters.len() * this.cluster_size {
+            return Err(FsError::DeviceError);
+        }
+        let inode = Arc::new(FatINode {
+            fs: this.clone(),
+            inner: Mutex::new(Inner {
+                pos,
+                attr,
+                size,
+                clusters,
+                removed: false,
+            }),
+        });
+        inodes.insert(pos, Arc::downgrade(&inode));
+        Ok(inode)
+    }
+}
+
+struct Inner {
+    pos: usize, // 短目录项在设备上的字节偏移，根目录为 0
+    attr: u8,
+    size: usize, // 文件的字节数，目录的大小由簇链决定
+    clusters: Vec<u32>,
+    removed: bool, // 目录项已经被删除，最后一个引用消失时归还簇链
+}
+
+impl Inner {
+    fn is_dir(&self) -> bool {
+        self.attr & ATTR_DIRECTORY != 0
+    }
+
+    fn first_cluster(&self) -> u32 {
+        self.clusters.first().cloned().unwrap_or(0)
+    }
+}
+
+// 从目录内容中解析出的一个文件
+struct DirEntry {
+    name: String,
+    slot: usize,      // 短目录项是目录中的第几项
+    long_slots: usize, // 之前的长目录项个数
+    raw: [u8; ENTRY_SIZE],
+}
+
+pub struct FatINode {
+    fs: Arc<Shared>,
+    inner: Mutex<Inner>,
+}
+
+impl FatINode {
+    fn is_dir(&self) -> bool {
+        self.inner.lock().is_dir()
+    }
+
+    fn pos(&self) -> usize {
+        self.inner.lock().pos
+    }
+
+    // 将首簇号和大小写回目录项
+    fn sync_entry(&self, inner: &Inner) -> Result<()> {
+        if inner.pos == 0 {
+            return Ok(());
+        }
+        let first = inner.first_cluster();
+        let device = &self.fs.device;
+        device.write_at(inner.pos + 20, &((first >> 16) as u16).to_le_bytes())?;
+        device.write_at(inner.pos + 26, &(first as u16).to_le_bytes())?;
+        let size = if inner.is_dir() { 0 } else { inner.size as u32 };
+        device.write_at(inner.pos + 28, &size.to_le_bytes())
+    }
+
+    // 将文件中 [offset, offset + len) 按簇拆开，对每一段调用 f(设备上的字节偏移, 已经处理的字节数, 这一段的长度)
+    fn for_each_cluster<F>(&self, inner: &Inner, offset: usize, len: usize, mut f: F) -> Result<()>
+    where
+        F: FnMut(usize, usize, usize) -> Result<()>,
+    {
+        let cluster_size = self.fs.cluster_size;
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let in_cluster = pos % cluster_size;
+            let n = (cluster_size - in_cluster).min(len - done);
+            let cluster = *inner.clusters.get(pos / cluster_size).ok_or(FsError::DeviceError)?;
+            f(self.fs.cluster_pos(cluster) + in_cluster, done, n)?;
+            done += n;
+        }
+        Ok(())
+    }
+
+    fn read_locked(&self, inner: &Inner, offset: usize, buf: &mut [u8]) -> Result<()> {
+        let device = &self.fs.device;
+        self.for_each_cluster(inner, offset, buf.len(), |pos, done, n| {
+            device.read_at(pos, &mut buf[done..done + n])
+        })
+    }
+
+    fn write_locked(&self, inner: &Inner, offset: usize, buf: &[u8]) -> Result<()> {
+        let device = &self.fs.device;
+        self.for_each_cluster(inner, offset, buf.len(), |pos, done, n| {
+            device.write_at(pos, &buf[done..done + n])
+        })
+    }
+
+    // 将簇链的长度调整为 count ，空间不足时保持原样
+    fn set_cluster_count(&self, inner: &mut Inner, count: usize) -> Result<()> {
+        let old_count = inner.clusters.len();
+        while inner.clusters.len() < count {
+            match self.fs.alloc_cluster(inner.clusters.last().cloned()) {
+                Ok(cluster) => inner.clusters.push(cluster),
+                Err(err) => {
+                    self.set_cluster_count(inner, old_count)?;
+                    return Err(err);
+                }
+            }
+        }
+        if inner.clusters.len() > count {
+            if count > 0 {
+                self.fs.fat_set(inner.clusters[count - 1], FAT_EOC)?;
+            }
+            for cluster in inner.clusters.drain(count..) {
+                self.fs.free_cluster(cluster)?;
+            }
+        }
+        Ok(())
+    }
+
+    // 修改文件大小，截断时把最后一簇中文件末尾之后的部分清零，之后再扩展时读到的就是 0
+    fn resize_locked(&self, inner: &mut Inner, len: usize) -> Result<()> {
+        if len > u32::max_value() as usize {
+            return Err(FsError::InvalidParam);
+        }
+        let cluster_size = self.fs.cluster_size;
+        self.set_cluster_count(inner, (len + cluster_size - 1) / cluster_size)?;
+        if len < inner.size && len % cluster_size != 0 {
+            let tail = len % cluster_size;
+            let pos = self.fs.cluster_pos(inner.clusters[len / cluster_size]) + tail;
+            self.fs.device.write_at(pos, &vec![0u8; cluster_size - tail])?;
+        }
+        inner.size = len;
+        self.sync_entry(inner)
+    }
+
+    // 读出整个目录的内容
+    fn dir_data(&self, inner: &Inner) -> Result<Vec<u8>> {
+        if !inner.is_dir() {
+            return Err(FsError::NotDir);
+        }
+        let mut data = vec![0u8; inner.clusters.len() * self.fs.cluster_size];
+        self.read_locked(inner, 0, &mut data)?;
+        Ok(data)
+    }
+
+    fn slot_pos(&self, inner: &Inner, slot: usize) -> usize {
+        let offset = slot * ENTRY_SIZE;
+        self.fs.cluster_pos(inner.clusters[offset / self.fs.cluster_size]) + offset % self.fs.cluster_size
+    }
+
+    fn find_entry(&self, inner: &Inner, name: &str) -> Result<Option<DirEntry>> {
+        Ok(parse_dir(&self.dir_data(inner)?)
+            .into_iter()
+            .find(|entry| entry.name.eq_ignore_ascii_case(name)))
+    }
+
+    fn get_entry(&self, name: &str) -> Result<Arc<FatINode>> {
+        let inner = self.inner.lock();
+        let entry = self.find_entry(&inner, name)?.ok_or(FsError::EntryNotFound)?;
+        Shared::get_inode(&self.fs, self.slot_pos(&inner, entry.slot), &entry.raw)
+    }
+
+    // 在目录中写入名为 name 的长目录项和短目录项 raw ，返回短目录项的位置
+    fn insert_entry(&self, name: &str, mut raw: [u8; ENTRY_SIZE]) -> Result<usize> {
+        let mut inner = self.inner.lock();
+        let data = self.dir_data(&inner)?;
+        let entries = parse_dir(&data);
+        let (short_name, ntres, long) = short_name(name, &entries);
+        raw[..11].copy_from_slice(&short_name);
+        raw[12] = ntres;
+        let mut slots = Vec::new();
+        if long {
+            slots = long_entries(name, checksum(&short_name));
+        }
+        slots.push(raw);
+
+        // 找到足够多的连续空闲项，不够时扩展目录
+        let total = data.len() / ENTRY_SIZE;
+        let mut start = total;
+        let mut run = 0;
+        for slot in 0..total {
+            match data[slot * ENTRY_SIZE] {
+                0 | DELETED => {
+                    if run == 0 {
+                        start = slot;
+                    }
+                    run += 1;
+                    if run == slots.len() {
+                        break;
+                    }
+                }
+                _ => run = 0,
+            }
+        }
+        if run < slots.len() {
+            if run == 0 {
+                start = total;
+            }
+            let entries_per_cluster = self.fs.cluster_size / ENTRY_SIZE;
+            let count = (start + slots.len() + entries_per_cluster - 1) / entries_per_cluster;
+            self.set_cluster_count(&mut inner, count)?;
+        }
+        for (i, slot) in slots.iter().enumerate() {
+            self.fs.device.write_at(self.slot_pos(&inner, start + i), slot)?;
+        }
+        Ok(self.slot_pos(&inner, start + slots.len() - 1))
+    }
+
+    // 删除名为 name 的目录项，返回它的短目录项
+    fn remove_entry(&self, name: &str) -> Result<[u8; ENTRY_SIZE]> {
+        let inner = self.inner.lock();
+        let entry = self.find_entry(&inner, name)?.ok_or(FsError::EntryNotFound)?;
+        for slot in entry.slot - entry.long_slots..=entry.slot {
+            self.fs.device.write_at(self.slot_pos(&inner, slot), &[DELETED])?;
+        }
+        Ok(entry.raw)
+    }
+
+    fn is_empty_dir(&self) -> Result<bool> {
+        let inner = self.inner.lock();
+        Ok(parse_dir(&self.dir_data(&inner)?).is_empty())
+    }
+
+    // 目录项被删除，最后一个引用消失时归还簇链
+    fn mark_removed(&self) {
+        let mut inner = self.inner.lock();
+        inner.removed = true;
+        inner.pos = usize::max_value();
+    }
+
+    // self 是否就是 target ，或者 target 位于以 self 为根的子树中
+    fn contains(&self, target: &FatINode) -> Result<bool> {
+        if core::ptr::eq(self, target) {
+            return Ok(true);
+        }
+        let children: Vec<(usize, [u8; ENTRY_SIZE])> = {
+            let inner = self.inner.lock();
+            if !inner.is_dir() {
+                return Ok(false);
+            }
+            parse_dir(&self.dir_data(&inner)?)
+                .into_iter()
+                .filter(|entry| entry.raw[11] & ATTR_DIRECTORY != 0)
+                .map(|entry| (self.slot_pos(&inner, entry.slot), entry.raw))
+                .collect()
+        };
+        for (pos, raw) in children {
+            if Shared::get_inode(&self.fs, pos, &raw)?.contains(target)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // 从 &Arc<dyn INode> 中取出同一文件系统中的 FatINode
+    fn same_fs(&self, other: &Arc<dyn INode>) -> Result<Arc<FatINode>> {
+        match other.downcast_ref::<FatINode>() {
+            Some(inode) if Arc::ptr_eq(&inode.fs, &self.fs) => {
+                // 已经确认了具体类型，可以把胖指针转换回 Arc<FatINode>
+                Ok(unsafe { Arc::from_raw(Arc::into_raw(other.clone()) as *const FatINode) })
+            }
+            _ => Err(FsError::NotSameFs),
+        }
+    }
+}
+
+impl Drop for FatINode {
+    fn drop(&mut self) {
+        let mut inner = self.inner.lock();
+        {
+            let mut inodes = self.fs.inodes.lock();
+            // 这期间可能已经有人重新读入了这个文件，这时不能删掉新的缓存项
+            if inodes.get(&inner.pos).map_or(false, |inode| inode.upgrade().is_none()) {
+                inodes.remove(&inner.pos);
+            }
+        }
+        // 最后一个引用消失时，文件已经被删除并且不再被打开，归还它占用的簇
+        if inner.removed && self.set_cluster_count(&mut inner, 0).is_err() {
+            println!("fat32: failed to free clusters");
+        }
+    }
+}
+
+impl INode for FatINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let inner = self.inner.lock();
+        if inner.is_dir() {
+            return Err(FsError::IsDir);
+        }
+        if offset >= inner.size {
+            return Ok(0);
+        }
+        let len = buf.len().min(inner.size - offset);
+        self.read_locked(&inner, offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let mut inner = self.inner.lock();
+        let end = offset.checked_add(buf.len()).ok_or(FsError::InvalidParam)?;
+        if inner.is_dir() {
+            return Err(FsError::IsDir);
+        }
+        if end > inner.size {
+            self.resize_locked(&mut inner, end)?;
+        }
+        self.write_locked(&inner, offset, buf)?;
+        Ok(buf.len())
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        let inner = self.inner.lock();
+        let (type_, size, mode) = if inner.is_dir() {
+            (FileType::Dir, inner.clusters.len() * self.fs.cluster_size, 0o755)
+        } else {
+            (FileType::File, inner.size, 0o644)
+        };
+        let blocks = inner.clusters.len() * self.fs.cluster_size / 512;
+        Ok(Metadata {
+            dev: 0,
+            inode: if inner.pos == 0 { 1 } else { inner.pos / ENTRY_SIZE },
+            size,
+            blk_size: self.fs.cluster_size,
+            blocks,
+            type_,
+            mode: if inner.attr & ATTR_READ_ONLY != 0 { mode & 0o555 } else { mode },
+            nlinks: if inner.is_dir() { 2 } else { 1 },
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn INode>> {
+        Ok(self.get_entry(name)?)
+    }
+
+    fn create(&self, name: &str, type_: FileType, mode: u32) -> Result<Arc<dyn INode>> {
+        check_name(name)?;
+        let _namespace = self.fs.namespace.lock();
+        match self.get_entry(name) {
+            Ok(_) => return Err(FsError::EntryExist),
+            Err(FsError::EntryNotFound) => {}
+            Err(err) => return Err(err),
+        }
+        let mut raw = [0u8; ENTRY_SIZE];
+        raw[11] = match type_ {
+            FileType::File => ATTR_ARCHIVE,
+            FileType::Dir => ATTR_DIRECTORY,
+            _ => return Err(FsError::NotSupported),
+        };
+        if mode & 0o222 == 0 {
+            raw[11] |= ATTR_READ_ONLY;
+        }
+        for &date in [16, 18, 24].iter() {
+            raw[date..date + 2].copy_from_slice(&DEFAULT_DATE.to_le_bytes());
+        }
+        if type_ == FileType::Dir {
+            // 新目录中只有 "." 和 ".."
+            let cluster = self.fs.alloc_cluster(None)?;
+            set_first_cluster(&mut raw, cluster);
+            let mut dot = raw;
+            dot[..11].copy_from_slice(b".          ");
+            let mut dotdot = raw;
+            dotdot[..11].copy_from_slice(b"..         ");
+            let parent = self.inner.lock();
+            set_first_cluster(&mut dotdot, if parent.pos == 0 { 0 } else { parent.first_cluster() });
+            drop(parent);
+            let pos = self.fs.cluster_pos(cluster);
+            let written = self.fs.device.write_at(pos, &dot)
+                .and_then(|_| self.fs.device.write_at(pos + ENTRY_SIZE, &dotdot));
+            if let Err(err) = written {
+                self.fs.free_cluster(cluster)?;
+                return Err(err);
+            }
+        }
+        let pos = match self.insert_entry(name, raw) {
+            Ok(pos) => pos,
+            Err(err) => {
+                if type_ == FileType::Dir {
+                    self.fs.free_cluster(first_cluster(&raw))?;
+                }
+                return Err(err);
+            }
+        };
+        let mut raw = [0u8; ENTRY_SIZE];
+        self.fs.device.read_at(pos, &mut raw)?;
+        Ok(Shared::get_inode(&self.fs, pos, &raw)?)
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        check_name(name)?;
+        let _namespace = self.fs.namespace.lock();
+        let inode = self.get_entry(name)?;
+        if inode.is_dir() && !inode.is_empty_dir()? {
+            return Err(FsError::DirNotEmpty);
+        }
+        let pos = inode.pos();
+        self.remove_entry(name)?;
+        inode.mark_removed();
+        self.fs.inodes.lock().remove(&pos);
+        Ok(())
+    }
+
+    fn link(&self, _name: &str, _other: &Arc<dyn INode>) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+
+    fn rename(&self, old_name: &str, target: &Arc<dyn INode>, new_name: &str) -> Result<()> {
+        check_name(old_name)?;
+        check_name(new_name)?;
+        let target = self.same_fs(target)?;
+        let _namespace = self.fs.namespace.lock();
+        let inode = self.get_entry(old_name)?;
+        if !target.is_dir() {
+            return Err(FsError::NotDir);
+        }
+        let is_dir = inode.is_dir();
+        // 不能把目录移动到它自己的子树中
+        if is_dir && inode.contains(&target)? {
+            return Err(FsError::InvalidParam);
+        }
+        match target.get_entry(new_name) {
+            Ok(existing) => {
+                if Arc::ptr_eq(&existing, &inode) {
+                    // 只改变了大小写，或者新旧名字相同，什么也不做
+                    return Ok(());
+                }
+                match (is_dir, existing.is_dir()) {
+                    (true, true) if !existing.is_empty_dir()? => return Err(FsError::DirNotEmpty),
+                    (true, false) => return Err(FsError::NotDir),
+                    (false, true) => return Err(FsError::IsDir),
+                    _ => {}
+                }
+                let pos = existing.pos();
+                target.remove_entry(new_name)?;
+                existing.mark_removed();
+                self.fs.inodes.lock().remove(&pos);
+            }
+            Err(FsError::EntryNotFound) => {}
+            Err(err) => return Err(err),
+        }
+        // 先在新目录中写入目录项，再删除旧的，持有 inode 的锁使首簇号和大小不会在这期间改变
+        let mut inner = inode.inner.lock();
+        let raw = {
+            let dir = self.inner.lock();
+            self.find_entry(&dir, old_name)?.ok_or(FsError::EntryNotFound)?.raw
+        };
+        let new_pos = target.insert_entry(new_name, raw)?;
+        self.remove_entry(old_name)?;
+        {
+            let mut inodes = self.fs.inodes.lock();
+            inodes.remove(&inner.pos);
+            inodes.insert(new_pos, Arc::downgrade(&inode));
+        }
+        inner.pos = new_pos;
+        if is_dir {
+            // 目录移动之后，".." 指向新的父目录
+            let parent = target.inner.lock();
+            let mut dotdot = [0u8; ENTRY_SIZE];
+            let dotdot_pos = self.fs.cluster_pos(inner.first_cluster()) + ENTRY_SIZE;
+            self.fs.device.read_at(dotdot_pos, &mut dotdot)?;
+            set_first_cluster(&mut dotdot, if parent.pos == 0 { 0 } else { parent.first_cluster() });
+            self.fs.device.write_at(dotdot_pos, &dotdot)?;
+        }
+        Ok(())
+    }
+
+    fn readdir(&self, index: usize) -> Result<String> {
+        let inner = self.inner.lock();
+        let data = self.dir_data(&inner)?;
+        match index {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            index => parse_dir(&data)
+                .into_iter()
+                .nth(index - 2)
+                .map(|entry| entry.name)
+                .ok_or(FsError::EntryNotFound),
+        }
+    }
+
+    fn resize(&self, len: usize) -> Result<()> {
+        let mut inner = self.inner.lock();
+        if inner.is_dir() {
+            return Err(FsError::IsDir);
+        }
+        self.resize_locked(&mut inner, len)
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.fs.device.flush()
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+fn get_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn get_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+fn first_cluster(raw: &[u8]) -> u32 {
+    (get_u16(raw, 20) as u32) << 16 | get_u16(raw, 26) as u32
+}
+
+fn set_first_cluster(raw: &mut [u8], cluster: u32) {
+    raw[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+    raw[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.encode_utf16().count() > MAX_NAME_LEN {
+        return Err(FsError::InvalidParam);
+    }
+    // FAT 的文件名中不能出现这些字符
+    if name.chars().any(|c| (c as u32) < 0x20 || "\"*/:<>?\\|".contains(c)) {
+        return Err(FsError::InvalidParam);
+    }
+    Ok(())
+}
+
+// 解析目录内容，跳过已删除的项、卷标以及 "." 和 ".."
+fn parse_dir(data: &[u8]) -> Vec<DirEntry> {
+    let mut entries = Vec::new();
+    // 正在拼接的长文件名：(校验和, 下一个期望的序号, 已经读到的长目录项个数, UTF-16 字符)
+    let mut long: Option<(u8, u8, usize, Vec<u16>)> = None;
+    for (slot, raw) in data.chunks(ENTRY_SIZE).enumerate() {
+        match raw[0] {
+            0 => break,
+            DELETED => {
+                long = None;
+                continue;
+            }
+            _ => {}
+        }
+        if raw[11] & 0x3f == ATTR_LONG_NAME {
+            // 长目录项按序号从大到小排列，序号最大的一项带有 LAST_LONG_ENTRY 标志
+            let ord = raw[0] & !LAST_LONG_ENTRY;
+            let mut chars = Vec::with_capacity(LONG_NAME_CHARS);
+            for &offset in [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30].iter() {
+                chars.push(get_u16(raw, offset));
+            }
+            long = match long.take() {
+                _ if raw[0] & LAST_LONG_ENTRY != 0 && ord > 0 => Some((raw[13], ord - 1, 1, chars)),
+                Some((sum, expect, count, mut name)) if ord == expect && ord > 0 && sum == raw[13] => {
+                    chars.extend_from_slice(&name);
+                    name = chars;
+                    Some((sum, ord - 1, count + 1, name))
+                }
+                _ => None,
+            };
+            continue;
+        }
+        let long_name = long.take();
+        if raw[11] & ATTR_VOLUME_ID != 0 || raw[0] == b'.' {
+            continue;
+        }
+        let mut short = [0u8; ENTRY_SIZE];
+        short.copy_from_slice(raw);
+        let (name, long_slots) = match long_name {
+            Some((sum, 0, count, chars)) if sum == checksum(&raw[..11]) => {
+                let len = chars.iter().position(|&c| c == 0).unwrap_or(chars.len());
+                let name: String = char::decode_utf16(chars[..len].iter().cloned())
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect();
+                (name, count)
+            }
+            _ => (short_display_name(raw), 0),
+        };
+        entries.push(DirEntry { name, slot, long_slots, raw: short });
+    }
+    entries
+}
+
+// 短目录项中的名字，按 NTRes 的标志转换为小写
+fn short_display_name(raw: &[u8]) -> String {
+    let part = |bytes: &[u8], lower: bool| -> String {
+        bytes.iter()
+            .map(|&b| if b == 0x05 { DELETED } else { b })
+            .map(|b| (if lower { b.to_ascii_lowercase() } else { b }) as char)
+            .collect::<String>()
+            .trim_end()
+            .into()
+    };
+    let base = part(&raw[..8], raw[12] & NTRES_LOWER_BASE != 0);
+    let ext = part(&raw[8..11], raw[12] & NTRES_LOWER_EXT != 0);
+    if ext.is_empty() {
+        base
+    } else {
+        base + "." + &ext
+    }
+}
+
+fn is_short_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "$%'-_@~`!(){}^#&".contains(c)
+}
+
+// 为 name 生成短名字，返回 (短名字, NTRes, 是否需要长目录项)
+fn short_name(name: &str, entries: &[DirEntry]) -> ([u8; 11], u8, bool) {
+    let mut short = [b' '; 11];
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) if dot > 0 => (&name[..dot], &name[dot + 1..]),
+        _ => (name, ""),
+    };
+    // 本身就符合 8.3 格式，并且主名和扩展名分别全为大写或者全为小写，可以只用短目录项
+    let single_case = |s: &str| !s.chars().any(|c| c.is_ascii_lowercase()) || !s.chars().any(|c| c.is_ascii_uppercase());
+    if !base.is_empty() && base.len() <= 8 && ext.len() <= 3
+        && base.chars().chain(ext.chars()).all(is_short_char)
+        && single_case(base) && single_case(ext)
+    {
+        short[..base.len()].copy_from_slice(base.to_ascii_uppercase().as_bytes());
+        short[8..8 + ext.len()].copy_from_slice(ext.to_ascii_uppercase().as_bytes());
+        let mut ntres = 0;
+        if base.chars().any(|c| c.is_ascii_lowercase()) {
+            ntres |= NTRES_LOWER_BASE;
+        }
+        if ext.chars().any(|c| c.is_ascii_lowercase()) {
+            ntres |= NTRES_LOWER_EXT;
+        }
+        return (short, ntres, false);
+    }
+    // 否则生成 "主名~n.扩展名" 形式的短名字，不能与目录中已有的短名字重复
+    let convert = |s: &str, max: usize| -> Vec<u8> {
+        s.chars()
+            .filter(|&c| c != ' ' && c != '.')
+            .map(|c| if is_short_char(c) { c.to_ascii_uppercase() as u8 } else { b'_' })
+            .take(max)
+            .collect()
+    };
+    let ext = convert(ext, 3);
+    short[8..8 + ext.len()].copy_from_slice(&ext);
+    let base = convert(base, 8);
+    for n in 1.. {
+        let suffix = format!("~{}", n);
+        let keep = base.len().min(8 - suffix.len());
+        short[..8].copy_from_slice(b"        ");
+        short[..keep].copy_from_slice(&base[..keep]);
+        short[keep..keep + suffix.len()].copy_from_slice(suffix.as_bytes());
+        if !entries.iter().any(|entry| entry.raw[..11] == short[..]) {
+            break;
+        }
+    }
+    (short, 0, true)
+}
+
+// 长目录项中保存的短名字校验和
+fn checksum(short: &[u8]) -> u8 {
+    short.iter().fold(0u8, |sum, &c| (sum >> 1 | sum << 7).wrapping_add(c))
+}
+
+// 保存 name 的长目录项，按在磁盘上的顺序排列
+fn long_entries(name: &str, sum: u8) -> Vec<[u8; ENTRY_SIZE]> {
+    let mut chars: Vec<u16> = name.encode_utf16().collect();
+    let count = (chars.len() + LONG_NAME_CHARS - 1) / LONG_NAME_CHARS;
+    // 名字之后是一个 0 ，剩余的位置填充 0xffff
+    if chars.len() < count * LONG_NAME_CHARS {
+        chars.push(0);
+    }
+    chars.resize(count * LONG_NAME_CHARS, 0xffff);
+    (0..count)
+        .rev()
+        .map(|i| {
+            let mut raw = [0u8; ENTRY_SIZE];
+            raw[0] = (i + 1) as u8 | if i == count - 1 { LAST_LONG_ENTRY } else { 0 };
+            raw[11] = ATTR_LONG_NAME;
+            raw[13] = sum;
+            let offsets = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+            for (j, &offset) in offsets.iter().enumerate() {
+                raw[offset..offset + 2].copy_from_slice(&chars[i * LONG_NAME_CHARS + j].to_le_bytes());
+            }
+            raw
+        })
+        .collect()
+}
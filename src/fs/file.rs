@@ -0,0 +1,115 @@
+//! 打开的文件
+//!
+//! 文件描述符表中保存的是 `Arc<File>` ，dup 和 fork 得到的描述符共享同一个 File ，
+//! 因此也共享读写位置。
+
+use super::{INode, Metadata, Result, FsError};
+use crate::sync::Mutex;
+use alloc::string::String;
+use alloc::sync::Arc;
+
+#[derive(Debug, Clone, Copy)]
+pub enum SeekFrom {
+    Start(usize),
+    End(isize),
+    Current(isize),
+}
+
+pub struct File {
+    inode: Arc<dyn INode>,
+    path: String, // 打开时的绝对路径
+    readable: bool,
+    writable: bool,
+    append: bool, // 每次写入之前先移动到文件末尾
+    // 读写控制台等设备时可能睡眠，因此使用睡眠锁
+    offset: Mutex<usize>,
+}
+
+impl File {
+    pub fn new(inode: Arc<dyn INode>, path: String, readable: bool, writable: bool, append: bool) -> File {
+        File {
+            inode,
+            path,
+            readable,
+            writable,
+            append,
+            offset: Mutex::new(0),
+        }
+    }
+
+    pub fn inode(&self) -> Arc<dyn INode> {
+        self.inode.clone()
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn readable(&self) -> bool {
+        self.readable
+    }
+
+    pub fn writable(&self) -> bool {
+        self.writable
+    }
+
+    pub fn metadata(&self) -> Result<Metadata> {
+        self.inode.metadata()
+    }
+
+    // 从当前位置读取，读写位置随之后移
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        let mut offset = self.offset.lock();
+        let len = self.inode.read_at(*offset, buf)?;
+        *offset += len;
+        Ok(len)
+    }
+
+    // 从当前位置写入，读写位置随之后移
+    pub fn write(&self, buf: &[u8]) -> Result<usize> {
+        let mut offset = self.offset.lock();
+        if self.append {
+            *offset = self.inode.metadata()?.size;
+        }
+        let len = self.inode.write_at(*offset, buf)?;
+        *offset += len;
+        Ok(len)
+    }
+
+    // 目录的读写位置是下一个要读取的目录项的序号。从这里开始依次把 (序号, 名字) 交给 f ，
+    // 直到目录末尾或者 f 返回 false ，使 f 返回 false 的那一项留到下次读取
+    pub fn read_entries<F: FnMut(usize, &str) -> bool>(&self, mut f: F) -> Result<()> {
+        let mut offset = self.offset.lock();
+        loop {
+            let name = match self.inode.readdir(*offset) {
+                Ok(name) => name,
+                Err(FsError::EntryNotFound) => return Ok(()),
+                Err(err) => return Err(err),
+            };
+            if !f(*offset, &name) {
+                return Ok(());
+            }
+            *offset += 1;
+        }
+    }
+
+    // 移动读写位置，返回新的位置
+    pub fn seek(&self, pos: SeekFrom) -> Result<usize> {
+        let mut offset = self.offset.lock();
+        let new_offset = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => offset_by(self.inode.metadata()?.size, delta),
+            SeekFrom::Current(delta) => offset_by(*offset, delta),
+        };
+        *offset = new_offset.ok_or(FsError::InvalidParam)?;
+        Ok(*offset)
+    }
+}
+
+fn offset_by(base: usize, delta: isize) -> Option<usize> {
+    if delta >= 0 {
+        base.checked_add(delta as usize)
+    } else {
+        base.checked_sub(delta.wrapping_neg() as usize)
+    }
+}
@@ -0,0 +1,255 @@
+//! initramfs：链接进内核镜像的 cpio (newc) 归档，启动时解析并以只读文件系统的形式挂载到 /
+//!
+//! newc 格式中每个文件由 110 字节的头部、文件名和文件内容依次组成，
+//! 头部是 "070701" 加上 13 个 8 位十六进制数，文件名和文件内容都补齐到 4 字节对齐，
+//! 名为 "TRAILER!!!" 的项标志着归档的结束。
+//! 文件内容直接引用内核镜像中的数据，不需要复制。
+
+use super::vfs::{INode, FileSystem, FsError, FileType, Metadata, Result};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::any::Any;
+use core::str;
+
+const MAGIC: &[u8] = b"070701";
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+// mode 中表示文件类型的位
+const S_IFMT: usize = 0o170000;
+const S_IFDIR: usize = 0o040000;
+const S_IFREG: usize = 0o100000;
+
+/// 链接进内核镜像的归档
+pub fn image() -> &'static [u8] {
+    unsafe {
+        core::slice::from_raw_parts(
+            _initramfs_start as *const u8,
+            _initramfs_end as usize - _initramfs_start as usize,
+        )
+    }
+}
+
+pub struct Initramfs {
+    root: Arc<CpioINode>,
+}
+
+impl Initramfs {
+    /// 解析 data 中的 cpio 归档，归档损坏时返回 None
+    pub fn new(data: &'static [u8]) -> Option<Initramfs> {
+        let mut root = Builder::dir(0o755);
+        let mut pos = 0;
+        loop {
+            let header = data.get(pos..pos + HEADER_SIZE)?;
+            if &header[..6] != MAGIC {
+                return None;
+            }
+            let ino = field(header, 0)?;
+            let mode = field(header, 1)?;
+            let file_size = field(header, 6)?;
+            let name_size = field(header, 11)?;
+            if name_size == 0 {
+                return None;
+            }
+            // 文件名包括结尾的 '\0'
+            let name = data.get(pos + HEADER_SIZE..pos + HEADER_SIZE + name_size - 1)?;
+            let name = str::from_utf8(name).ok()?;
+            if name == TRAILER {
+                break;
+            }
+            let data_start = align4(pos + HEADER_SIZE + name_size);
+            let content = data.get(data_start..data_start + file_size)?;
+            pos = align4(data_start + file_size);
+
+            let node = match mode & S_IFMT {
+                S_IFDIR => Builder::dir(mode & 0o7777),
+                S_IFREG => Builder::file(mode & 0o7777, content),
+                // 设备文件和符号链接等由其他文件系统提供
+                _ => continue,
+            };
+            root.insert(name, Builder { ino, ..node });
+        }
+        let mut next_ino = 1;
+        Some(Initramfs { root: root.build(&mut next_ino) })
+    }
+}
+
+impl FileSystem for Initramfs {
+    fn root_inode(&self) -> Arc<dyn INode> {
+        self.root.clone()
+    }
+}
+
+// 解析归档时使用的可变树，父目录可能出现在子项之后，也可能根本不出现在归档中
+struct Builder {
+    ino: usize,
+    type_: FileType,
+    mode: usize,
+    data: &'static [u8],
+    children: BTreeMap<String, Builder>,
+}
+
+impl Builder {
+    fn dir(mode: usize) -> Builder {
+        Builder { ino: 0, type_: FileType::Dir, mode, data: &[], children: BTreeMap::new() }
+    }
+
+    fn file(mode: usize, data: &'static [u8]) -> Builder {
+        Builder { ino: 0, type_: FileType::File, mode, data, children: BTreeMap::new() }
+    }
+
+    // 将 node 放到相对路径 path 处，沿途缺少的目录自动创建
+    fn insert(&mut self, path: &str, node: Builder) {
+        let mut parts = path.split('/').filter(|part| !part.is_empty() && *part != ".");
+        let mut name = match parts.next() {
+            Some(name) => name,
+            // 归档中的 "." 就是根目录本身
+            None => {
+                self.mode = node.mode;
+                return;
+            }
+        };
+        let mut dir = self;
+        for next in parts {
+            dir = dir.children.entry(String::from(name)).or_insert_with(|| Builder::dir(0o755));
+            name = next;
+        }
+        let merge = match dir.children.get(name) {
+            Some(old) => old.type_ == FileType::Dir && node.type_ == FileType::Dir,
+            None => false,
+        };
+        if merge {
+            // 先被自动创建的目录，保留已经加入的子项
+            let old = dir.children.get_mut(name).unwrap();
+            old.ino = node.ino;
+            old.mode = node.mode;
+        } else {
+            dir.children.insert(String::from(name), node);
+        }
+    }
+
+    // 归档中的 inode 编号可能重复或者缺失，这里重新按顺序编号
+    fn build(self, next_ino: &mut usize) -> Arc<CpioINode> {
+        let ino = *next_ino;
+        *next_ino += 1;
+        let children = self.children
+            .into_iter()
+            .map(|(name, child)| (name, child.build(next_ino)))
+            .collect();
+        Arc::new(CpioINode {
+            ino,
+            type_: self.type_,
+            mode: self.mode as u16,
+            data: self.data,
+            children,
+        })
+    }
+}
+
+pub struct CpioINode {
+    ino: usize,
+    type_: FileType,
+    mode: u16,
+    data: &'static [u8],
+    children: BTreeMap<String, Arc<CpioINode>>,
+}
+
+impl INode for CpioINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        if self.type_ == FileType::Dir {
+            return Err(FsError::IsDir);
+        }
+        if offset >= self.data.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(self.data.len() - offset);
+        buf[..len].copy_from_slice(&self.data[offset..offset + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        Ok(Metadata {
+            dev: 0,
+            inode: self.ino,
+            size: self.data.len(),
+            blk_size: 0,
+            blocks: 0,
+            type_: self.type_,
+            mode: self.mode,
+            nlinks: if self.type_ == FileType::Dir { 2 } else { 1 },
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn INode>> {
+        if self.type_ != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        match self.children.get(name) {
+            Some(child) => Ok(child.clone()),
+            None => Err(FsError::EntryNotFound),
+        }
+    }
+
+    fn create(&self, _name: &str, _type: FileType, _mode: u32) -> Result<Arc<dyn INode>> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn readdir(&self, index: usize) -> Result<String> {
+        if self.type_ != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        match index {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            index => self.children.keys().nth(index - 2).cloned().ok_or(FsError::EntryNotFound),
+        }
+    }
+
+    fn resize(&self, _len: usize) -> Result<()> {
+        Err(FsError::ReadOnly)
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+// 头部中 magic 之后的第 index 个字段
+fn field(header: &[u8], index: usize) -> Option<usize> {
+    let start = MAGIC.len() + index * 8;
+    let text = str::from_utf8(&header[start..start + 8]).ok()?;
+    usize::from_str_radix(text, 16).ok()
+}
+
+fn align4(x: usize) -> usize {
+    (x + 3) & !3
+}
+
+// 将 Makefile 中 initramfs 指定的 cpio 归档嵌入到内核的数据段中
+global_asm!(concat!(
+    r#"
+    .section .data
+    .global _initramfs_start
+    .global _initramfs_end
+    .align 2
+_initramfs_start:
+    .incbin ""#,
+    env!("initramfs"),
+    r#""
+_initramfs_end:
+"#
+));
+
+extern "C" {
+    fn _initramfs_start();
+    fn _initramfs_end();
+}
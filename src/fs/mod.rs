@@ -0,0 +1,219 @@
+//! 文件系统
+//!
+//! 各个文件系统通过 `mount` 挂载到一个目录上。解析路径时先把它规范化为绝对路径，
+//! 选出挂载点与之匹配得最长的文件系统，再从这个文件系统的根目录开始逐级 lookup 。
+//! 路径中的 "." 和 ".." 在规范化时按字面处理，因此 ".." 可以越过挂载点回到上一层文件系统。
+//! 遇到符号链接时，用链接的内容替换路径中到它为止的部分，再从头解析新的路径。
+
+pub mod vfs;
+pub mod file;
+pub mod devfs;
+pub mod initramfs;
+pub mod tmpfs;
+pub mod block;
+pub mod cache;
+pub mod sfs;
+pub mod fat32;
+pub mod ext2;
+
+pub use self::vfs::{INode, FileSystem, FsError, FileType, Metadata, Result};
+pub use self::file::{File, SeekFrom};
+pub use self::block::BlockDevice;
+
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::consts::{TMPFS_SIZE, BLOCK_CACHE_SIZE};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+// 解析一个路径时最多跟随的符号链接数
+const MAX_SYMLINKS: usize = 8;
+
+struct MountPoint {
+    path: Vec<String>, // 挂载点规范化之后的各级目录名，根目录为空
+    fs: Arc<dyn FileSystem>,
+}
+
+lazy_static! {
+    static ref MOUNTS: Mutex<Vec<MountPoint>> = Mutex::new(Vec::new());
+}
+
+pub fn init() {
+    match initramfs::Initramfs::new(initramfs::image()) {
+        Some(initramfs) => mount("/", Arc::new(initramfs)).expect("failed to mount initramfs"),
+        None => println!("fs: invalid initramfs, nothing is mounted at /"),
+    }
+    mount("/dev", Arc::new(devfs::DevFs::new())).expect("failed to mount devfs");
+    mount("/tmp", Arc::new(tmpfs::TmpFs::new(TMPFS_SIZE))).expect("failed to mount tmpfs");
+    let (base, size) = block::disk_image();
+    let ram = Arc::new(unsafe { block::RamDisk::new(base, size) });
+    let disk = cache::BlockCache::new("ram0", ram, BLOCK_CACHE_SIZE);
+    devfs::register("cachestat", Arc::new(cache::CacheStatFile));
+    match sfs::SimpleFileSystem::open(disk) {
+        Ok(sfs) => mount("/disk", Arc::new(sfs)).expect("failed to mount sfs"),
+        Err(err) => println!("fs: invalid disk image: {:?}, nothing is mounted at /disk", err),
+    }
+    // 探测到的块设备依次挂载到 /mnt 、/mnt1 、/mnt2 ……
+    for (i, device) in crate::device::block_devices().into_iter().enumerate() {
+        let name = format!("vd{}", (b'a' + i as u8) as char);
+        let path = if i == 0 { String::from("/mnt") } else { format!("/mnt{}", i) };
+        let disk = cache::BlockCache::new(&name, device, BLOCK_CACHE_SIZE);
+        match open_disk(disk) {
+            Ok(fs) => mount(&path, fs).expect("failed to mount disk"),
+            Err(err) => println!("fs: no filesystem on {}: {:?}, nothing is mounted at {}", name, err, path),
+        }
+    }
+}
+
+/// 依次尝试用 ext2 、SFS 和 FAT32 打开 device 上的文件系统
+pub fn open_disk(device: Arc<dyn BlockDevice>) -> Result<Arc<dyn FileSystem>> {
+    if let Ok(fs) = ext2::Ext2::open(device.clone()) {
+        return Ok(Arc::new(fs));
+    }
+    if let Ok(fs) = sfs::SimpleFileSystem::open(device.clone()) {
+        return Ok(Arc::new(fs));
+    }
+    fat32::Fat32::open(device).map(|fs| Arc::new(fs) as Arc<dyn FileSystem>)
+}
+
+/// 将 fs 挂载到绝对路径 path 上，同一个挂载点只能挂载一个文件系统
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<()> {
+    let path: Vec<String> = components(&canonicalize("/", path)?)
+        .map(String::from)
+        .collect();
+    let mut mounts = MOUNTS.lock();
+    if mounts.iter().any(|mp| mp.path == path) {
+        return Err(FsError::EntryExist);
+    }
+    println!("fs: mounted at /{}", path.join("/"));
+    mounts.push(MountPoint { path, fs });
+    Ok(())
+}
+
+/// 对所有挂载的文件系统执行 sync ，再写回所有块缓存中的脏块
+pub fn sync_all() -> Result<()> {
+    let filesystems: Vec<Arc<dyn FileSystem>> = MOUNTS.lock().iter().map(|mp| mp.fs.clone()).collect();
+    for fs in filesystems {
+        fs.sync()?;
+    }
+    cache::flush_all()
+}
+
+/// 以 cwd 为当前目录，将 path 规范化为不含 "." 、".." 和多余 '/' 的绝对路径
+pub fn canonicalize(cwd: &str, path: &str) -> Result<String> {
+    if path.is_empty() {
+        return Err(FsError::EntryNotFound);
+    }
+    let mut parts: Vec<&str> = Vec::new();
+    let prefix = if path.starts_with('/') { "" } else { cwd };
+    for part in prefix.split('/').chain(path.split('/')) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            name => parts.push(name),
+        }
+    }
+    let mut result = String::new();
+    for part in parts.iter() {
+        result.push('/');
+        result.push_str(part);
+    }
+    if result.is_empty() {
+        result.push('/');
+    }
+    Ok(result)
+}
+
+/// 查找 path 所指的 INode ，相对路径从 cwd 开始解析，路径中的符号链接都会被跟随
+pub fn lookup(cwd: &str, path: &str) -> Result<Arc<dyn INode>> {
+    lookup_follow(cwd, path, true)
+}
+
+/// 与 lookup 相同，但最后一级是符号链接时返回链接本身
+pub fn lookup_nofollow(cwd: &str, path: &str) -> Result<Arc<dyn INode>> {
+    lookup_follow(cwd, path, false)
+}
+
+/// 读出符号链接指向的路径
+pub fn read_link(inode: &Arc<dyn INode>) -> Result<String> {
+    let metadata = inode.metadata()?;
+    if metadata.type_ != FileType::SymLink {
+        return Err(FsError::InvalidParam);
+    }
+    let mut buf = vec![0u8; metadata.size];
+    let len = inode.read_at(0, &mut buf)?;
+    buf.truncate(len);
+    String::from_utf8(buf).map_err(|_| FsError::InvalidParam)
+}
+
+fn lookup_follow(cwd: &str, path: &str, follow: bool) -> Result<Arc<dyn INode>> {
+    let mut path = canonicalize(cwd, path)?;
+    for _ in 0..=MAX_SYMLINKS {
+        match walk(&path, follow)? {
+            Walk::Found(inode) => return Ok(inode),
+            Walk::Redirect(next) => path = next,
+        }
+    }
+    Err(FsError::SymLoop)
+}
+
+enum Walk {
+    Found(Arc<dyn INode>),
+    Redirect(String), // 遇到了符号链接，需要重新解析的路径
+}
+
+// 从挂载点开始逐级查找绝对路径 path 。
+// 遇到符号链接时把路径中到它为止的部分替换为链接的内容，链接与路径一样按字面规范化
+fn walk(path: &str, follow: bool) -> Result<Walk> {
+    let (fs, depth) = find_mount(path)?;
+    let names: Vec<&str> = components(path).collect();
+    let mut inode = fs.root_inode();
+    for i in depth..names.len() {
+        inode = inode.lookup(names[i])?;
+        let last = i + 1 == names.len();
+        if (follow || !last) && inode.metadata()?.type_ == FileType::SymLink {
+            let target = read_link(&inode)?;
+            let dir = format!("/{}", names[..i].join("/"));
+            let next = canonicalize(&dir, &target)?;
+            if last {
+                return Ok(Walk::Redirect(next));
+            }
+            return Ok(Walk::Redirect(canonicalize(&next, &names[i + 1..].join("/"))?));
+        }
+    }
+    Ok(Walk::Found(inode))
+}
+
+/// 查找 path 的父目录，返回父目录和最后一级的名字，用于新建和删除
+pub fn lookup_parent(cwd: &str, path: &str) -> Result<(Arc<dyn INode>, String)> {
+    let path = canonicalize(cwd, path)?;
+    let split = path.rfind('/').unwrap();
+    let name = &path[split + 1..];
+    if name.is_empty() {
+        // 根目录没有父目录
+        return Err(FsError::InvalidParam);
+    }
+    let parent = if split == 0 { "/" } else { &path[..split] };
+    Ok((lookup("/", parent)?, String::from(name)))
+}
+
+// 绝对路径中的各级名字
+fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|part| !part.is_empty())
+}
+
+// 挂载点与 path 匹配得最长的文件系统，以及挂载点的层数
+fn find_mount(path: &str) -> Result<(Arc<dyn FileSystem>, usize)> {
+    let parts: Vec<&str> = components(path).collect();
+    MOUNTS.lock()
+        .iter()
+        .filter(|mp| mp.path.len() <= parts.len() && mp.path.iter().zip(parts.iter()).all(|(a, b)| a == b))
+        .max_by_key(|mp| mp.path.len())
+        .map(|mp| (mp.fs.clone(), mp.path.len()))
+        .ok_or(FsError::EntryNotFound)
+}
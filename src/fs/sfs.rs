@@ -0,0 +1,663 @@
+//! SFS：简单的磁盘文件系统，磁盘镜像由宿主机上的 tools/mksfs 生成
+//!
+//! 磁盘以 4096 字节的块为单位组织：
+//! - 0 号块是超级块
+//! - 1 号块是根目录的 inode
+//! - 从 2 号块开始是空闲块位图，每一位对应一个块，置 1 表示已经使用
+//! - 其余的块按需分配给 inode 、数据块和间接块
+//!
+//! 每个 inode 独占一个块，inode 的编号就是它所在的块号。文件的前 NDIRECT 个数据块记录在 inode 中，
+//! 之后的块号记录在一个间接块中。目录的内容是一串定长的目录项，不保存 "." 和 ".." ，
+//! 删除目录项时用最后一项填补空位。
+//! 所有修改都直接写到设备上，sync 只需要让设备写回它自己的缓存。
+
+use super::block::{BlockDevice, SECTOR_SIZE};
+use super::vfs::{INode, FileSystem, FsError, FileType, Metadata, Result};
+use crate::sync::Mutex;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::mem::size_of;
+use core::{slice, str};
+
+// 以下常量与 tools/mksfs 中的保持一致
+pub const MAGIC: u32 = 0x2f8d_be2a;
+pub const BLKSIZE: usize = 4096;
+const BLKN_SUPER: usize = 0;
+const BLKN_ROOT: usize = 1;
+const BLKN_FREEMAP: usize = 2;
+const NDIRECT: usize = 12;
+const NINDIRECT: usize = BLKSIZE / 4;
+const MAX_FILE_BLOCKS: usize = NDIRECT + NINDIRECT;
+const MAX_NAME_LEN: usize = 59; // 目录项中的名字以 '\0' 结尾
+const ENTRY_SIZE: usize = 64;
+const TYPE_FILE: u16 = 1;
+const TYPE_DIR: u16 = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct SuperBlock {
+    magic: u32,
+    blocks: u32,        // 总块数
+    unused_blocks: u32, // 空闲块数
+    freemap_blocks: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct DiskINode {
+    size: u32,
+    type_: u16,
+    nlinks: u16,
+    blocks: u32, // 数据块数，不包括间接块
+    mode: u16,
+    _pad: u16,
+    direct: [u32; NDIRECT],
+    indirect: u32, // 没有间接块时为 0
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DiskEntry {
+    id: u32,
+    name: [u8; MAX_NAME_LEN + 1],
+}
+
+impl DiskEntry {
+    fn new(name: &str, id: usize) -> DiskEntry {
+        let mut entry = DiskEntry {
+            id: id as u32,
+            name: [0; MAX_NAME_LEN + 1],
+        };
+        entry.name[..name.len()].copy_from_slice(name.as_bytes());
+        entry
+    }
+
+    fn name(&self) -> &str {
+        let len = self.name.iter().position(|&c| c == 0).unwrap_or(MAX_NAME_LEN);
+        str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+}
+
+// 以上结构体都只由整数组成，可以直接当作字节读写
+fn as_bytes<T: Copy>(value: &T) -> &[u8] {
+    unsafe { slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
+
+fn as_bytes_mut<T: Copy>(value: &mut T) -> &mut [u8] {
+    unsafe { slice::from_raw_parts_mut(value as *mut T as *mut u8, size_of::<T>()) }
+}
+
+pub struct SimpleFileSystem {
+    root: Arc<SfsINode>,
+}
+
+impl SimpleFileSystem {
+    /// 打开 device 上的 SFS ，超级块或者根目录不合法时返回 DeviceError
+    pub fn open(device: Arc<dyn BlockDevice>) -> Result<SimpleFileSystem> {
+        let mut super_block = SuperBlock::default();
+        device.read_at(BLKN_SUPER * BLKSIZE, as_bytes_mut(&mut super_block))?;
+        let blocks = super_block.blocks as usize;
+        if super_block.magic != MAGIC
+            || blocks * BLKSIZE > device.sector_count() * SECTOR_SIZE
+            || (super_block.freemap_blocks as usize) * BLKSIZE * 8 < blocks
+        {
+            return Err(FsError::DeviceError);
+        }
+        let mut free_map = vec![0u8; (blocks + 7) / 8];
+        device.read_at(BLKN_FREEMAP * BLKSIZE, &mut free_map)?;
+        let shared = Arc::new(Shared {
+            device,
+            super_block: Mutex::new(super_block),
+            free_map: Mutex::new(free_map),
+            inodes: Mutex::new(BTreeMap::new()),
+            namespace: Mutex::new(()),
+        });
+        let root = Shared::get_inode(&shared, BLKN_ROOT)?;
+        if !root.is_dir() {
+            return Err(FsError::DeviceError);
+        }
+        Ok(SimpleFileSystem { root })
+    }
+
+    /// 剩余的字节数
+    pub fn free_space(&self) -> usize {
+        self.root.fs.super_block.lock().unused_blocks as usize * BLKSIZE
+    }
+}
+
+impl FileSystem for SimpleFileSystem {
+    fn root_inode(&self) -> Arc<dyn INode> {
+        self.root.clone()
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.root.fs.device.flush()
+    }
+}
+
+// 同一个 SFS 中所有 inode 共享的状态
+struct Shared {
+    device: Arc<dyn BlockDevice>,
+    super_block: Mutex<SuperBlock>,
+    free_map: Mutex<Vec<u8>>,
+    // 已经读入内存的 inode ，保证同一个 inode 在内存中只有一份
+    inodes: Mutex<BTreeMap<usize, Weak<SfsINode>>>,
+    namespace: Mutex<()>, // 修改目录结构时持有
+}
+
+impl Shared {
+    fn read_block(&self, id: usize, offset: usize, buf: &mut [u8]) -> Result<()> {
+        self.device.read_at(id * BLKSIZE + offset, buf)
+    }
+
+    fn write_block(&self, id: usize, offset: usize, buf: &[u8]) -> Result<()> {
+        self.device.write_at(id * BLKSIZE + offset, buf)
+    }
+
+    // 分配一个清零的块
+    fn alloc_block(&self) -> Result<usize> {
+        let id = {
+            let mut free_map = self.free_map.lock();
+            let mut super_block = self.super_block.lock();
+            let blocks = super_block.blocks as usize;
+            let byte = free_map.iter().position(|&b| b != 0xff).ok_or(FsError::NoDeviceSpace)?;
+            let id = byte * 8 + (!free_map[byte]).trailing_zeros() as usize;
+            if id >= blocks {
+                return Err(FsError::NoDeviceSpace);
+            }
+            // 位图中还有空闲块而空闲块数已经为 0 ，说明文件系统已经损坏
+            let unused_blocks = super_block.unused_blocks.checked_sub(1).ok_or(FsError::DeviceError)?;
+            free_map[byte] |= 1 << (id % 8);
+            self.write_block(BLKN_FREEMAP, byte, &free_map[byte..byte + 1])?;
+            super_block.unused_blocks = unused_blocks;
+            self.write_block(BLKN_SUPER, 0, as_bytes(&*super_block))?;
+            id
+        };
+        self.write_block(id, 0, &vec![0u8; BLKSIZE])?;
+        Ok(id)
+    }
+
+    // 释放一个块。块号越界或者这个块本来就是空闲的说明文件系统已经损坏
+    fn free_block(&self, id: usize) -> Result<()> {
+        let mut free_map = self.free_map.lock();
+        let mut super_block = self.super_block.lock();
+        let byte = id / 8;
+        if id >= super_block.blocks as usize || byte >= free_map.len() || free_map[byte] & (1 << (id % 8)) == 0 {
+            return Err(FsError::DeviceError);
+        }
+        free_map[byte] &= !(1 << (id % 8));
+        self.write_block(BLKN_FREEMAP, byte, &free_map[byte..byte + 1])?;
+        super_block.unused_blocks += 1;
+        self.write_block(BLKN_SUPER, 0, as_bytes(&*super_block))
+    }
+
+    // 取得编号为 id 的 inode ，不在内存中时从磁盘读入
+    fn get_inode(this: &Arc<Self>, id: usize) -> Result<Arc<SfsINode>> {
+        if id >= this.super_block.lock().blocks as usize {
+            return Err(FsError::DeviceError);
+        }
+        let mut inodes = this.inodes.lock();
+        if let Some(inode) = inodes.get(&id).and_then(Weak::upgrade) {
+            return Ok(inode);
+        }
+        let mut disk = DiskINode::default();
+        this.read_block(id, 0, as_bytes_mut(&mut disk))?;
+        if disk.type_ != TYPE_FILE && disk.type_ != TYPE_DIR {
+            return Err(FsError::DeviceError);
+        }
+        let inode = Arc::new(SfsINode {
+            id,
+            fs: this.clone(),
+            disk: Mutex::new(disk),
+        });
+        inodes.insert(id, Arc::downgrade(&inode));
+        Ok(inode)
+    }
+
+    // 在磁盘上新建一个 inode
+    fn new_inode(this: &Arc<Self>, type_: FileType, mode: u16) -> Result<Arc<SfsINode>> {
+        let id = this.alloc_block()?;
+        let disk = DiskINode {
+            type_: if type_ == FileType::Dir { TYPE_DIR } else { TYPE_FILE },
+            // 目录被父目录中的目录项和自己的 "." 引用
+            nlinks: if type_ == FileType::Dir { 2 } else { 1 },
+            mode,
+            ..DiskINode::default()
+        };
+        if let Err(err) = this.write_block(id, 0, as_bytes(&disk)) {
+            this.free_block(id)?;
+            return Err(err);
+        }
+        let inode = Arc::new(SfsINode {
+            id,
+            fs: this.clone(),
+            disk: Mutex::new(disk),
+        });
+        this.inodes.lock().insert(id, Arc::downgrade(&inode));
+        Ok(inode)
+    }
+}
+
+pub struct SfsINode {
+    id: usize, // inode 所在的块号
+    fs: Arc<Shared>,
+    // 修改之后立即写回磁盘
+    disk: Mutex<DiskINode>,
+}
+
+impl SfsINode {
+    fn is_dir(&self) -> bool {
+        self.disk.lock().type_ == TYPE_DIR
+    }
+
+    fn sync_disk(&self, disk: &DiskINode) -> Result<()> {
+        self.fs.write_block(self.id, 0, as_bytes(disk))
+    }
+
+    // 文件中第 index 个数据块的块号
+    fn block_id(&self, disk: &DiskINode, index: usize) -> Result<usize> {
+        if index < NDIRECT {
+            return Ok(disk.direct[index] as usize);
+        }
+        let mut id = 0u32;
+        self.fs.read_block(disk.indirect as usize, (index - NDIRECT) * 4, as_bytes_mut(&mut id))?;
+        Ok(id as usize)
+    }
+
+    fn set_block_id(&self, disk: &mut DiskINode, index: usize, id: usize) -> Result<()> {
+        if index < NDIRECT {
+            disk.direct[index] = id as u32;
+            return Ok(());
+        }
+        if disk.indirect == 0 {
+            disk.indirect = self.fs.alloc_block()? as u32;
+        }
+        self.fs.write_block(disk.indirect as usize, (index - NDIRECT) * 4, as_bytes(&(id as u32)))
+    }
+
+    // 释放第 blocks 个之后的数据块，不再需要间接块时一并释放
+    fn free_blocks_from(&self, disk: &mut DiskINode, blocks: usize) -> Result<()> {
+        while disk.blocks as usize > blocks {
+            let id = self.block_id(disk, disk.blocks as usize - 1)?;
+            self.fs.free_block(id)?;
+            disk.blocks -= 1;
+        }
+        if blocks <= NDIRECT && disk.indirect != 0 {
+            self.fs.free_block(disk.indirect as usize)?;
+            disk.indirect = 0;
+        }
+        Ok(())
+    }
+
+    // 修改文件大小，增加或者释放数据块。空间不足时文件保持原样
+    fn resize_locked(&self, disk: &mut DiskINode, len: usize) -> Result<()> {
+        let blocks = (len + BLKSIZE - 1) / BLKSIZE;
+        if blocks > MAX_FILE_BLOCKS {
+            return Err(FsError::NoDeviceSpace);
+        }
+        let old_blocks = disk.blocks as usize;
+        while (disk.blocks as usize) < blocks {
+            let grown = self.fs.alloc_block().and_then(|id| {
+                let index = disk.blocks as usize;
+                self.set_block_id(disk, index, id).or_else(|err| {
+                    self.fs.free_block(id)?;
+                    Err(err)
+                })
+            });
+            if let Err(err) = grown {
+                self.free_blocks_from(disk, old_blocks)?;
+                return Err(err);
+            }
+            disk.blocks += 1;
+        }
+        self.free_blocks_from(disk, blocks)?;
+        // 截断时把最后一块中文件末尾之后的部分清零，之后再扩展时读到的就是 0
+        if len < disk.size as usize && len % BLKSIZE != 0 {
+            let tail = len % BLKSIZE;
+            let id = self.block_id(disk, blocks - 1)?;
+            self.fs.write_block(id, tail, &vec![0u8; BLKSIZE - tail])?;
+        }
+        disk.size = len as u32;
+        self.sync_disk(disk)
+    }
+
+    // 将文件中 [offset, offset + len) 按块拆开，对每一段调用 f(块号, 块内偏移, 已经处理的字节数, 这一段的长度)
+    fn for_each_block<F>(&self, disk: &DiskINode, offset: usize, len: usize, mut f: F) -> Result<()>
+    where
+        F: FnMut(usize, usize, usize, usize) -> Result<()>,
+    {
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let in_block = pos % BLKSIZE;
+            let n = (BLKSIZE - in_block).min(len - done);
+            f(self.block_id(disk, pos / BLKSIZE)?, in_block, done, n)?;
+            done += n;
+        }
+        Ok(())
+    }
+
+    // 读取 [offset, offset + buf.len()) ，调用者保证它在文件范围内
+    fn read_locked(&self, disk: &DiskINode, offset: usize, buf: &mut [u8]) -> Result<()> {
+        let fs = &self.fs;
+        self.for_each_block(disk, offset, buf.len(), |id, in_block, done, n| {
+            fs.read_block(id, in_block, &mut buf[done..done + n])
+        })
+    }
+
+    // 写入 [offset, offset + buf.len()) ，调用者保证它在文件范围内
+    fn write_locked(&self, disk: &DiskINode, offset: usize, buf: &[u8]) -> Result<()> {
+        let fs = &self.fs;
+        self.for_each_block(disk, offset, buf.len(), |id, in_block, done, n| {
+            fs.write_block(id, in_block, &buf[done..done + n])
+        })
+    }
+
+    fn read_entry(&self, disk: &DiskINode, index: usize) -> Result<DiskEntry> {
+        let mut entry = DiskEntry::new("", 0);
+        self.read_locked(disk, index * ENTRY_SIZE, as_bytes_mut(&mut entry))?;
+        Ok(entry)
+    }
+
+    // 名为 name 的目录项的序号和 inode 编号
+    fn find_entry(&self, disk: &DiskINode, name: &str) -> Result<Option<(usize, usize)>> {
+        if disk.type_ != TYPE_DIR {
+            return Err(FsError::NotDir);
+        }
+        for index in 0..disk.size as usize / ENTRY_SIZE {
+            let entry = self.read_entry(disk, index)?;
+            if entry.name() == name {
+                return Ok(Some((index, entry.id as usize)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn get_entry(&self, name: &str) -> Result<Arc<SfsINode>> {
+        let id = {
+            let disk = self.disk.lock();
+            self.find_entry(&disk, name)?.ok_or(FsError::EntryNotFound)?.1
+        };
+        Shared::get_inode(&self.fs, id)
+    }
+
+    fn insert_entry(&self, name: &str, inode: &SfsINode) -> Result<()> {
+        let is_dir = inode.is_dir();
+        let mut disk = self.disk.lock();
+        let size = disk.size as usize;
+        self.resize_locked(&mut disk, size + ENTRY_SIZE)?;
+        self.write_locked(&disk, size, as_bytes(&DiskEntry::new(name, inode.id)))?;
+        if is_dir {
+            // 子目录中的 ".." 引用了这个目录
+            disk.nlinks += 1;
+            self.sync_disk(&disk)?;
+        }
+        Ok(())
+    }
+
+    fn remove_entry(&self, name: &str, is_dir: bool) -> Result<()> {
+        let mut disk = self.disk.lock();
+        let (index, _) = self.find_entry(&disk, name)?.ok_or(FsError::EntryNotFound)?;
+        let last = disk.size as usize / ENTRY_SIZE - 1;
+        if index != last {
+            let entry = self.read_entry(&disk, last)?;
+            self.write_locked(&disk, index * ENTRY_SIZE, as_bytes(&entry))?;
+        }
+        if is_dir {
+            disk.nlinks = disk.nlinks.checked_sub(1).ok_or(FsError::DeviceError)?;
+        }
+        self.resize_locked(&mut disk, last * ENTRY_SIZE)
+    }
+
+    fn is_empty_dir(&self) -> bool {
+        let disk = self.disk.lock();
+        disk.type_ == TYPE_DIR && disk.size == 0
+    }
+
+    // 目录项被删除后减少链接数，目录被删除后它自己的 "." 也不再存在
+    fn drop_link(&self) -> Result<()> {
+        let mut disk = self.disk.lock();
+        disk.nlinks = if disk.type_ == TYPE_DIR {
+            0
+        } else {
+            disk.nlinks.checked_sub(1).ok_or(FsError::DeviceError)?
+        };
+        self.sync_disk(&disk)
+    }
+
+    // self 是否就是 target ，或者 target 位于以 self 为根的子树中
+    fn contains(&self, target: &SfsINode) -> Result<bool> {
+        if self.id == target.id {
+            return Ok(true);
+        }
+        let children: Vec<usize> = {
+            let disk = self.disk.lock();
+            if disk.type_ != TYPE_DIR {
+                return Ok(false);
+            }
+            let mut children = Vec::new();
+            for index in 0..disk.size as usize / ENTRY_SIZE {
+                children.push(self.read_entry(&disk, index)?.id as usize);
+            }
+            children
+        };
+        for id in children {
+            if Shared::get_inode(&self.fs, id)?.contains(target)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    // 从 &Arc<dyn INode> 中取出同一文件系统中的 SfsINode
+    fn same_fs(&self, other: &Arc<dyn INode>) -> Result<Arc<SfsINode>> {
+        match other.downcast_ref::<SfsINode>() {
+            Some(inode) if Arc::ptr_eq(&inode.fs, &self.fs) => {
+                // 已经确认了具体类型，可以把胖指针转换回 Arc<SfsINode>
+                Ok(unsafe { Arc::from_raw(Arc::into_raw(other.clone()) as *const SfsINode) })
+            }
+            _ => Err(FsError::NotSameFs),
+        }
+    }
+}
+
+impl Drop for SfsINode {
+    fn drop(&mut self) {
+        {
+            let mut inodes = self.fs.inodes.lock();
+            // 这期间可能已经有人重新读入了这个 inode ，这时不能删掉新的缓存项
+            if inodes.get(&self.id).map_or(false, |inode| inode.upgrade().is_none()) {
+                inodes.remove(&self.id);
+            }
+        }
+        // 最后一个引用消失时，文件已经被删除并且不再被打开，归还它占用的块
+        let mut disk = self.disk.lock();
+        if disk.nlinks == 0 {
+            let freed = self.free_blocks_from(&mut disk, 0).and_then(|_| self.fs.free_block(self.id));
+            if freed.is_err() {
+                println!("sfs: failed to free inode {}", self.id);
+            }
+        }
+    }
+}
+
+impl INode for SfsINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let disk = self.disk.lock();
+        if disk.type_ == TYPE_DIR {
+            return Err(FsError::IsDir);
+        }
+        let size = disk.size as usize;
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min(size - offset);
+        self.read_locked(&disk, offset, &mut buf[..len])?;
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let mut disk = self.disk.lock();
+        let end = offset.checked_add(buf.len()).ok_or(FsError::InvalidParam)?;
+        if disk.type_ == TYPE_DIR {
+            return Err(FsError::IsDir);
+        }
+        if end > disk.size as usize {
+            self.resize_locked(&mut disk, end)?;
+        }
+        self.write_locked(&disk, offset, buf)?;
+        Ok(buf.len())
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        let disk = self.disk.lock();
+        let type_ = if disk.type_ == TYPE_DIR { FileType::Dir } else { FileType::File };
+        Ok(Metadata {
+            dev: 0,
+            inode: self.id,
+            size: disk.size as usize,
+            blk_size: BLKSIZE,
+            blocks: disk.blocks as usize,
+            type_,
+            mode: disk.mode,
+            nlinks: disk.nlinks as usize,
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn INode>> {
+        Ok(self.get_entry(name)?)
+    }
+
+    fn create(&self, name: &str, type_: FileType, mode: u32) -> Result<Arc<dyn INode>> {
+        check_name(name)?;
+        let type_ = match type_ {
+            FileType::File | FileType::Dir => type_,
+            _ => return Err(FsError::NotSupported),
+        };
+        let _namespace = self.fs.namespace.lock();
+        match self.get_entry(name) {
+            Ok(_) => return Err(FsError::EntryExist),
+            Err(FsError::EntryNotFound) => {}
+            Err(err) => return Err(err),
+        }
+        let inode = Shared::new_inode(&self.fs, type_, (mode & 0o7777) as u16)?;
+        if let Err(err) = self.insert_entry(name, &inode) {
+            // 没有目录项引用这个 inode ，最后一个引用消失时归还它
+            inode.disk.lock().nlinks = 0;
+            return Err(err);
+        }
+        Ok(inode)
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        check_name(name)?;
+        let _namespace = self.fs.namespace.lock();
+        let inode = self.get_entry(name)?;
+        let is_dir = inode.is_dir();
+        if is_dir && !inode.is_empty_dir() {
+            return Err(FsError::DirNotEmpty);
+        }
+        self.remove_entry(name, is_dir)?;
+        inode.drop_link()
+    }
+
+    fn link(&self, name: &str, other: &Arc<dyn INode>) -> Result<()> {
+        check_name(name)?;
+        let other = self.same_fs(other)?;
+        if other.is_dir() {
+            return Err(FsError::IsDir);
+        }
+        let _namespace = self.fs.namespace.lock();
+        match self.get_entry(name) {
+            Ok(_) => return Err(FsError::EntryExist),
+            Err(FsError::EntryNotFound) => {}
+            Err(err) => return Err(err),
+        }
+        self.insert_entry(name, &other)?;
+        let mut disk = other.disk.lock();
+        disk.nlinks += 1;
+        other.sync_disk(&disk)
+    }
+
+    fn rename(&self, old_name: &str, target: &Arc<dyn INode>, new_name: &str) -> Result<()> {
+        check_name(old_name)?;
+        check_name(new_name)?;
+        let target = self.same_fs(target)?;
+        let _namespace = self.fs.namespace.lock();
+        let inode = self.get_entry(old_name)?;
+        if !target.is_dir() {
+            return Err(FsError::NotDir);
+        }
+        let is_dir = inode.is_dir();
+        // 不能把目录移动到它自己的子树中
+        if is_dir && inode.contains(&target)? {
+            return Err(FsError::InvalidParam);
+        }
+        match target.get_entry(new_name) {
+            Ok(existing) => {
+                if existing.id == inode.id {
+                    // 新旧名字是同一个文件的两个链接，什么也不做
+                    return Ok(());
+                }
+                match (is_dir, existing.is_dir()) {
+                    (true, true) if !existing.is_empty_dir() => return Err(FsError::DirNotEmpty),
+                    (true, false) => return Err(FsError::NotDir),
+                    (false, true) => return Err(FsError::IsDir),
+                    _ => {}
+                }
+                target.remove_entry(new_name, is_dir)?;
+                existing.drop_link()?;
+            }
+            Err(FsError::EntryNotFound) => {}
+            Err(err) => return Err(err),
+        }
+        self.remove_entry(old_name, is_dir)?;
+        target.insert_entry(new_name, &inode)
+    }
+
+    fn readdir(&self, index: usize) -> Result<String> {
+        let disk = self.disk.lock();
+        if disk.type_ != TYPE_DIR {
+            return Err(FsError::NotDir);
+        }
+        match index {
+            0 => Ok(String::from(".")),
+            1 => Ok(String::from("..")),
+            index if index - 2 < disk.size as usize / ENTRY_SIZE => {
+                Ok(String::from(self.read_entry(&disk, index - 2)?.name()))
+            }
+            _ => Err(FsError::EntryNotFound),
+        }
+    }
+
+    fn resize(&self, len: usize) -> Result<()> {
+        let mut disk = self.disk.lock();
+        if disk.type_ == TYPE_DIR {
+            return Err(FsError::IsDir);
+        }
+        self.resize_locked(&mut disk, len)
+    }
+
+    fn sync(&self) -> Result<()> {
+        self.fs.device.flush()
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\0') {
+        return Err(FsError::InvalidParam);
+    }
+    // 目录项中放不下的名字
+    if name.len() > MAX_NAME_LEN {
+        return Err(FsError::InvalidParam);
+    }
+    Ok(())
+}
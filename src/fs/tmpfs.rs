@@ -0,0 +1,427 @@
+//! tmpfs：全部数据都保存在内存中的可写文件系统
+//!
+//! 目录项保存在内核堆上，文件内容按页存放在从页帧分配器分配的物理页帧中，写入时按需分配，
+//! 整个文件系统占用的页帧数不超过创建时指定的上限。
+//! 新建、删除、链接和重命名都会修改目录结构，它们由一把文件系统级的锁串行执行，
+//! 因此每一步只需要锁住一个 inode ，不会因为加锁顺序而死锁。
+
+use super::vfs::{INode, FileSystem, FsError, FileType, Metadata, Result};
+use crate::new_memory::{Frame, access_pa_via_va, frame_allocator};
+use crate::consts::PAGE_SIZE;
+use crate::sync::{Mutex, RwLock};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct TmpFs {
+    root: Arc<TmpINode>,
+}
+
+impl TmpFs {
+    /// 新建一个空的 tmpfs ，文件内容最多占用 max_size 字节（按页向上取整）
+    pub fn new(max_size: usize) -> TmpFs {
+        let shared = Arc::new(Shared {
+            max_pages: (max_size + PAGE_SIZE - 1) / PAGE_SIZE,
+            used_pages: AtomicUsize::new(0),
+            next_ino: AtomicUsize::new(1),
+            namespace: Mutex::new(()),
+        });
+        TmpFs {
+            root: TmpINode::new(&shared, FileType::Dir, 0o777),
+        }
+    }
+
+    /// 已经使用的字节数
+    pub fn used(&self) -> usize {
+        self.root.shared.used_pages.load(Ordering::Relaxed) * PAGE_SIZE
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn root_inode(&self) -> Arc<dyn INode> {
+        self.root.clone()
+    }
+}
+
+// 同一个 tmpfs 中所有 inode 共享的状态
+struct Shared {
+    max_pages: usize,
+    used_pages: AtomicUsize,
+    next_ino: AtomicUsize,
+    namespace: Mutex<()>, // 修改目录结构时持有
+}
+
+impl Shared {
+    // 分配一个清零的页帧，超出上限或者物理内存耗尽时返回 NoDeviceSpace
+    fn alloc_page(&self) -> Result<Frame> {
+        let mut used = self.used_pages.load(Ordering::Relaxed);
+        loop {
+            if used >= self.max_pages {
+                return Err(FsError::NoDeviceSpace);
+            }
+            let old = self.used_pages.compare_and_swap(used, used + 1, Ordering::Relaxed);
+            if old == used {
+                break;
+            }
+            used = old;
+        }
+        match frame_allocator::alloc_frame() {
+            Some(frame) => {
+                unsafe { ptr::write_bytes(page_ptr(frame), 0, PAGE_SIZE) };
+                Ok(frame)
+            }
+            None => {
+                self.used_pages.fetch_sub(1, Ordering::Relaxed);
+                Err(FsError::NoDeviceSpace)
+            }
+        }
+    }
+
+    fn free_page(&self, frame: Frame) {
+        frame_allocator::dealloc_frame(frame);
+        self.used_pages.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+enum Content {
+    // 文件的第 i 页保存在 frames[i] 中，frames 的长度总是刚好覆盖 size
+    File { size: usize, frames: Vec<Frame> },
+    Dir(BTreeMap<String, Arc<TmpINode>>),
+}
+
+struct Inner {
+    mode: u16,
+    nlinks: usize,
+    content: Content,
+}
+
+pub struct TmpINode {
+    ino: usize,
+    shared: Arc<Shared>,
+    inner: RwLock<Inner>,
+}
+
+impl TmpINode {
+    fn new(shared: &Arc<Shared>, type_: FileType, mode: u16) -> Arc<TmpINode> {
+        let (nlinks, content) = match type_ {
+            // 目录被父目录中的目录项和自己的 "." 引用
+            FileType::Dir => (2, Content::Dir(BTreeMap::new())),
+            _ => (1, Content::File { size: 0, frames: Vec::new() }),
+        };
+        Arc::new(TmpINode {
+            ino: shared.next_ino.fetch_add(1, Ordering::Relaxed),
+            shared: shared.clone(),
+            inner: RwLock::new(Inner { mode, nlinks, content }),
+        })
+    }
+
+    fn is_dir(&self) -> bool {
+        match self.inner.read().content {
+            Content::Dir(_) => true,
+            Content::File { .. } => false,
+        }
+    }
+
+    fn get_entry(&self, name: &str) -> Result<Arc<TmpINode>> {
+        match self.inner.read().content {
+            Content::Dir(ref entries) => entries.get(name).cloned().ok_or(FsError::EntryNotFound),
+            Content::File { .. } => Err(FsError::NotDir),
+        }
+    }
+
+    fn insert_entry(&self, name: &str, inode: Arc<TmpINode>) {
+        let mut inner = self.inner.write();
+        if inode.is_dir() {
+            // 子目录中的 ".." 引用了这个目录
+            inner.nlinks += 1;
+        }
+        if let Content::Dir(ref mut entries) = inner.content {
+            entries.insert(String::from(name), inode);
+        }
+    }
+
+    fn remove_entry(&self, name: &str) -> Option<Arc<TmpINode>> {
+        let mut inner = self.inner.write();
+        let inode = match inner.content {
+            Content::Dir(ref mut entries) => entries.remove(name)?,
+            Content::File { .. } => return None,
+        };
+        if inode.is_dir() {
+            inner.nlinks -= 1;
+        }
+        Some(inode)
+    }
+
+    fn is_empty_dir(&self) -> bool {
+        match self.inner.read().content {
+            Content::Dir(ref entries) => entries.is_empty(),
+            Content::File { .. } => false,
+        }
+    }
+
+    // 目录项被删除后减少链接数，目录被删除后它自己的 "." 也不再存在
+    fn drop_link(&self) {
+        let mut inner = self.inner.write();
+        inner.nlinks = match inner.content {
+            Content::Dir(_) => 0,
+            Content::File { .. } => inner.nlinks - 1,
+        };
+    }
+
+    // self 是否就是 target ，或者 target 位于以 self 为根的子树中
+    fn contains(&self, target: &TmpINode) -> bool {
+        if ptr::eq(self, target) {
+            return true;
+        }
+        match self.inner.read().content {
+            Content::Dir(ref entries) => entries.values().any(|child| child.contains(target)),
+            Content::File { .. } => false,
+        }
+    }
+
+    // 从 &Arc<dyn INode> 中取出同一文件系统中的 TmpINode
+    fn same_fs(&self, other: &Arc<dyn INode>) -> Result<Arc<TmpINode>> {
+        match other.downcast_ref::<TmpINode>() {
+            Some(inode) if Arc::ptr_eq(&inode.shared, &self.shared) => {
+                // 已经确认了具体类型，可以把胖指针转换回 Arc<TmpINode>
+                Ok(unsafe { Arc::from_raw(Arc::into_raw(other.clone()) as *const TmpINode) })
+            }
+            _ => Err(FsError::NotSameFs),
+        }
+    }
+}
+
+impl Drop for TmpINode {
+    fn drop(&mut self) {
+        // 最后一个引用消失时，文件已经被删除并且不再被打开，归还它占用的页帧
+        if let Content::File { ref mut frames, .. } = self.inner.write().content {
+            for frame in frames.drain(..) {
+                self.shared.free_page(frame);
+            }
+        }
+    }
+}
+
+impl INode for TmpINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let inner = self.inner.read();
+        let (size, frames) = match inner.content {
+            Content::File { size, ref frames } => (size, frames),
+            Content::Dir(_) => return Err(FsError::IsDir),
+        };
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min(size - offset);
+        copy_pages(frames, offset, len, |page, done, n| unsafe {
+            ptr::copy_nonoverlapping(page, buf[done..].as_mut_ptr(), n);
+        });
+        Ok(len)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let mut inner = self.inner.write();
+        let end = offset.checked_add(buf.len()).ok_or(FsError::InvalidParam)?;
+        if let Content::Dir(_) = inner.content {
+            return Err(FsError::IsDir);
+        }
+        if end > file_size(&inner) {
+            resize(&self.shared, &mut inner, end)?;
+        }
+        if let Content::File { ref frames, .. } = inner.content {
+            copy_pages(frames, offset, buf.len(), |page, done, n| unsafe {
+                ptr::copy_nonoverlapping(buf[done..].as_ptr(), page, n);
+            });
+        }
+        Ok(buf.len())
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        let inner = self.inner.read();
+        let (type_, size, blocks) = match inner.content {
+            Content::File { size, ref frames } => (FileType::File, size, frames.len()),
+            Content::Dir(ref entries) => (FileType::Dir, entries.len(), 0),
+        };
+        Ok(Metadata {
+            dev: 0,
+            inode: self.ino,
+            size,
+            blk_size: PAGE_SIZE,
+            blocks,
+            type_,
+            mode: inner.mode,
+            nlinks: inner.nlinks,
+        })
+    }
+
+    fn lookup(&self, name: &str) -> Result<Arc<dyn INode>> {
+        Ok(self.get_entry(name)?)
+    }
+
+    fn create(&self, name: &str, type_: FileType, mode: u32) -> Result<Arc<dyn INode>> {
+        check_name(name)?;
+        let type_ = match type_ {
+            FileType::File | FileType::Dir => type_,
+            _ => return Err(FsError::NotSupported),
+        };
+        let _namespace = self.shared.namespace.lock();
+        match self.get_entry(name) {
+            Ok(_) => return Err(FsError::EntryExist),
+            Err(FsError::EntryNotFound) => {}
+            Err(err) => return Err(err),
+        }
+        let inode = TmpINode::new(&self.shared, type_, (mode & 0o7777) as u16);
+        self.insert_entry(name, inode.clone());
+        Ok(inode)
+    }
+
+    fn unlink(&self, name: &str) -> Result<()> {
+        check_name(name)?;
+        let _namespace = self.shared.namespace.lock();
+        let inode = self.get_entry(name)?;
+        if inode.is_dir() && !inode.is_empty_dir() {
+            return Err(FsError::DirNotEmpty);
+        }
+        self.remove_entry(name);
+        inode.drop_link();
+        Ok(())
+    }
+
+    fn link(&self, name: &str, other: &Arc<dyn INode>) -> Result<()> {
+        check_name(name)?;
+        let other = self.same_fs(other)?;
+        if other.is_dir() {
+            return Err(FsError::IsDir);
+        }
+        let _namespace = self.shared.namespace.lock();
+        match self.get_entry(name) {
+            Ok(_) => return Err(FsError::EntryExist),
+            Err(FsError::EntryNotFound) => {}
+            Err(err) => return Err(err),
+        }
+        other.inner.write().nlinks += 1;
+        self.insert_entry(name, other);
+        Ok(())
+    }
+
+    fn rename(&self, old_name: &str, target: &Arc<dyn INode>, new_name: &str) -> Result<()> {
+        check_name(old_name)?;
+        check_name(new_name)?;
+        let target = self.same_fs(target)?;
+        let _namespace = self.shared.namespace.lock();
+        let inode = self.get_entry(old_name)?;
+        if !target.is_dir() {
+            return Err(FsError::NotDir);
+        }
+        // 不能把目录移动到它自己的子树中
+        if inode.is_dir() && inode.contains(&target) {
+            return Err(FsError::InvalidParam);
+        }
+        match target.get_entry(new_name) {
+            Ok(existing) => {
+                if Arc::ptr_eq(&existing, &inode) {
+                    // 新旧名字是同一个文件的两个链接，什么也不做
+                    return Ok(());
+                }
+                match (inode.is_dir(), existing.is_dir()) {
+                    (true, true) if !existing.is_empty_dir() => return Err(FsError::DirNotEmpty),
+                    (true, false) => return Err(FsError::NotDir),
+                    (false, true) => return Err(FsError::IsDir),
+                    _ => {}
+                }
+                target.remove_entry(new_name);
+                existing.drop_link();
+            }
+            Err(FsError::EntryNotFound) => {}
+            Err(err) => return Err(err),
+        }
+        self.remove_entry(old_name);
+        target.insert_entry(new_name, inode);
+        Ok(())
+    }
+
+    fn readdir(&self, index: usize) -> Result<String> {
+        match self.inner.read().content {
+            Content::Dir(ref entries) => match index {
+                0 => Ok(String::from(".")),
+                1 => Ok(String::from("..")),
+                index => entries.keys().nth(index - 2).cloned().ok_or(FsError::EntryNotFound),
+            },
+            Content::File { .. } => Err(FsError::NotDir),
+        }
+    }
+
+    fn resize(&self, len: usize) -> Result<()> {
+        let mut inner = self.inner.write();
+        resize(&self.shared, &mut inner, len)
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+        return Err(FsError::InvalidParam);
+    }
+    Ok(())
+}
+
+fn file_size(inner: &Inner) -> usize {
+    match inner.content {
+        Content::File { size, .. } => size,
+        Content::Dir(_) => 0,
+    }
+}
+
+// 修改文件大小，增加或者释放页帧。空间不足时文件保持原样
+fn resize(shared: &Shared, inner: &mut Inner, len: usize) -> Result<()> {
+    let (size, frames) = match inner.content {
+        Content::File { ref mut size, ref mut frames } => (size, frames),
+        Content::Dir(_) => return Err(FsError::IsDir),
+    };
+    let pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+    let old_pages = frames.len();
+    while frames.len() < pages {
+        match shared.alloc_page() {
+            Ok(frame) => frames.push(frame),
+            Err(err) => {
+                for frame in frames.drain(old_pages..) {
+                    shared.free_page(frame);
+                }
+                return Err(err);
+            }
+        }
+    }
+    while frames.len() > pages {
+        shared.free_page(frames.pop().unwrap());
+    }
+    // 截断时把最后一页中文件末尾之后的部分清零，之后再扩展时读到的就是 0
+    if len < *size && len % PAGE_SIZE != 0 {
+        let tail = len % PAGE_SIZE;
+        unsafe { ptr::write_bytes(page_ptr(frames[pages - 1]).add(tail), 0, PAGE_SIZE - tail) };
+    }
+    *size = len;
+    Ok(())
+}
+
+// 将文件中 [offset, offset + len) 按页拆开，对每一段调用 f(页内地址, 已经处理的字节数, 这一段的长度)
+fn copy_pages<F: FnMut(*mut u8, usize, usize)>(frames: &[Frame], offset: usize, len: usize, mut f: F) {
+    let mut done = 0;
+    while done < len {
+        let pos = offset + done;
+        let in_page = pos % PAGE_SIZE;
+        let n = (PAGE_SIZE - in_page).min(len - done);
+        f(unsafe { page_ptr(frames[pos / PAGE_SIZE]).add(in_page) }, done, n);
+        done += n;
+    }
+}
+
+fn page_ptr(frame: Frame) -> *mut u8 {
+    access_pa_via_va(frame.start_address()) as *mut u8
+}
@@ -0,0 +1,125 @@
+//! 虚拟文件系统接口
+//!
+//! 每个具体的文件系统实现 `FileSystem` ，并把其中的文件和目录表示为 `INode` 。
+//! 内核的其他部分只通过这两个 trait 访问文件，不关心文件实际存放在哪里。
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::any::Any;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FsError {
+    NotSupported,  // 这个 INode 不支持该操作
+    NotFile,       // 对目录进行了只有文件才支持的操作
+    IsDir,         // 期望一个文件，但找到的是目录
+    NotDir,        // 期望一个目录，但找到的不是目录
+    EntryNotFound, // 目录中没有这一项
+    EntryExist,    // 目录中已经有同名的项
+    NotSameFs,     // 不能跨文件系统建立链接或者重命名
+    InvalidParam,  // 参数不合法，例如名字中含有 '/'
+    NoDeviceSpace, // 设备上没有剩余空间
+    DirNotEmpty,   // 删除了非空的目录
+    ReadOnly,      // 文件系统是只读的
+    SymLoop,       // 解析路径时跟随了过多的符号链接
+    DeviceError,   // 底层设备出错
+    Interrupted,   // 等待时被信号打断
+}
+
+pub type Result<T> = core::result::Result<T, FsError>;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FileType {
+    File,
+    Dir,
+    CharDevice,
+    BlockDevice,
+    SymLink,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Metadata {
+    pub dev: usize,     // 所在设备的编号
+    pub inode: usize,   // 在文件系统中的编号
+    pub size: usize,    // 字节数
+    pub blk_size: usize,
+    pub blocks: usize,  // 占用的块数
+    pub type_: FileType,
+    pub mode: u16,      // 权限位，只用于 fstat 报告
+    pub nlinks: usize,  // 硬链接数
+}
+
+pub trait INode: Any + Sync + Send {
+    // 从 offset 处读取至多 buf.len() 字节，返回实际读到的字节数，读到文件末尾时返回 0 。
+    // 符号链接的内容就是它指向的路径
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize>;
+    // 从 offset 处写入 buf ，必要时扩展文件，返回实际写入的字节数
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize>;
+    fn metadata(&self) -> Result<Metadata>;
+
+    // 以下操作只有目录支持
+    // 在目录中查找名为 name 的项，name 中不含 '/'
+    fn lookup(&self, _name: &str) -> Result<Arc<dyn INode>> {
+        Err(FsError::NotDir)
+    }
+    // 在目录中新建一个名为 name 的文件或者目录
+    fn create(&self, _name: &str, _type: FileType, _mode: u32) -> Result<Arc<dyn INode>> {
+        Err(FsError::NotDir)
+    }
+    // 删除目录中名为 name 的项，目录必须为空
+    fn unlink(&self, _name: &str) -> Result<()> {
+        Err(FsError::NotDir)
+    }
+    // 在目录中建立一个名为 name 的硬链接，指向同一文件系统中的文件 other
+    fn link(&self, _name: &str, _other: &Arc<dyn INode>) -> Result<()> {
+        Err(FsError::NotDir)
+    }
+    // 将目录中名为 old_name 的项移动到目录 target 中，改名为 new_name ，
+    // new_name 已经存在时将其替换
+    fn rename(&self, _old_name: &str, _target: &Arc<dyn INode>, _new_name: &str) -> Result<()> {
+        Err(FsError::NotDir)
+    }
+    // 目录中的第 index 项的名字，包括 "." 和 ".."，超出范围时返回 EntryNotFound
+    fn readdir(&self, _index: usize) -> Result<String> {
+        Err(FsError::NotDir)
+    }
+
+    // 修改文件的大小，扩展的部分填充 0
+    fn resize(&self, _len: usize) -> Result<()> {
+        Err(FsError::NotSupported)
+    }
+    // 将这个文件的数据写回设备
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+    // 用于将 Arc<dyn INode> 转换回具体的类型
+    fn as_any_ref(&self) -> &dyn Any;
+}
+
+impl dyn INode {
+    pub fn downcast_ref<T: INode>(&self) -> Option<&T> {
+        self.as_any_ref().downcast_ref::<T>()
+    }
+
+    // 读出整个文件
+    pub fn read_as_vec(&self) -> Result<alloc::vec::Vec<u8>> {
+        let size = self.metadata()?.size;
+        let mut buf = alloc::vec![0u8; size];
+        let mut read = 0;
+        while read < size {
+            match self.read_at(read, &mut buf[read..])? {
+                0 => break,
+                len => read += len,
+            }
+        }
+        buf.truncate(read);
+        Ok(buf)
+    }
+}
+
+pub trait FileSystem: Sync + Send {
+    fn root_inode(&self) -> Arc<dyn INode>;
+    // 将所有修改写回设备
+    fn sync(&self) -> Result<()> {
+        Ok(())
+    }
+}
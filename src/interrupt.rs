@@ -3,6 +3,7 @@ use crate::riscv::register::{ sscratch, sstatus, stvec };
 use crate::context::TrapFrame;
 use crate::clock::{ TICK, clock_set_next_event };
 use crate::process::tick;
+use crate::consts::KERNEL_OFFSET;
 //use core::intrinsics::type_id;
 
 //use riscv::register::sscratch;
@@ -41,7 +42,8 @@ pub fn enable_and_wfi() {    // 使能中断并等待中断
 pub fn disable_and_store() -> usize {    // 禁用中断并返回当前中断状态
     let bits: usize;
     unsafe {
-        asm!("csrci sstatus, 1 << 1" : "=r"(bits) ::: "volatile");
+        // csrrci 在清除 SIE 的同时读出原来的 sstatus
+        asm!("csrrci $0, sstatus, 1 << 1" : "=r"(bits) ::: "volatile");
     }
     bits & (1 << 1)
 }
@@ -79,12 +81,19 @@ pub fn rust_trap(tf: &mut TrapFrame) {
 
     match tf.scause.cause() {
         Trap::Exception(Exception::Breakpoint) => breakpoint(),
+        Trap::Exception(Exception::UserEnvCall) => syscall(tf),
         Trap::Interrupt(Interrupt::SupervisorTimerInterrupt) => super_timer(),
+        Trap::Interrupt(Interrupt::SupervisorExternalInterrupt) => crate::device::irq::handle(),
         Trap::Exception(Exception::InstructionPageFault) => page_fault(tf),
         Trap::Exception(Exception::LoadPageFault) => page_fault(tf),
         Trap::Exception(Exception::StorePageFault) => page_fault(tf),
         _ => panic!("unexpected trap: {:x?}", tf.scause.cause()),
     }
+    // 即将返回用户态时不持有任何锁，被杀死的线程在这里退出，再处理当前进程收到的信号
+    if tf.sstatus & (1 << 8) == 0 {
+        crate::process::exit_if_killed();
+        crate::process::handle_signals();
+    }
     // 返回汇编代码继续执行sret
 }
 
@@ -105,26 +114,26 @@ fn super_timer() {
 }
 
 fn page_fault(tf: &mut TrapFrame) {
+    // 写时复制等情况由当前进程的地址空间处理，处理完成后重新执行引发异常的指令
+    if crate::process::handle_page_fault(tf.stval) {
+        return;
+    }
     println!("{:?} @ {:#x}", tf.scause.cause(), tf.stval);
+    // 用户进程（或内核替用户进程访问用户内存时）访问了非法地址，只杀死这个进程
+    let from_user = tf.sstatus & (1 << 8) == 0; // SPP 为 0 说明异常来自 U 态
+    if crate::process::current_process().is_some() && (from_user || tf.stval < KERNEL_OFFSET) {
+        println!("segmentation fault: process {} killed", crate::process::current_pid());
+        crate::process::exit_group(crate::process::SEGFAULT_EXIT_CODE);
+    }
     panic!("page fault");
 }
 
-pub const SYS_WRITE: usize = 64;
-pub const SYS_EXIT: usize = 93;
-
 fn syscall(tf: &mut TrapFrame) {
-    tf.sepc += 4;   // 主动跳过当前指令
-    match tf.x[17] {
-        SYS_WRITE => {
-            print!("{}", tf.x[10] as u8 as char);
-        },
-        SYS_EXIT => {
-            println!("exit!");
-            use crate::process::exit;
-            exit(tf.x[10]);
-        },
-        _ => {
-            println!("unknown user syscall !");
-        }
-    };
-}
\ No newline at end of file
+    tf.increase_sepc();   // 主动跳过当前指令
+    let ret = crate::syscall::syscall(
+        tf.x[17],
+        [tf.x[10], tf.x[11], tf.x[12], tf.x[13], tf.x[14], tf.x[15]],
+        tf,
+    );
+    tf.x[10] = ret as usize;
+}
@@ -35,6 +35,7 @@ pub fn puts(s: &str) {
     for ch in s.chars() {
         putchar(ch);
     }
+    crate::device::console::puts(s);
 }
 
 
@@ -24,6 +24,10 @@ pub mod new_memory;
 pub mod consts;
 
 pub mod process;
+pub mod syscall;
+pub mod sync;
+pub mod tty;
+pub mod fs;
 extern crate alloc;
 
 pub mod device;
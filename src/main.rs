@@ -5,7 +5,7 @@
 #![feature(panic_info_message)]
 
 use serica_os::{println, uart_println, uart_print};
-use serica_os::{interrupt, clock, new_memory, process, consts, device};
+use serica_os::{interrupt, clock, new_memory, process, consts, device, fs, tty};
 global_asm!(include_str!("boot/entry.asm"));
 
 
@@ -28,7 +28,7 @@ pub extern "C" fn os_start() -> ! {
     // ready to start scheduling. The last thing this
     // should do is start the timer.
     greet();
-    let mut my_uart = device::uart::Uart::new(0x1000_0000);
+    let mut my_uart = device::uart::Uart::new(consts::UART_BASE);
     my_uart.init();
 
 
@@ -45,8 +45,14 @@ pub extern "C" fn os_start() -> ! {
 
     }
 
-    process::init();
+    device::init();
+    fs::init();
+    process::init(process::SchedulerKind::MLFQ);
+    tty::init();
+    fs::cache::spawn_flusher();
+    device::console::spawn_flusher();
     clock::init();
+    device::enable_interrupts();
     process::run();
     uart_println!("UART: Hi");
 
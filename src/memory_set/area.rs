@@ -0,0 +1,106 @@
+use super::attr::MemoryAttr;
+use super::handler::MemoryHandler;
+use crate::new_memory::paging::Page;
+use crate::new_memory::paging::mapper::Mapper;
+use crate::new_memory::{Frame, swap};
+use crate::consts::PAGE_SIZE;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+// 一段连续的虚拟内存区域 [start, end) ，区域内的页拥有相同的权限和映射方式
+#[derive(Clone)]
+pub struct MemoryArea {
+    pub start: usize,
+    pub end: usize,
+    pub attr: MemoryAttr,
+    pub handler: Box<dyn MemoryHandler>,
+}
+
+impl MemoryArea {
+    pub fn new(start: usize, end: usize, attr: MemoryAttr, handler: Box<dyn MemoryHandler>) -> MemoryArea {
+        MemoryArea { start, end, attr, handler }
+    }
+
+    // 区域内的所有虚拟页
+    pub fn pages(&self) -> impl Iterator<Item = Page> {
+        Page::range_inclusive(Page::containing_address(self.start), Page::containing_address(self.end - 1))
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        addr >= self.start && addr < self.end
+    }
+
+    pub fn is_overlap_with(&self, start: usize, end: usize) -> bool {
+        let p1 = self.start / PAGE_SIZE;
+        let p2 = (self.end - 1) / PAGE_SIZE + 1;
+        let p3 = start / PAGE_SIZE;
+        let p4 = (end - 1) / PAGE_SIZE + 1;
+        !(p1 >= p4 || p3 >= p2)
+    }
+
+    pub fn map(&self, mapper: &mut Mapper) {
+        self.map_range(mapper, self.start, self.end);
+    }
+
+    pub fn unmap(&self, mapper: &mut Mapper) {
+        self.unmap_range(mapper, self.start, self.end);
+    }
+
+    // 只为 [start, end) 中的页建立映射，用于区域扩大的部分
+    pub fn map_range(&self, mapper: &mut Mapper, start: usize, end: usize) {
+        for page in pages_between(start, end) {
+            self.handler.map(mapper, page.start_address(), &self.attr);
+        }
+    }
+
+    // 只解除 [start, end) 中的页的映射，用于区域缩小或被部分释放的部分
+    pub fn unmap_range(&self, mapper: &mut Mapper, start: usize, end: usize) {
+        for page in pages_between(start, end) {
+            // 已经被换出的页只需要释放交换槽
+            if swap::discard(mapper, page) {
+                continue;
+            }
+            self.handler.unmap(mapper, page.start_address());
+        }
+    }
+
+    // 复制地址空间时在原页表中调用，收集区域内每一页需要共享给新地址空间的物理页帧
+    pub fn share(&self, mapper: &mut Mapper) -> Vec<Option<Frame>> {
+        self.pages()
+            .map(|page| {
+                // 被换出的页先换入，再和新的地址空间共享
+                self.swap_in(mapper, page);
+                self.handler.share(mapper, page.start_address())
+            })
+            .collect()
+    }
+
+    // page 被换出时将其换入，返回是否进行了换入
+    pub fn swap_in(&self, mapper: &mut Mapper, page: Page) -> bool {
+        swap::swap_in(mapper, page, self.attr.value())
+    }
+
+    pub fn clone_map(&self, mapper: &mut Mapper, frames: &[Option<Frame>]) {
+        for (page, src) in self.pages().zip(frames.iter()) {
+            self.handler.clone_map(mapper, page.start_address(), *src, &self.attr);
+        }
+    }
+
+    pub fn handle_page_fault(&self, mapper: &mut Mapper, addr: usize) -> bool {
+        if self.swap_in(mapper, Page::containing_address(addr)) {
+            return true;
+        }
+        self.handler.handle_page_fault(mapper, addr, &self.attr)
+    }
+}
+
+// [start, end) 覆盖到的所有虚拟页，start >= end 时为空
+fn pages_between(start: usize, end: usize) -> impl Iterator<Item = Page> {
+    let (first, last) = if start < end {
+        (Page::containing_address(start), Page::containing_address(end - 1))
+    } else {
+        // 构造一个空的范围
+        (Page::containing_address(PAGE_SIZE), Page::containing_address(0))
+    };
+    Page::range_inclusive(first, last)
+}
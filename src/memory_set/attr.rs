@@ -0,0 +1,45 @@
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryAttr(u32);
+
+// 了解riscv32页表项/页目录项 的结构，根据其结构设置相关属性即可
+impl MemoryAttr {
+    // 由于我们创建的页表需要是有效（valid）的，所以 new 函数中使用 1 进行初始化
+    pub fn new() -> MemoryAttr {
+        MemoryAttr(1)
+    }
+
+    pub fn set_readonly(mut self) -> MemoryAttr {
+        self.0 = self.0 | 0b10;
+        self
+    }
+
+    pub fn set_execute(mut self) -> MemoryAttr {
+        self.0 = self.0 | 0b1000;
+        self
+    }
+
+    pub fn set_WR(mut self) -> MemoryAttr {
+        self.0 = self.0 | 0b10 | 0b100;
+        self
+    }
+
+    // 用户态可以访问
+    pub fn set_user(mut self) -> MemoryAttr {
+        self.0 = self.0 | 0b10000;
+        self
+    }
+
+    // 页表项中的权限位
+    pub fn value(&self) -> u32 {
+        self.0
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.0 & 0b100 != 0
+    }
+
+    // 不可读也不可执行的页不能被映射（页表项会被当作指向下一级页表）
+    pub fn is_accessible(&self) -> bool {
+        self.0 & 0b1010 != 0
+    }
+}
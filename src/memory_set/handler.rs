@@ -0,0 +1,234 @@
+use super::attr::MemoryAttr;
+use crate::new_memory::{Frame, AreaFrameAllocator, FrameAllocator, frame_allocator, access_pa_via_va, swap};
+use crate::new_memory::paging::Page;
+use crate::new_memory::paging::mapper::Mapper;
+use crate::new_memory::paging::entry::EntryBits;
+use crate::consts::PAGE_SIZE;
+use alloc::boxed::Box;
+use core::ptr;
+
+// 一段内存区域中的虚拟页按照什么方式映射到物理页帧，由 MemoryHandler 决定
+pub trait MemoryHandler: Send + 'static {
+    fn box_clone(&self) -> Box<dyn MemoryHandler>;
+
+    // 建立 va 所在虚拟页的映射
+    fn map(&self, mapper: &mut Mapper, va: usize, attr: &MemoryAttr);
+
+    // 解除 va 所在虚拟页的映射，并回收其占用的物理页帧
+    fn unmap(&self, mapper: &mut Mapper, va: usize);
+
+    // 复制地址空间时在原页表中调用，返回新地址空间中 va 应当共享的物理页帧
+    fn share(&self, mapper: &mut Mapper, va: usize) -> Option<Frame>;
+
+    // 在复制出的地址空间中为 va 建立映射，src 为 share 返回的物理页帧
+    fn clone_map(&self, mapper: &mut Mapper, va: usize, src: Option<Frame>, attr: &MemoryAttr);
+
+    // 处理 va 处的缺页异常，返回 false 说明这次访问不合法
+    fn handle_page_fault(&self, mapper: &mut Mapper, va: usize, attr: &MemoryAttr) -> bool;
+}
+
+impl Clone for Box<dyn MemoryHandler> {
+    fn clone(&self) -> Box<dyn MemoryHandler> {
+        self.box_clone()
+    }
+}
+
+// 分配一个物理页帧并清零
+pub fn alloc_zeroed_frame() -> Frame {
+    let frame = frame_allocator::alloc_frame().expect("no more frames");
+    unsafe {
+        ptr::write_bytes(access_pa_via_va(frame.start_address()) as *mut u8, 0, PAGE_SIZE);
+    }
+    frame
+}
+
+// 复制一个物理页帧的内容
+pub fn copy_frame(src: Frame, dst: Frame) {
+    unsafe {
+        ptr::copy_nonoverlapping(
+            access_pa_via_va(src.start_address()) as *const u8,
+            access_pa_via_va(dst.start_address()) as *mut u8,
+            PAGE_SIZE,
+        );
+    }
+}
+
+// 写时复制：fork 时父子进程共享同一个物理页帧，可写的页被改为只读并打上 Cow 标记
+fn share_cow(mapper: &mut Mapper, va: usize) -> Option<Frame> {
+    let entry = mapper.entry_mut(Page::containing_address(va))?;
+    let frame = entry.pointed_frame().unwrap();
+    if entry.is_writable() {
+        entry.set_flags((entry.flags() & !EntryBits::Write.val()) | EntryBits::Cow.val());
+    }
+    frame_allocator::share_frame(frame);
+    Some(frame)
+}
+
+fn map_cow(mapper: &mut Mapper, va: usize, frame: Frame, attr: &MemoryAttr) {
+    let mut flags = attr.value();
+    if attr.is_writable() {
+        flags = (flags & !EntryBits::Write.val()) | EntryBits::Cow.val();
+    }
+    let page = Page::containing_address(va);
+    mapper.map_to(page, frame, flags, &mut AreaFrameAllocator);
+    swap::track(mapper, page);
+}
+
+// 第一次写入共享的页：如果已经没有其他引用，直接恢复写权限，否则复制出一个新的页帧
+fn handle_cow(mapper: &mut Mapper, va: usize, attr: &MemoryAttr) -> bool {
+    let page = Page::containing_address(va);
+    let entry = match mapper.entry_mut(page) {
+        Some(entry) if entry.is_cow() => entry,
+        _ => return false,
+    };
+    let frame = entry.pointed_frame().unwrap();
+    if frame_allocator::frame_ref_count(frame) == 1 {
+        entry.set_flags(attr.value());
+    } else {
+        let new_frame = frame_allocator::alloc_frame().expect("no more frames");
+        copy_frame(frame, new_frame);
+        entry.set(new_frame, attr.value());
+        frame_allocator::dealloc_frame(frame);
+        swap::track(mapper, page);
+        // 在原来的页帧上等待的线程需要按新的物理地址重新等待
+        crate::syscall::futex::frame_copied(frame);
+    }
+    true
+}
+
+// 线性映射：虚拟地址减去 offset 即为物理地址，例如内核和设备所在的区域
+#[derive(Debug, Clone)]
+pub struct Linear {
+    offset: usize,
+}
+
+impl Linear {
+    pub fn new(offset: usize) -> Self {
+        Linear { offset }
+    }
+}
+
+impl MemoryHandler for Linear {
+    fn box_clone(&self) -> Box<dyn MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, mapper: &mut Mapper, va: usize, attr: &MemoryAttr) {
+        let frame = Frame::containing_address(va - self.offset);
+        mapper.map_to(Page::containing_address(va), frame, attr.value(), &mut AreaFrameAllocator);
+    }
+
+    fn unmap(&self, mapper: &mut Mapper, va: usize) {
+        // 物理页帧并不属于这段区域，只解除映射
+        mapper.unmap(Page::containing_address(va), &mut AreaFrameAllocator);
+    }
+
+    fn share(&self, _mapper: &mut Mapper, _va: usize) -> Option<Frame> {
+        None
+    }
+
+    fn clone_map(&self, mapper: &mut Mapper, va: usize, _src: Option<Frame>, attr: &MemoryAttr) {
+        self.map(mapper, va, attr);
+    }
+
+    fn handle_page_fault(&self, _mapper: &mut Mapper, _va: usize, _attr: &MemoryAttr) -> bool {
+        false
+    }
+}
+
+// 按页分配：映射时立即为每一页分配一个物理页帧
+#[derive(Debug, Clone)]
+pub struct ByFrame;
+
+impl ByFrame {
+    pub fn new() -> Self {
+        ByFrame
+    }
+}
+
+impl MemoryHandler for ByFrame {
+    fn box_clone(&self) -> Box<dyn MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, mapper: &mut Mapper, va: usize, attr: &MemoryAttr) {
+        let page = Page::containing_address(va);
+        let frame = alloc_zeroed_frame();
+        mapper.map_to(page, frame, attr.value(), &mut AreaFrameAllocator);
+        swap::track(mapper, page);
+    }
+
+    fn unmap(&self, mapper: &mut Mapper, va: usize) {
+        let page = Page::containing_address(va);
+        let frame = mapper.translate_page(page).expect("page is not mapped");
+        swap::untrack(mapper, page);
+        mapper.unmap(page, &mut AreaFrameAllocator);
+        frame_allocator::dealloc_frame(frame);
+    }
+
+    fn share(&self, mapper: &mut Mapper, va: usize) -> Option<Frame> {
+        share_cow(mapper, va)
+    }
+
+    fn clone_map(&self, mapper: &mut Mapper, va: usize, src: Option<Frame>, attr: &MemoryAttr) {
+        map_cow(mapper, va, src.expect("page is not mapped"), attr);
+    }
+
+    fn handle_page_fault(&self, mapper: &mut Mapper, va: usize, attr: &MemoryAttr) -> bool {
+        handle_cow(mapper, va, attr)
+    }
+}
+
+// 延迟分配：映射时只是保留这段虚拟地址，第一次访问触发缺页异常时才分配物理页帧
+#[derive(Debug, Clone)]
+pub struct Delay;
+
+impl Delay {
+    pub fn new() -> Self {
+        Delay
+    }
+}
+
+impl MemoryHandler for Delay {
+    fn box_clone(&self) -> Box<dyn MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, _mapper: &mut Mapper, _va: usize, _attr: &MemoryAttr) {
+        // 什么都不做，等到缺页时再分配
+    }
+
+    fn unmap(&self, mapper: &mut Mapper, va: usize) {
+        let page = Page::containing_address(va);
+        if let Some(frame) = mapper.translate_page(page) {
+            swap::untrack(mapper, page);
+            mapper.unmap(page, &mut AreaFrameAllocator);
+            frame_allocator::dealloc_frame(frame);
+        }
+    }
+
+    fn share(&self, mapper: &mut Mapper, va: usize) -> Option<Frame> {
+        share_cow(mapper, va)
+    }
+
+    fn clone_map(&self, mapper: &mut Mapper, va: usize, src: Option<Frame>, attr: &MemoryAttr) {
+        // 只共享已经分配了物理页帧的页，其余的页在新地址空间中依然延迟分配
+        if let Some(src) = src {
+            map_cow(mapper, va, src, attr);
+        }
+    }
+
+    fn handle_page_fault(&self, mapper: &mut Mapper, va: usize, attr: &MemoryAttr) -> bool {
+        let page = Page::containing_address(va);
+        if mapper.translate_page(page).is_some() {
+            return handle_cow(mapper, va, attr);
+        }
+        if !attr.is_accessible() {
+            return false;
+        }
+        let frame = alloc_zeroed_frame();
+        mapper.map_to(page, frame, attr.value(), &mut AreaFrameAllocator);
+        swap::track(mapper, page);
+        true
+    }
+}
@@ -1,24 +1,336 @@
-pub struct MemoryAttr(u32);
+//! 地址空间
+//!
+//! 每个用户进程都拥有一个 MemorySet ，它由若干段互不重叠的 MemoryArea 和一张页表组成。
+//! 内核的映射在创建页表时从内核页表中共享过来，因此切换到任何一个地址空间后内核都能正常运行。
 
-// 了解riscv32页表项/页目录项 的结构，根据其结构设置相关属性即可
-impl MemoryAttr {
-    // 由于我们创建的页表需要是有效（valid）的，所以 new 函数中使用 1 进行初始化
-    pub fn new() -> MemoryAttr {
-        MemoryAttr(1)
+pub mod attr;
+pub mod area;
+pub mod handler;
+
+use attr::MemoryAttr;
+use area::MemoryArea;
+use handler::MemoryHandler;
+use crate::new_memory::{Frame, AreaFrameAllocator, FrameAllocator, TEMPORARY_PAGE, access_pa_via_va, frame_allocator};
+use crate::new_memory::paging::{Page, ActivePageTable, InactivePageTable, ENTRY_COUNT};
+use crate::new_memory::paging::mapper::Mapper;
+use crate::new_memory::paging::temporary_page::TemporaryPage;
+use crate::new_memory::paging::table::{Table, Level2};
+use crate::consts::{PAGE_SIZE, KERNEL_OFFSET, USER_STACK_OFFSET, USER_STACK_SIZE, USER_STACK_LIMIT};
+use crate::riscv::{instructions, register::satp};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ptr;
+
+pub struct MemorySet {
+    areas: Vec<MemoryArea>,
+    page_table: InactivePageTable,
+}
+
+impl MemorySet {
+    // 新建一个只包含内核映射的地址空间
+    pub fn new() -> MemorySet {
+        let mut allocator = AreaFrameAllocator::new();
+        let mut active_table = unsafe { ActivePageTable::new() };
+        let mut temporary_page = TemporaryPage::new(TEMPORARY_PAGE, &mut allocator);
+        let mut page_table = {
+            let frame = allocator.allocate_frame().expect("no more frames");
+            InactivePageTable::new(frame, &mut active_table, &mut temporary_page)
+        };
+        page_table.map_kernel();
+        MemorySet {
+            areas: Vec::new(),
+            page_table,
+        }
+    }
+
+    // 在这个地址空间的页表中执行 f
+    pub fn with<F>(&mut self, f: F)
+        where F: FnOnce(&mut Mapper)
+    {
+        let mut active_table = unsafe { ActivePageTable::new() };
+        let mut temporary_page = TemporaryPage::new(TEMPORARY_PAGE, &mut AreaFrameAllocator);
+        active_table.with(&mut self.page_table, &mut temporary_page, f);
+    }
+
+    // 加入一段新的内存区域 [start, end) 并建立映射，
+    // data 为需要复制到区域开头的数据在内核中的 (起始地址, 长度)
+    pub fn push(&mut self,
+                start: usize,
+                end: usize,
+                attr: MemoryAttr,
+                handler: impl MemoryHandler,
+                data: Option<(usize, usize)>) {
+        assert!(start < end, "invalid memory area");
+        assert!(self.test_free_area(start, end), "memory area overlap");
+        let area = MemoryArea::new(start, end, attr, Box::new(handler));
+        self.with(|mapper| {
+            area.map(mapper);
+            if let Some((src, length)) = data {
+                copy_to_area(mapper, &area, area.start, src, length);
+            }
+        });
+        self.areas.push(area);
+    }
+
+    // 将内核中 [src, src + length) 的数据复制到 addr 开始的位置，这段地址需要位于同一个已有的区域中
+    pub fn copy_to(&mut self, addr: usize, src: usize, length: usize) {
+        if length == 0 {
+            return;
+        }
+        let area = self.areas
+            .iter()
+            .find(|area| area.contains(addr))
+            .expect("copy to an unmapped address");
+        assert!(addr + length <= area.end, "copy out of the memory area");
+        let mut active_table = unsafe { ActivePageTable::new() };
+        let mut temporary_page = TemporaryPage::new(TEMPORARY_PAGE, &mut AreaFrameAllocator);
+        active_table.with(&mut self.page_table, &mut temporary_page, |mapper| {
+            copy_to_area(mapper, area, addr, src, length);
+        });
+    }
+
+    // 从地址空间中移除 [start, end) ，与之部分重叠的区域会被切开，只保留范围之外的部分
+    pub fn pop(&mut self, start: usize, end: usize) {
+        let areas = core::mem::replace(&mut self.areas, Vec::new());
+        let mut removed = Vec::new();
+        for area in areas {
+            if !area.is_overlap_with(start, end) {
+                self.areas.push(area);
+                continue;
+            }
+            if area.start < start {
+                let mut left = area.clone();
+                left.end = start;
+                self.areas.push(left);
+            }
+            if area.end > end {
+                let mut right = area.clone();
+                right.start = end;
+                self.areas.push(right);
+            }
+            removed.push(area);
+        }
+        self.with(|mapper| {
+            for area in removed.iter() {
+                area.unmap_range(mapper, start.max(area.start), end.min(area.end));
+            }
+        });
+    }
+
+    // 将起始地址为 start 的区域调整为 [new_start, new_end) ，新旧范围必须有交集，
+    // 新增的部分不能与其他区域重叠
+    pub fn resize_area(&mut self, start: usize, new_start: usize, new_end: usize) -> bool {
+        assert!(new_start < new_end, "invalid memory area");
+        let index = match self.areas.iter().position(|area| area.start == start) {
+            Some(index) => index,
+            None => return false,
+        };
+        let overlap = self.areas
+            .iter()
+            .enumerate()
+            .any(|(i, area)| i != index && area.is_overlap_with(new_start, new_end));
+        if overlap {
+            return false;
+        }
+        let old = self.areas[index].clone();
+        assert!(old.is_overlap_with(new_start, new_end), "memory area moved");
+        // 以页为单位计算需要新增和回收的部分
+        let old_start = page_floor(old.start);
+        let old_end = page_ceil(old.end);
+        let new_page_start = page_floor(new_start);
+        let new_page_end = page_ceil(new_end);
+        self.with(|mapper| {
+            old.unmap_range(mapper, old_start, old_end.min(new_page_start));
+            old.unmap_range(mapper, old_start.max(new_page_end), old_end);
+            old.map_range(mapper, new_page_start, new_page_end.min(old_start));
+            old.map_range(mapper, new_page_start.max(old_end), new_page_end);
+        });
+        let area = &mut self.areas[index];
+        area.start = new_start;
+        area.end = new_end;
+        true
+    }
+
+    // 从 hint 开始寻找一段长度为 len 的空闲虚拟地址，不超过 limit ，也不经过与内核共享的 p2 表项
+    pub fn find_free_area(&self, hint: usize, len: usize, limit: usize) -> Option<usize> {
+        // 候选的起点：hint 本身，以及 hint 之后每个区域和每个与内核共享的 p2 表项的结尾
+        let slot_size = PAGE_SIZE * ENTRY_COUNT;
+        let mut candidates: Vec<usize> = self.areas
+            .iter()
+            .map(|area| page_ceil(area.end))
+            .chain((0..KERNEL_OFFSET / slot_size).filter(|&i| is_kernel_entry(i)).map(|i| (i + 1) * slot_size))
+            .filter(|&end| end > hint)
+            .collect();
+        candidates.push(page_ceil(hint));
+        candidates.sort();
+        candidates.into_iter().find(|&start| {
+            start + len <= limit && is_user_range(start, start + len) && self.test_free_area(start, start + len)
+        })
+    }
+
+    // [start, end) 是否与已有的区域都不重叠
+    pub fn test_free_area(&self, start: usize, end: usize) -> bool {
+        self.areas
+            .iter()
+            .find(|area| area.is_overlap_with(start, end))
+            .is_none()
+    }
+
+    // 找到 addr 所在的区域
+    pub fn find_area(&self, addr: usize) -> Option<&MemoryArea> {
+        self.areas.iter().find(|area| area.contains(addr))
+    }
+
+    // 切换到这个地址空间
+    pub unsafe fn activate(&self) {
+        satp::write(self.token());
+        instructions::flush_tlb();
+    }
+
+    pub fn token(&self) -> usize {
+        self.page_table.token()
+    }
+
+    // 复制出一个内容完全相同的地址空间，按页分配的区域采用写时复制，父子双方共享物理页帧
+    pub fn clone(&mut self) -> MemorySet {
+        let mut new_set = MemorySet::new();
+        let areas = self.areas.clone();
+        let mut frames = Vec::new();
+        self.with(|mapper| {
+            for area in areas.iter() {
+                frames.push(area.share(mapper));
+            }
+        });
+        new_set.with(|mapper| {
+            for (area, frames) in areas.iter().zip(frames.iter()) {
+                area.clone_map(mapper, frames);
+            }
+        });
+        new_set.areas = areas;
+        new_set
+    }
+
+    // 处理当前地址空间中 addr 处的缺页异常，返回 false 说明这次访问不合法
+    pub fn handle_page_fault(&mut self, addr: usize) -> bool {
+        assert_eq!(self.token(), satp::read(), "memory set is not active");
+        if self.find_area(addr).is_none() && !self.grow_stack(addr) {
+            return false;
+        }
+        let area = self.find_area(addr).unwrap();
+        // 自己就是当前的页表，直接通过递归映射修改
+        let mut active_table = unsafe { ActivePageTable::new() };
+        if !area.handle_page_fault(&mut active_table, addr) {
+            return false;
+        }
+        instructions::flush_tlb();
+        true
     }
 
-    pub fn set_readonly(mut self) -> MemoryAttr {
-        self.0 = self.0 | 0b10;
-        self
+    // 当前地址空间中 addr 对应的物理地址。尚未分配、已被换出或者写时复制的页先按写访问处理缺页，
+    // 这样得到的物理地址在这一页被换出之前不会再变化
+    pub fn translate(&mut self, addr: usize) -> Option<usize> {
+        assert_eq!(self.token(), satp::read(), "memory set is not active");
+        let page = Page::containing_address(addr);
+        let offset = addr & (PAGE_SIZE - 1);
+        let mut active_table = unsafe { ActivePageTable::new() };
+        match active_table.entry_mut(page) {
+            Some(entry) if !entry.is_cow() => {}
+            _ => {
+                if !self.handle_page_fault(addr) {
+                    return None;
+                }
+            }
+        }
+        active_table.translate_page(page).map(|frame| frame.start_address() + offset)
     }
 
-    pub fn set_execute(mut self) -> MemoryAttr {
-        self.0 = self.0 | 0b1000;
-        self
+    // 访问栈底之下不超过 USER_STACK_LIMIT 的地址时，栈向下增长到 addr 所在的页
+    fn grow_stack(&mut self, addr: usize) -> bool {
+        let stack_top = USER_STACK_OFFSET + USER_STACK_SIZE;
+        if addr >= stack_top || addr < stack_top - USER_STACK_LIMIT {
+            return false;
+        }
+        let stack_start = match self.areas.iter().find(|area| area.end == stack_top) {
+            Some(area) => area.start,
+            None => return false,
+        };
+        addr < stack_start && self.resize_area(stack_start, page_floor(addr), stack_top)
     }
 
-    pub fn set_WR(mut self) -> MemoryAttr {
-        self.0 = self.0 | 0b10 | 0b100;
-        self
+    // 解除所有区域的映射并回收其物理页帧
+    pub fn clear(&mut self) {
+        let areas = core::mem::replace(&mut self.areas, Vec::new());
+        self.with(|mapper| {
+            for area in areas.iter() {
+                area.unmap(mapper);
+            }
+        });
+    }
+}
+
+impl Drop for MemorySet {
+    fn drop(&mut self) {
+        self.clear();
+        // 用户区域的 p1 页表已经在 unmap 时回收，剩下的是共享的内核 p1 页表，只回收 p2 页表本身
+        let p2 = unsafe {
+            &mut *(access_pa_via_va(self.page_table.p2_frame.start_address()) as *mut Table<Level2>)
+        };
+        for i in 0..KERNEL_OFFSET >> 22 {
+            if p2[i].is_valid() && !is_kernel_entry(i) {
+                frame_allocator::dealloc_frame(p2[i].pointed_frame().unwrap());
+            }
+        }
+        frame_allocator::dealloc_frame(self.page_table.p2_frame);
+    }
+}
+
+pub fn page_floor(addr: usize) -> usize {
+    addr & !(PAGE_SIZE - 1)
+}
+
+pub fn page_ceil(addr: usize) -> usize {
+    (addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+// p2 页表中第 index 项是否是从内核页表中共享来的
+// [start, end) 能否用于用户的映射：位于 KERNEL_OFFSET 之下，并且不经过与内核共享的 p2 表项。
+// 内核在低地址映射了 PLIC 、串口和 virtio 设备的寄存器，这些 p1 页表被所有地址空间共用，
+// 在其中建立用户映射会修改所有地址空间，解除映射时还可能把共享的 p1 页表当作空表回收
+pub fn is_user_range(start: usize, end: usize) -> bool {
+    if start >= end || end > KERNEL_OFFSET {
+        return false;
+    }
+    let first = Page::containing_address(start).p2_index();
+    let last = Page::containing_address(end - 1).p2_index();
+    (first..=last).all(|i| !is_kernel_entry(i))
+}
+
+fn is_kernel_entry(index: usize) -> bool {
+    let kernel_p2 = unsafe {
+        &*(access_pa_via_va(crate::new_memory::kernel_root_frame().start_address()) as *const Table<Level2>)
+    };
+    index < ENTRY_COUNT - 2 && kernel_p2[index].is_valid()
+}
+
+// 将内核中 [src, src + length) 的数据复制到区域中 start 开始的位置，此时 mapper 对应的页表不一定处于激活状态，
+// 因此通过页表找到物理页帧后再经过线性映射写入
+fn copy_to_area(mapper: &mut Mapper, area: &MemoryArea, start: usize, src: usize, length: usize) {
+    let end = start + length;
+    let mut va = start;
+    while va < end {
+        let page = Page::containing_address(va);
+        let page_end = page.start_address() + PAGE_SIZE;
+        let copy_end = page_end.min(end);
+        // 映射后面的页时可能因为物理页帧不足换出了前面的页
+        area.swap_in(mapper, page);
+        let frame: Frame = mapper.translate_page(page).expect("copy to an unmapped page");
+        unsafe {
+            ptr::copy_nonoverlapping(
+                (src + va - start) as *const u8,
+                (access_pa_via_va(frame.start_address()) + va - page.start_address()) as *mut u8,
+                copy_end - va,
+            );
+        }
+        va = copy_end;
     }
-}
\ No newline at end of file
+}
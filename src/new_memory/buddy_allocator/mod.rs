@@ -32,13 +32,14 @@ impl BuddyAllocator {
     pub fn alloc(&mut self, alloc_size: usize) -> Option<usize> {
         // 向上”取整“后再取对数
         let size = log2_up(alloc_size) as i8;
+        // 根节点记录着最大的空闲块，它也放不下时内存不足，由调用者决定是否换出页面后重试
+        if size > self.nodes[0] {
+            return None;
+        }
         let mut location = 0;
         let mut height = self.level - 1;
         let ret;
         while height != 0 {
-            if size > self.nodes[location] {
-                panic!("memory is not enough ");
-            }
             if self.nodes[(location << 1) + 1] >= size {
                 location = (location << 1) + 1;
             }
@@ -1,9 +1,11 @@
 //use crate::memory::buddy_allocator::{BuddyAllocator, log2_down};
 use super::buddy_allocator::{BuddyAllocator, log2_down};
 use super::Frame;
+use super::swap;
 
 use lazy_static::*;
-use spin::Mutex;
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::collections::BTreeMap;
 //use crate::riscv::addr::{Frame, PhysAddr};
 use crate::consts;
 
@@ -13,21 +15,37 @@ lazy_static! {
         = Mutex::new(BuddyAllocator::new());
 }
 
-pub fn init(start: usize, length: usize) {
-    // 以page为单位进行管理和分配
+// 被多个页表共享的页帧的引用计数，不在其中的页帧引用计数为 1
+lazy_static! {
+    static ref FRAME_REF_COUNT: Mutex<BTreeMap<usize, usize>>
+        = Mutex::new(BTreeMap::new());
+}
 
-    BUDDY_ALLOCATOR.lock()
-        .init(log2_down((start + length - consts::MEMORY_OFFSET) / consts::PAGE_SIZE) as u8);
+// 返回伙伴分配器管理的物理内存的结束地址，之后的内存不会被分配出去
+pub fn init(start: usize, length: usize) -> usize {
+    // 以page为单位进行管理和分配
+    // 分配器的第 level - 1 层是叶子，共管理 2^(level - 1) 个页帧
+    let level = log2_down((start + length - consts::MEMORY_OFFSET) / consts::PAGE_SIZE) as u8;
+    BUDDY_ALLOCATOR.lock().init(level);
     // here we devide PAGE_SIZE because memory are considered as pages
 //    BUDDY_ALLOCATOR.lock()
 //        .init(log2_down((consts::MEMORY_END - consts::MEMORY_OFFSET) / consts::PAGE_SIZE) as u8);
 
     alloc_frames((start - consts::MEMORY_OFFSET) / consts::PAGE_SIZE);
     println!("++++init frame allocator succeed!++++");
+    consts::MEMORY_OFFSET + (1 << (level - 1)) * consts::PAGE_SIZE
 }
 
+// 物理页帧耗尽时换出一个用户页，直到分配成功或者没有可以换出的页
 pub fn alloc_frame() -> Option<Frame> {
-    alloc_frames(1)
+    loop {
+        if let Some(frame) = alloc_frames(1) {
+            return Some(frame);
+        }
+        if !swap::swap_out() {
+            return None;
+        }
+    }
 }
 // buddy_allocator::alloc 返回的是内存块编号，类型为 Option<usize> ，
 // 所以需要将其转换为物理地址，然后通过 Frame::of_addr 转换为物理帧。
@@ -40,10 +58,30 @@ pub fn alloc_frames(size: usize) -> Option<Frame> {
     ret.map(|addr| Frame::containing_address(addr))
 }
 
+// 页帧被共享时引用计数减一，最后一个引用被释放时才真正回收页帧
 pub fn dealloc_frame(target: Frame) {
+    {
+        let mut ref_count = FRAME_REF_COUNT.lock();
+        if let Some(count) = ref_count.get_mut(&target.number) {
+            *count -= 1;
+            if *count == 1 {
+                ref_count.remove(&target.number);
+            }
+            return;
+        }
+    }
     dealloc_frames(target, 1);
 }
 
+// 增加一个对 target 的引用
+pub fn share_frame(target: Frame) {
+    *FRAME_REF_COUNT.lock().entry(target.number).or_insert(1) += 1;
+}
+
+pub fn frame_ref_count(target: Frame) -> usize {
+    FRAME_REF_COUNT.lock().get(&target.number).cloned().unwrap_or(1)
+}
+
 pub fn dealloc_frames(target: Frame, size: usize) {
     BUDDY_ALLOCATOR
         .lock()
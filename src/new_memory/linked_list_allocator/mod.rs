@@ -12,7 +12,7 @@ use core::mem;
 use core::ops::Deref;   // 可扩展`*`的用法
 use core::ptr::NonNull;
 use hole::{Hole, HoleList};
-use spin::Mutex;
+use crate::sync::SpinNoIrqLock as Mutex; // 时钟中断中也可能分配内存，持有堆的锁时需要关中断
 
 mod hole;
 
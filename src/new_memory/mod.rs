@@ -2,10 +2,11 @@ pub mod linked_list_allocator;
 pub mod buddy_allocator;
 pub mod frame_allocator;
 pub mod paging;
+pub mod swap;
 
 use crate::consts;
 use crate::HEAP_ALLOCATOR;
-use crate::riscv::register::sstatus;
+use crate::riscv::register::{sstatus, satp};
 use crate::new_memory::paging::{Page, ActivePageTable};
 use crate::new_memory::paging::entry::{EntryBits, Entry};
 use core::ptr::null_mut;
@@ -96,7 +97,7 @@ pub fn init() {
     let memory_size = consts::MEMORY_END - memory_start;
 
 
-    frame_allocator::init(memory_start, memory_size);
+    let managed_end = frame_allocator::init(memory_start, memory_size);
 
 
 
@@ -105,10 +106,45 @@ pub fn init() {
     test_paging(&mut allocator);
     print_os_layout();
     remap_kernel(&mut allocator);
+    unsafe {
+        KERNEL_ROOT_FRAME = Frame::containing_address(satp::root_table_paddr()).number;
+    }
+    // 伙伴分配器只管理 2 的幂个页帧，物理内存的最后 SWAP_SIZE 字节落在它不管理的部分时用作交换区。
+    // 交换区位于线性映射的范围内，重新映射内核之后才能访问
+    let swap_start = consts::MEMORY_END - consts::SWAP_SIZE;
+    if swap_start >= managed_end {
+        swap::init(alloc::boxed::Box::new(swap::RamSwap::new(
+            access_pa_via_va(swap_start),
+            consts::SWAP_SIZE,
+        )));
+    } else {
+        println!("swap: no unmanaged memory left, swap is disabled");
+    }
 
     println!("OK!");
 }
 
+/// 临时页，用于在修改其他页表时映射其页表帧。位于 KERNEL_OFFSET 之上、内核的线性映射和递归映射之间，
+/// 用户程序不能在这里建立映射
+pub const TEMPORARY_PAGE: Page = Page { number: 0xff000 };
+
+/// 内核页表的根页帧号，所有用户页表都从这里共享内核的映射
+static mut KERNEL_ROOT_FRAME: usize = 0;
+
+pub fn kernel_root_frame() -> Frame {
+    Frame { number: unsafe { KERNEL_ROOT_FRAME } }
+}
+
+/// 通过内核的线性映射访问物理地址
+pub fn access_pa_via_va(pa: usize) -> usize {
+    pa + consts::KERNEL_OFFSET - consts::MEMORY_OFFSET
+}
+
+/// access_pa_via_va 的逆映射，va 必须位于内核的线性映射中，例如内核堆、内核栈和 access_pa_via_va 的结果
+pub fn virt_to_phys(va: usize) -> usize {
+    va - consts::KERNEL_OFFSET + consts::MEMORY_OFFSET
+}
+
 /// table_level: 2, 1, 0, where 0 represents a frame
 /// index: [p2idx, p1idx, p0idx]
 pub fn print_entry(table_level: usize, index: [usize; 3]) {
@@ -165,8 +201,7 @@ pub fn remap_kernel<A>(allocator: &mut A)
 
     println!("remap kernel");
     use self::paging::{InactivePageTable ,temporary_page::TemporaryPage};
-    let mut temporary_page = TemporaryPage::new(Page { number: 0x20000 },
-                                                allocator);
+    let mut temporary_page = TemporaryPage::new(TEMPORARY_PAGE, allocator);
 
     let mut active_table = unsafe { ActivePageTable::new() };
     let mut new_table = {
@@ -181,6 +216,16 @@ pub fn remap_kernel<A>(allocator: &mut A)
         mapper.identity_map(uart_frame, EntryBits::ReadWrite.val(), allocator);
         println!("\n\tidentity map uart ......\n");
         print_entry(0, [1023, uart_frame.p2_index(), uart_frame.p1_index()]);
+        // virtio-mmio 设备的寄存器，每个槽一页
+        for slot in 0..consts::VIRTIO_MMIO_SLOTS {
+            let frame = Frame::containing_address(consts::VIRTIO_MMIO_BASE + slot * consts::PAGE_SIZE);
+            mapper.identity_map(frame, EntryBits::ReadWrite.val(), allocator);
+        }
+        // PLIC 的优先级和使能寄存器，以及各个上下文的阈值和 claim 寄存器
+        for offset in (0..0x3000).step_by(consts::PAGE_SIZE).chain((0x20_0000..0x20_2000).step_by(consts::PAGE_SIZE)) {
+            let frame = Frame::containing_address(consts::PLIC_BASE + offset);
+            mapper.identity_map(frame, EntryBits::ReadWrite.val(), allocator);
+        }
 //
 //        print_os_layout();
 //        println!("==========remap==========\n");
@@ -221,7 +266,13 @@ pub fn remap_kernel<A>(allocator: &mut A)
         for frame in Frame::range_inclusive(boot_start, boot_end) {
             mapper.linear_map(frame, offset as u32, EntryBits::ReadWrite.val(), allocator);
         }
-//
+        // 内核之后的物理内存也按同样的偏移映射，之后便可以通过 access_pa_via_va 直接访问任意物理页帧
+        println!("\n\tremap physical memory......\n");
+        let mem_start = Frame::containing_address(end as usize - offset - 1 + consts::PAGE_SIZE);
+        let mem_end = Frame::containing_address(consts::MEMORY_END - 1);
+        for frame in Frame::range_inclusive(mem_start, mem_end) {
+            mapper.linear_map(frame, offset as u32, EntryBits::ReadWrite.val(), allocator);
+        }
     });
 //
     let old_table = active_table.switch(new_table);
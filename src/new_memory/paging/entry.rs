@@ -12,6 +12,9 @@ pub enum EntryBits {
     Global = 1 << 5,
     Access = 1 << 6,
     Dirty = 1 << 7,
+    // RSW bits, reserved for the supervisor
+    Cow = 1 << 8, // shared read-only by fork, copy the frame on first write
+    Swapped = 1 << 9, // not valid, the page lives in the swap slot stored in the PPN field
 
     // Convenience combinations
     ReadWrite = 1 << 1 | 1 << 2,
@@ -82,6 +85,53 @@ impl Entry {
         self.entry
     }
 
+    // The low 10 bits: V R W X U G A D and the two RSW bits
+    pub fn flags(&self) -> u32 {
+        self.entry & 0x3ff
+    }
+
+    pub fn set_flags(&mut self, flags: u32) {
+        self.entry = (self.entry & !0x3ff) | (flags & 0x3ff);
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.entry & EntryBits::Write.val() != 0
+    }
+
+    pub fn is_cow(&self) -> bool {
+        self.entry & EntryBits::Cow.val() != 0
+    }
+
+    pub fn is_accessed(&self) -> bool {
+        self.entry & EntryBits::Access.val() != 0
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.entry & EntryBits::Dirty.val() != 0
+    }
+
+    pub fn is_swapped(&self) -> bool {
+        self.is_invalid() && self.entry & EntryBits::Swapped.val() != 0
+    }
+
+    /// An entry with nothing in it, a swapped out page is invalid but still in use.
+    pub fn is_unused(&self) -> bool {
+        self.entry == 0
+    }
+
+    /// Mark the page as swapped out to the given slot, the entry is left invalid.
+    pub fn set_swapped(&mut self, slot: usize) {
+        self.entry = (slot as u32) << 10 | EntryBits::Swapped.val();
+    }
+
+    pub fn swap_slot(&self) -> Option<usize> {
+        if self.is_swapped() {
+            Some(self.entry as usize >> 10)
+        } else {
+            None
+        }
+    }
+
     pub fn pointed_frame(&self) -> Option<Frame> {
         if self.is_valid() {
             Some(Frame::containing_address(
@@ -26,22 +26,39 @@ impl Mapper {
     /// Returns `None` if the address is not mapped.
     pub fn translate(&self, virtual_address: usize) -> Option<usize> {
         let offset = virtual_address & (PAGE_SIZE - 1);
-        println!("translate virt addr 0x{:x?}", virtual_address);
         self.translate_page(Page::containing_address(virtual_address))
             .map(|frame| frame.number * PAGE_SIZE + offset)
     }
 
     pub fn translate_page(&self, page: Page) -> Option<Frame> {
-        println!("translate page {:x?}", page);
         let p1 = self.p2().next_table(page.p2_index());
+        p1.and_then(|p1| p1[page.p1_index()].pointed_frame())
+    }
 
 
-        let res = p1.and_then(|p1| p1[page.p1_index()].pointed_frame());
+    /// Returns the leaf entry of the given page if it is mapped.
+    pub fn entry_mut(&mut self, page: Page) -> Option<&mut Entry> {
+        let p1 = self.p2_mut().next_table_mut(page.p2_index())?;
+        let entry = &mut p1[page.p1_index()];
+        if entry.is_valid() {
+            Some(entry)
+        } else {
+            None
+        }
+    }
 
-        println!("translate page: {:x?} => {:x?}", page, res);
-        res
+    /// Returns the leaf entry of the given page even if it is not valid,
+    /// as long as its P1 table exists. Used to find swapped out pages.
+    pub fn raw_entry_mut(&mut self, page: Page) -> Option<&mut Entry> {
+        let p1 = self.p2_mut().next_table_mut(page.p2_index())?;
+        Some(&mut p1[page.p1_index()])
     }
 
+    /// The frame of the P2 table this mapper is working on. Through the recursive
+    /// mapping, entry 1022 of the current P2 always points to the P2 itself.
+    pub fn root_frame(&self) -> Frame {
+        self.p2()[ENTRY_COUNT - 2].pointed_frame().unwrap()
+    }
 
     /// Maps the page to the frame with the provided flags.
     /// The `VALID` flag is added by default. Needs a
@@ -88,40 +105,28 @@ impl Mapper {
         self.map_to(page, frame, flags, allocator)
     }
 
-    /// Unmaps the given page and adds all freed frames to the given
-    /// `FrameAllocator`.
+    /// Unmaps the given page. The mapped frame is left to the caller,
+    /// the p1 table is returned to the given `FrameAllocator` once it is empty.
     pub fn unmap<A>(&mut self, page: Page, allocator: &mut A)
         where A: FrameAllocator
     {
         use crate::riscv::instructions;
 
-        println!("active table unmap page: {:x?}", page);
-
         assert!(self.translate(page.start_address()).is_some());
 
-        let mut del_p1 = true;
-
         let p1 = self.p2_mut()
             .next_table_mut(page.p2_index())
             .expect("mapping code does not support huge pages");
-        let frame = p1[page.p1_index()].pointed_frame().unwrap();
-        println!("{:x?} is valid, start to unmap, the pointed frame is {:x?}", page, frame);
         p1[page.p1_index()].set_entry(0);
-        for i in 0..ENTRY_COUNT {
-            if p1[i].is_valid() {
-                del_p1 = false;
-                break;
-            }
-        }
+        let del_p1 = (0..ENTRY_COUNT).all(|i| p1[i].is_unused());
         instructions::flush_tlb();
-        // TODO free p(1,2,3) table if empty
-//        allocator.deallocate_frame(frame);
 
         if del_p1 {
-            allocator.deallocate_frame(self.p2()[page.p2_index()].pointed_frame().unwrap());
+            let p1_frame = self.p2()[page.p2_index()].pointed_frame().unwrap();
+            self.p2_mut()[page.p2_index()].set_entry(0);
+            instructions::flush_tlb();
+            allocator.deallocate_frame(p1_frame);
         }
-
-        self.p2_mut()[page.p2_index()].set_entry(0);
         assert!(self.translate(page.start_address()).is_none());
     }
 }
\ No newline at end of file
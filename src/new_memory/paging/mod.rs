@@ -3,13 +3,14 @@ pub mod table;
 pub mod temporary_page;
 pub mod mapper;
 
-const ENTRY_COUNT: usize = 1024;
+pub const ENTRY_COUNT: usize = 1024;
 use super::{PAGE_ORDER, PAGE_SIZE, Frame, FrameAllocator};
 use self::temporary_page::TemporaryPage;
 use self::mapper::Mapper;
 use core::ops::{Deref, DerefMut};
 use self::entry::*;
-use crate::new_memory::print_entry;
+use self::table::{Table, Level2};
+use crate::new_memory::{print_entry, access_pa_via_va, kernel_root_frame};
 
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -100,40 +101,24 @@ impl ActivePageTable {
         where F: FnOnce(&mut Mapper)
     {
         use crate::riscv::{instructions, register::satp};
-        println!("\n========== with ==========");
         {
             let backup = Frame::containing_address(satp::root_table_paddr());
 
-
             // map temporary_page to current p2 table
-            let tp2 = temporary_page.p2_index();
-            let tp1 = temporary_page.p1_index();
             let p2_table = temporary_page.map_table_frame(backup.clone(), self);
-            println!("backup: {:x?}\ntemp page map:", backup);
-            print_entry(2, [tp2, 0, 0]);
-            print_entry(1, [tp2, tp1, 0]);
-            println!("... overwrite recursive mapping");
 
-//            // overwrite recursive mapping
+            // overwrite recursive mapping
             self.p2_mut()[ENTRY_COUNT - 1].set(table.p2_frame.clone(), EntryBits::Valid.val());
             instructions::flush_tlb();
-            println!("new table frame: {:x?}", table.p2_frame);
-            print_entry(0, [1023, 1022, 1022]);
-//            println!("overwrite recursive mapping: {:x?} => {:x?}", self.p2_mut()[ENTRY_COUNT - 2].get_entry(), self.p2_mut()[ENTRY_COUNT - 1].get_entry());
+
             // execute f in the new context
             f(self);
-//            println!("closure done\n");
-//            println!("510: {:x?}", self.p2_mut().next_table(1022).unwrap().entries[769].get_entry());
-//
-//            // restore recursive mapping to original p2 table
-//            p2_table[ENTRY_COUNT - 1].set(backup, EntryBits::Valid.val());
-//
-//            instructions::flush_tlb();
-
-            println!("==========with done==========\n")
+
+            // restore recursive mapping to original p2 table
+            p2_table[ENTRY_COUNT - 1].set(backup, EntryBits::Valid.val());
+            instructions::flush_tlb();
         }
-// TODO
-//        temporary_page.unmap(self);
+        temporary_page.unmap(self);
     }
 
     pub fn switch(&mut self, new_table: InactivePageTable) -> InactivePageTable {
@@ -165,8 +150,6 @@ impl InactivePageTable {
                temporary_page: &mut TemporaryPage)
                -> InactivePageTable {
         {
-
-            println!("\n==========new inactive==========");
             let table = temporary_page.map_table_frame(frame.clone(),
                                                        active_table);
             // now we are able to zero the table
@@ -176,18 +159,39 @@ impl InactivePageTable {
             table[ENTRY_COUNT - 1].set(frame.clone(), EntryBits::Valid.val());
             assert_eq!(table[ENTRY_COUNT - 1].get_entry() >> 10, (frame.start_address() >> 12) as u32);
         }
+        temporary_page.unmap(active_table);
+        InactivePageTable { p2_frame: frame }
+    }
 
-        {
-
-            println!("set recursive:");
-            super::print_entry(0, [temporary_page.p2_index(), temporary_page.p1_index(), 1022]);
-            super::print_entry(0, [temporary_page.p2_index(), temporary_page.p1_index(), 1023]);
+    /// The value to be written into satp to activate this table.
+    pub fn token(&self) -> usize {
+        1 << 31 | self.p2_frame.number
+    }
 
+    /// Share the kernel mappings with this table, so that the kernel keeps working
+    /// after switching to it. Both tables are accessed through the linear mapping
+    /// of physical memory, the recursive entries are left untouched.
+    pub fn map_kernel(&mut self) {
+        let kernel_p2 = unsafe {
+            &*(access_pa_via_va(kernel_root_frame().start_address()) as *const Table<Level2>)
+        };
+        let p2 = unsafe {
+            &mut *(access_pa_via_va(self.p2_frame.start_address()) as *mut Table<Level2>)
+        };
+        for i in 0..ENTRY_COUNT - 2 {
+            if kernel_p2[i].is_valid() {
+                p2[i].set_entry(kernel_p2[i].get_entry());
+            }
         }
-        temporary_page.unmap(active_table);
-        super::print_entry(2, [temporary_page.p2_index(), 0, 0]);
-
-        println!("==========new inactive done==========\n");
-        InactivePageTable { p2_frame: frame }
     }
 }
+
+/// Find the leaf entry of `page` in the page table rooted at `root` by walking the
+/// tables through the linear mapping of physical memory, so it works for inactive
+/// tables as well. Returns `None` if the P1 table does not exist.
+pub unsafe fn entry_of(root: Frame, page: Page) -> Option<&'static mut Entry> {
+    let p2 = &*(access_pa_via_va(root.start_address()) as *const Table<Level2>);
+    let p1_frame = p2[page.p2_index()].pointed_frame()?;
+    let p1 = &mut *(access_pa_via_va(p1_frame.start_address()) as *mut Table<table::Level1>);
+    Some(&mut p1[page.p1_index()])
+}
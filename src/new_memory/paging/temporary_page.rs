@@ -21,24 +21,14 @@ impl TemporaryPage {
         -> usize
     {
         use super::entry::EntryBits;
-        println!("map temporary page {:x?} to given frame {:x?} in the active table", self.page, frame);
         assert!(active_table.translate_page(self.page).is_none(),
                 "temporary page is already mapped");
         active_table.map_to(self.page, frame, EntryBits::ReadWrite.val(), &mut self.allocator);
-        println!("temporary map done, start address:0x{:x?}\n", self.page.start_address());
-
-        use super::super::print_entry;
-        print_entry(2, [self.page.p2_index(), 0, 0]);
-        print_entry(1, [self.page.p2_index(), self.page.p1_index(), 0]);
-        print_entry(0, [self.page.p2_index(), self.page.p1_index(), 1022]);
-        print_entry(0, [self.page.p2_index(), self.page.p1_index(), 1023]);
-
         self.page.start_address()
     }
 
     /// Unmaps the temporary page in the active table.
     pub fn unmap(&mut self, active_table: &mut ActivePageTable) {
-        println!("temporary page unmap");
         active_table.unmap(self.page, &mut self.allocator)
     }
 
@@ -89,6 +79,17 @@ impl FrameAllocator for TinyAllocator {
                 return;
             }
         }
-        panic!("Tiny allocator can hold only 3 frames.");
+        panic!("Tiny allocator can hold only 1 frame.");
+    }
+}
+
+/// Give the frame reserved for the temporary page's p1 table back.
+impl Drop for TinyAllocator {
+    fn drop(&mut self) {
+        for frame_option in &mut self.0 {
+            if let Some(frame) = frame_option.take() {
+                super::super::frame_allocator::dealloc_frame(frame);
+            }
+        }
     }
 }
\ No newline at end of file
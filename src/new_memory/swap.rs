@@ -0,0 +1,249 @@
+//! 页面置换
+//!
+//! 物理页帧耗尽时，从用户页中按照 Clock（二次机会）算法选出一页写入交换区，
+//! 并在页表项中记下它所在的交换槽；之后访问这一页触发缺页异常，再将它换入。
+//!
+//! 所有可以被换出的用户页都登记在一个环形队列中，队首就是 Clock 算法的指针：
+//! 页表项的 Access 位为 1 的页获得第二次机会，清除 Access 位后放回队尾；
+//! 换入的页会保留原来的交换槽，如果再次被换出时 Dirty 位仍为 0 ，就不需要重新写回。
+
+use super::{Frame, PAGE_SIZE, access_pa_via_va, frame_allocator};
+use super::paging::{Page, entry_of};
+use super::paging::entry::EntryBits;
+use super::paging::mapper::Mapper;
+use crate::riscv::instructions;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use alloc::collections::{BTreeMap, VecDeque};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::slice;
+use lazy_static::*;
+use crate::sync::SpinNoIrqLock as Mutex;
+
+/// 交换区所在的设备，以页为单位读写
+pub trait SwapDevice: Send {
+    /// 设备能容纳的页数
+    fn slot_count(&self) -> usize;
+    fn read_slot(&mut self, slot: usize, buf: &mut [u8]);
+    fn write_slot(&mut self, slot: usize, buf: &[u8]);
+}
+
+/// 用一段预留的物理内存充当交换区，在有真正的块设备之前使用
+pub struct RamSwap {
+    base: usize, // 预留内存在内核中的虚拟地址
+    slots: usize,
+}
+
+impl RamSwap {
+    pub fn new(base: usize, size: usize) -> RamSwap {
+        RamSwap { base, slots: size / PAGE_SIZE }
+    }
+
+    fn slot(&mut self, slot: usize) -> &mut [u8] {
+        assert!(slot < self.slots, "swap slot out of range");
+        unsafe { slice::from_raw_parts_mut((self.base + slot * PAGE_SIZE) as *mut u8, PAGE_SIZE) }
+    }
+}
+
+impl SwapDevice for RamSwap {
+    fn slot_count(&self) -> usize {
+        self.slots
+    }
+
+    fn read_slot(&mut self, slot: usize, buf: &mut [u8]) {
+        buf.copy_from_slice(self.slot(slot));
+    }
+
+    fn write_slot(&mut self, slot: usize, buf: &[u8]) {
+        self.slot(slot).copy_from_slice(buf);
+    }
+}
+
+// 用 (页表根页帧号, 虚拟页号) 标识一个用户页
+type PageKey = (usize, usize);
+
+struct SwapManager {
+    device: Option<Box<dyn SwapDevice>>,
+    free_slots: Vec<usize>,
+    // 驻留在内存中的用户页，以及它在交换区中仍然有效的副本
+    resident: BTreeMap<PageKey, Option<usize>>,
+    // Clock 算法的环形队列，已经不在 resident 中的页在经过指针时丢弃
+    clock: VecDeque<PageKey>,
+}
+
+lazy_static! {
+    static ref SWAP: Mutex<SwapManager> = Mutex::new(SwapManager {
+        device: None,
+        free_slots: Vec::new(),
+        resident: BTreeMap::new(),
+        clock: VecDeque::new(),
+    });
+}
+
+static SWAP_IN_COUNT: AtomicUsize = AtomicUsize::new(0);
+static SWAP_OUT_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone, Copy)]
+pub struct SwapStats {
+    pub swap_in: usize,
+    pub swap_out: usize,
+    pub free_slots: usize,
+    pub resident_pages: usize,
+}
+
+/// 启用交换区，在此之前物理页帧耗尽时分配直接失败
+pub fn init(device: Box<dyn SwapDevice>) {
+    let mut manager = SWAP.lock();
+    manager.free_slots = (0..device.slot_count()).rev().collect();
+    println!("swap: {} slots", device.slot_count());
+    manager.device = Some(device);
+}
+
+pub fn stats() -> SwapStats {
+    let manager = SWAP.lock();
+    SwapStats {
+        swap_in: SWAP_IN_COUNT.load(Ordering::Relaxed),
+        swap_out: SWAP_OUT_COUNT.load(Ordering::Relaxed),
+        free_slots: manager.free_slots.len(),
+        resident_pages: manager.resident.len(),
+    }
+}
+
+/// 登记 mapper 中刚刚映射好的用户页，它之后可以被换出
+pub fn track(mapper: &Mapper, page: Page) {
+    track_with_slot(mapper, page, None);
+}
+
+/// 这一页不再属于任何地址空间
+pub fn untrack(mapper: &Mapper, page: Page) {
+    let mut manager = SWAP.lock();
+    if let Some(Some(slot)) = manager.resident.remove(&key_of(mapper, page)) {
+        manager.free_slots.push(slot);
+    }
+}
+
+/// 如果 page 已经被换出，释放它的交换槽并清空页表项，返回这一页是否处于换出状态
+pub fn discard(mapper: &mut Mapper, page: Page) -> bool {
+    let slot = match mapper.raw_entry_mut(page) {
+        Some(entry) => match entry.swap_slot() {
+            Some(slot) => {
+                entry.set_entry(0);
+                slot
+            }
+            None => return false,
+        },
+        None => return false,
+    };
+    SWAP.lock().free_slots.push(slot);
+    true
+}
+
+/// 如果 page 处于换出状态，为它分配新的页帧并从交换区读回，以 flags 重新映射
+pub fn swap_in(mapper: &mut Mapper, page: Page, flags: u32) -> bool {
+    let slot = match mapper.raw_entry_mut(page).and_then(|entry| entry.swap_slot()) {
+        Some(slot) => slot,
+        None => return false,
+    };
+    // 分配页帧时可能换出其他的页，因此不能在持有锁或者页表项引用的时候分配
+    let frame = frame_allocator::alloc_frame().expect("no more frames");
+    {
+        let mut manager = SWAP.lock();
+        let buf = frame_buf(frame);
+        manager.device.as_mut().expect("swap is not enabled").read_slot(slot, buf);
+    }
+    mapper.raw_entry_mut(page).unwrap().set(frame, flags);
+    instructions::flush_tlb();
+    // 交换槽中的副本依然有效，再次换出时如果没有被修改就不用写回
+    track_with_slot(mapper, page, Some(slot));
+    SWAP_IN_COUNT.fetch_add(1, Ordering::Relaxed);
+    true
+}
+
+/// 按照 Clock 算法选出一个用户页换出，释放它的物理页帧。没有可以换出的页时返回 false
+pub fn swap_out() -> bool {
+    let mut manager = SWAP.lock();
+    let manager = &mut *manager;
+    let device = match manager.device.as_mut() {
+        Some(device) => device,
+        None => return false,
+    };
+    // 每一页最多被经过两次：第一次清除 Access 位，第二次就可以被换出
+    let mut budget = manager.clock.len() * 2;
+    let mut victim = None;
+    while budget > 0 {
+        budget -= 1;
+        let key = match manager.clock.pop_front() {
+            Some(key) => key,
+            None => break,
+        };
+        let cached_slot = match manager.resident.get(&key) {
+            Some(slot) => *slot,
+            None => continue,
+        };
+        let entry = match unsafe { entry_of(Frame { number: key.0 }, Page { number: key.1 }) } {
+            Some(entry) if entry.is_valid() => entry,
+            _ => {
+                manager.resident.remove(&key);
+                continue;
+            }
+        };
+        let frame = entry.pointed_frame().unwrap();
+        if entry.is_accessed() || frame_allocator::frame_ref_count(frame) > 1 {
+            // 最近被访问过的页获得第二次机会；被 fork 共享的页涉及多个页表，不换出
+            entry.set_flags(entry.flags() & !EntryBits::Access.val());
+            manager.clock.push_back(key);
+            continue;
+        }
+        let slot = match cached_slot {
+            Some(slot) if !entry.is_dirty() => slot,
+            Some(slot) => {
+                device.write_slot(slot, frame_buf(frame));
+                slot
+            }
+            None => match manager.free_slots.pop() {
+                Some(slot) => {
+                    device.write_slot(slot, frame_buf(frame));
+                    slot
+                }
+                None => {
+                    // 交换区已满
+                    manager.clock.push_back(key);
+                    break;
+                }
+            },
+        };
+        entry.set_swapped(slot);
+        manager.resident.remove(&key);
+        victim = Some(frame);
+        break;
+    }
+    // 被清除了 Access 位的页表项可能还缓存在 TLB 中
+    instructions::flush_tlb();
+    match victim {
+        Some(frame) => {
+            frame_allocator::dealloc_frame(frame);
+            SWAP_OUT_COUNT.fetch_add(1, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
+
+fn track_with_slot(mapper: &Mapper, page: Page, slot: Option<usize>) {
+    let key = key_of(mapper, page);
+    let mut manager = SWAP.lock();
+    match manager.resident.insert(key, slot) {
+        // 同一页被重新映射到了新的页帧，原来的副本已经没有用了
+        Some(Some(old)) => manager.free_slots.push(old),
+        Some(None) => {}
+        None => manager.clock.push_back(key),
+    }
+}
+
+fn key_of(mapper: &Mapper, page: Page) -> PageKey {
+    (mapper.root_frame().number, page.number)
+}
+
+fn frame_buf(frame: Frame) -> &'static mut [u8] {
+    unsafe { slice::from_raw_parts_mut(access_pa_via_va(frame.start_address()) as *mut u8, PAGE_SIZE) }
+}
@@ -1,43 +1,84 @@
 mod structs;
-mod scheduler;
+pub mod scheduler;
 mod thread_pool;
 mod processor;
+mod wait_queue;
+mod timer;
+mod spawn;
 
-use structs::Thread;
+use structs::{Thread, Process, new_user_vm};
 use processor::Processor;
-use scheduler::Scheduler;
+pub use scheduler::SchedulerKind;
 use thread_pool::ThreadPool;
+pub use wait_queue::WaitQueue;
+pub use timer::sleep;
+pub use spawn::{spawn, JoinHandle};
+use crate::context::TrapFrame;
+use crate::consts::{MAX_THREAD_NUM, USER_STACK_OFFSET, USER_STACK_SIZE};
 
 use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use lazy_static::*;
+use crate::sync::SpinNoIrqLock as Mutex;
 
 pub type Tid = usize; // thread id
+pub type Pid = usize; // process id
 pub type ExitCode = usize;
 
+// 第一个用户进程，负责收养所有的孤儿进程
+pub const INIT_PID: Pid = 1;
+// 第一个用户进程的程序在 initramfs 中的路径
+const INIT_PATH: &str = "/bin/main";
+
+// 访问非法地址被杀死的进程的退出码，与 shell 中 128 + SIGSEGV 的约定一致
+pub const SEGFAULT_EXIT_CODE: ExitCode = 139;
+
+// 终端中输入 ^C 时发送的信号
+pub const SIGINT: usize = 2;
+
 static CPU: Processor = Processor::new();
 
+static NEXT_PID: AtomicUsize = AtomicUsize::new(INIT_PID);
+
+// 所有尚未被回收的进程
+lazy_static! {
+    static ref PROCESS_TABLE: Mutex<BTreeMap<Pid, Arc<Mutex<Process>>>>
+        = Mutex::new(BTreeMap::new());
+}
+
 pub fn tick() {
+    timer::tick();
     CPU.tick();
 }
 
-pub fn init() {
+// kind 指定使用的调度算法
+pub fn init(kind: SchedulerKind) {
     println!("+------ now to initialize process ------+");
-    let scheduler = Scheduler::new(1);
-    let thread_pool = ThreadPool::new(10, scheduler);
+    println!("scheduler: {:?}", kind);
+    let scheduler = kind.create(1);
+    let thread_pool = ThreadPool::new(MAX_THREAD_NUM, scheduler);
     CPU.init(Thread::new_idle(), Box::new(thread_pool));
 
 }
 
 pub fn run() {
     let thread0 = Thread::new_kernel(hello_thread, 0);
-    CPU.add_thread(thread0);
+    CPU.add_thread(thread0).expect("too many threads");
     let thread1 = Thread::new_kernel(hello_thread, 1);
-    CPU.add_thread(thread1);
+    CPU.add_thread(thread1).expect("too many threads");
     let thread2 = Thread::new_kernel(hello_thread, 2);
-    CPU.add_thread(thread2);
+    CPU.add_thread(thread2).expect("too many threads");
     let thread3 = Thread::new_kernel(hello_thread, 3);
-    CPU.add_thread(thread3);
+    CPU.add_thread(thread3).expect("too many threads");
     let thread4 = Thread::new_kernel(hello_thread, 4);
-    CPU.add_thread(thread4);
+    CPU.add_thread(thread4).expect("too many threads");
+
+    let init = crate::fs::lookup("/", INIT_PATH)
+        .and_then(|inode| inode.read_as_vec())
+        .expect("init program not found");
+    spawn_user(&init, None).expect("failed to create init process");
     CPU.run();
 }
 #[no_mangle]
@@ -46,18 +87,367 @@ pub extern "C" fn hello_thread(arg: usize) -> ! {
     println!("arg is {}", arg);
     for i in 0..2 {
         println!("{}{}{}{}{}{}{}{}", arg, arg, arg, arg, arg, arg, arg, arg);
-        for j in 0..1000 {
-        }
+        sleep(crate::clock::TICKS_PER_SEC / 10);
     }
     println!("end of thread {}", arg);
     CPU.exit(0)
 }
 
-pub fn exit(code: usize) {
-    CPU.exit(code);
+// 结束当前线程，若它是所属进程的最后一个线程，则该进程也随之退出
+pub fn exit(code: usize) -> ! {
+    if let Some(process) = current_process() {
+        let tid = current_tid();
+        let last = {
+            let mut process = process.lock();
+            process.threads.retain(|&t| t != tid);
+            process.thread_exit.notify_all();
+            process.threads.is_empty()
+        };
+        if last {
+            exit_process(process, code);
+        }
+    }
+    CPU.exit(code)
+}
+
+// 结束当前线程所属的整个进程，其他线程被杀死
+pub fn exit_group(code: usize) -> ! {
+    if let Some(process) = current_process() {
+        exit_process(process, code);
+    }
+    CPU.exit(code)
+}
+
+fn alloc_pid() -> Pid {
+    NEXT_PID.fetch_add(1, Ordering::Relaxed)
+}
+
+fn get_process(pid: Pid) -> Option<Arc<Mutex<Process>>> {
+    PROCESS_TABLE.lock().get(&pid).cloned()
+}
+
+// 当前线程所属的进程，内核线程返回 None
+pub fn current_process() -> Option<Arc<Mutex<Process>>> {
+    CPU.current_thread().process.clone()
+}
+
+// 当前进程的 pid ，内核线程没有所属进程，返回其 tid
+pub fn current_pid() -> Pid {
+    match current_process() {
+        Some(process) => process.lock().pid,
+        None => current_tid(),
+    }
+}
+
+// 在当前进程的地址空间中处理缺页异常，返回 false 说明当前线程不属于任何进程或访问不合法
+pub fn handle_page_fault(addr: usize) -> bool {
+    match current_process() {
+        Some(process) => process.lock().vm.handle_page_fault(addr),
+        None => false,
+    }
+}
+
+// 由 ELF 文件创建一个新的用户进程，parent 为 None 的进程没有父进程
+pub fn spawn_user(data: &[u8], parent: Option<Pid>) -> Result<Pid, &'static str> {
+    let (vm, entry_addr, brk_start) = new_user_vm(data)?;
+    let pid = alloc_pid();
+    let process = Arc::new(Mutex::new(Process::new(pid, parent, vm, brk_start)));
+    PROCESS_TABLE.lock().insert(pid, process.clone());
+    if let Some(parent) = parent.and_then(get_process) {
+        let mut parent = parent.lock();
+        parent.children.push(pid);
+        process.lock().pgid = parent.pgid;
+    }
+    let tid = match CPU.add_thread(Thread::new_user(entry_addr, process.clone())) {
+        Some(tid) => tid,
+        None => {
+            forget_child(pid, parent);
+            return Err("too many threads");
+        }
+    };
+    process.lock().threads.push(tid);
+    Ok(pid)
+}
+
+// 新进程的线程没能创建出来，把它从进程表和父进程中移除
+fn forget_child(pid: Pid, parent: Option<Pid>) {
+    PROCESS_TABLE.lock().remove(&pid);
+    if let Some(parent) = parent.and_then(get_process) {
+        parent.lock().children.retain(|&child| child != pid);
+    }
+}
+
+// 复制当前进程，子进程从 tf 处返回用户态，返回子进程的 pid 。只有调用 fork 的线程会被复制。
+// 线程数已经达到上限时返回 None
+pub fn fork(tf: &TrapFrame) -> Option<Pid> {
+    let process = current_process().expect("fork from a kernel thread");
+    let pid = alloc_pid();
+    let child = {
+        let mut parent = process.lock();
+        parent.children.push(pid);
+        let mut child = Process::new(pid, Some(parent.pid), parent.vm.clone(), parent.brk_start);
+        child.brk = parent.brk;
+        child.pgid = parent.pgid;
+        child.files = parent.files.clone();
+        child.cwd = parent.cwd.clone();
+        Arc::new(Mutex::new(child))
+    };
+    PROCESS_TABLE.lock().insert(pid, child.clone());
+    let tid = match CPU.add_thread(Thread::new_fork(tf, child.clone())) {
+        Some(tid) => tid,
+        None => {
+            let parent = child.lock().parent;
+            forget_child(pid, parent);
+            return None;
+        }
+    };
+    child.lock().threads.push(tid);
+    Some(pid)
+}
+
+// 在当前进程中创建一个新线程，它与当前线程共享地址空间，在 stack 栈上从 entry(arg) 开始运行。
+// 线程数已经达到上限时返回 None
+pub fn clone(entry: usize, stack: usize, arg: usize) -> Option<Tid> {
+    let process = current_process().expect("clone from a kernel thread");
+    let tid = CPU.add_thread(Thread::new_clone(entry, stack, arg, process.clone()))?;
+    process.lock().threads.push(tid);
+    Some(tid)
+}
+
+// 用 data 所指的 ELF 文件替换当前进程的地址空间，并将 tf 重置为从程序入口开始执行。
+// 其他线程在替换地址空间之前被杀死
+pub fn exec(data: &[u8], tf: &mut TrapFrame) -> Result<(), &'static str> {
+    let process = current_process().expect("exec from a kernel thread");
+    let (vm, entry_addr, brk_start) = new_user_vm(data)?;
+    if kill_other_threads(&process, 0).is_err() {
+        // 当前线程已经被杀死，返回用户态之前就会退出
+        return Err("killed by another thread");
+    }
+    let old_vm = {
+        let mut process = process.lock();
+        unsafe {
+            vm.activate();
+        }
+        process.brk_start = brk_start;
+        process.brk = brk_start;
+        core::mem::replace(&mut process.vm, vm)
+    };
+    // 新的地址空间已经生效，可以安全地释放原来的地址空间
+    drop(old_vm);
+    *tf = TrapFrame::new_user(entry_addr, USER_STACK_OFFSET + USER_STACK_SIZE);
+    Ok(())
+}
+
+// wait 失败的原因
+pub enum WaitError {
+    // 没有符合条件的子进程
+    NoChild,
+    // 等待期间收到了信号或者被杀死
+    Interrupted,
 }
 
-extern "C" {
-    fn _user_img_start();
-    fn _user_img_end();
-}
\ No newline at end of file
+// 等待 pid 所指的子进程（None 表示任意子进程）退出并回收它，返回其 pid 和退出码
+pub fn wait(pid: Option<Pid>) -> Result<(Pid, ExitCode), WaitError> {
+    let process = current_process().expect("wait from a kernel thread");
+    loop {
+        {
+            // 被杀死的线程不再等待，返回用户态之前就会退出
+            if CPU.killed().is_some() {
+                return Err(WaitError::Interrupted);
+            }
+            let mut parent = process.lock();
+            let candidates: alloc::vec::Vec<Pid> = parent.children
+                .iter()
+                .cloned()
+                .filter(|&child| pid.map_or(true, |pid| pid == child))
+                .collect();
+            if candidates.is_empty() {
+                return Err(WaitError::NoChild);
+            }
+            for child in candidates {
+                let exit_code = get_process(child).and_then(|child| child.lock().exit_code);
+                if let Some(code) = exit_code {
+                    parent.children.retain(|&pid| pid != child);
+                    PROCESS_TABLE.lock().remove(&child);
+                    return Ok((child, code));
+                }
+            }
+            // 信号在持有进程锁时设置，检查之后才发出的信号一定能唤醒下面的睡眠
+            if parent.signals != 0 {
+                return Err(WaitError::Interrupted);
+            }
+            // 释放锁并睡眠，直到某个子进程退出
+            let queue = parent.child_exit.clone();
+            queue.wait_unlock(parent);
+        }
+    }
+}
+
+// 杀死 process 中除当前线程以外的所有线程，等它们全部退出之后返回，此后可以安全地释放地址空间。
+// 被杀死的线程只是被标记并唤醒，在返回用户态之前由 exit_if_killed 自行退出。
+// 两个线程同时调用时，先拿到进程锁的一方杀死另一方，当前线程已经被杀死时返回它应当使用的退出码
+fn kill_other_threads(process: &Arc<Mutex<Process>>, code: ExitCode) -> Result<(), ExitCode> {
+    let current = current_tid();
+    let mut guard = process.lock();
+    if let Some(code) = CPU.killed() {
+        return Err(code);
+    }
+    loop {
+        // 每一轮都重新标记，等待期间被杀死的线程 clone 出来的线程同样会被杀死
+        let mut others = 0;
+        for &tid in guard.threads.iter() {
+            if tid != current {
+                CPU.kill(tid, code);
+                others += 1;
+            }
+        }
+        if others == 0 {
+            return Ok(());
+        }
+        let queue = guard.thread_exit.clone();
+        queue.wait_unlock(guard);
+        guard = process.lock();
+    }
+}
+
+// 当前线程已经被其他线程杀死时退出，在返回用户态之前调用，此时它不持有任何锁
+pub fn exit_if_killed() {
+    if let Some(code) = CPU.killed() {
+        exit_killed(code);
+    }
+}
+
+// 被杀死的线程所在的进程由杀死它的线程负责结束，这里只需要离开进程并唤醒等待的线程
+fn exit_killed(code: ExitCode) -> ! {
+    if let Some(process) = current_process() {
+        let tid = current_tid();
+        let mut process = process.lock();
+        process.threads.retain(|&t| t != tid);
+        process.thread_exit.notify_all();
+    }
+    CPU.exit(code)
+}
+
+// 进程退出：杀死其余的线程，释放用户内存，将子进程交给 init 收养，并唤醒等待它的父进程
+fn exit_process(process: Arc<Mutex<Process>>, code: ExitCode) {
+    if let Err(code) = kill_other_threads(&process, code) {
+        // 进程由杀死当前线程的线程负责结束
+        drop(process);
+        exit_killed(code);
+    }
+    let (pid, parent, children, files) = {
+        let mut process = process.lock();
+        process.threads.clear();
+        process.exit_code = Some(code);
+        process.vm.clear();
+        let files = core::mem::replace(&mut process.files, BTreeMap::new());
+        let children = core::mem::replace(&mut process.children, alloc::vec::Vec::new());
+        (process.pid, process.parent, children, files)
+    };
+    // 关闭最后一个引用时可能要写回磁盘并睡眠等待，不能在持有进程的自旋锁时进行
+    drop(files);
+    if !children.is_empty() {
+        match get_process(INIT_PID) {
+            Some(ref init) if pid != INIT_PID => {
+                for child in children {
+                    if let Some(child) = get_process(child) {
+                        child.lock().parent = Some(INIT_PID);
+                    }
+                    init.lock().children.push(child);
+                }
+                init.lock().child_exit.notify_all();
+            }
+            _ => println!("process {} exited with orphans but init is gone", pid),
+        }
+    }
+    if let Some(parent) = parent.and_then(get_process) {
+        parent.lock().child_exit.notify_all();
+    } else {
+        // 没有父进程等待回收，直接从进程表中移除
+        PROCESS_TABLE.lock().remove(&pid);
+    }
+}
+
+// 把当前进程或者它的子进程 pid 移到进程组 pgid 中，pgid 等于 pid 时新建一个进程组。
+// 没有会话的概念，不检查 pgid 是否已经存在。pid 不是当前进程也不是它的子进程时返回 false
+pub fn set_pgid(pid: Pid, pgid: Pid) -> bool {
+    let current = match current_process() {
+        Some(process) => process,
+        None => return false,
+    };
+    let target = {
+        let current = current.lock();
+        if pid == current.pid {
+            None
+        } else if current.children.contains(&pid) {
+            Some(pid)
+        } else {
+            return false;
+        }
+    };
+    let process = match target {
+        None => current,
+        Some(pid) => match get_process(pid) {
+            Some(process) => process,
+            None => return false,
+        },
+    };
+    process.lock().pgid = pgid;
+    true
+}
+
+pub fn get_pgid(pid: Pid) -> Option<Pid> {
+    get_process(pid).map(|process| process.lock().pgid)
+}
+
+// 向进程组 pgid 中所有尚未退出的进程发送信号，并唤醒它们正在睡眠的线程，让这些线程检查 signal_pending 。
+// 所有等待都允许被提前唤醒，醒来后会重新检查条件。init 不会被信号结束，总是跳过它
+pub fn signal_group(pgid: Pid, sig: usize) {
+    let processes: alloc::vec::Vec<_> = PROCESS_TABLE.lock().values().cloned().collect();
+    for process in processes {
+        let mut process = process.lock();
+        if process.pgid == pgid && process.pid != INIT_PID && process.exit_code.is_none() {
+            process.signals |= 1 << sig;
+            for &tid in process.threads.iter() {
+                CPU.wakeup(tid);
+            }
+        }
+    }
+}
+
+// 当前线程是否应当中断正在进行的等待：所在进程有尚未处理的信号，或者线程已经被杀死
+pub fn signal_pending() -> bool {
+    if CPU.killed().is_some() {
+        return true;
+    }
+    match current_process() {
+        Some(process) => process.lock().signals != 0,
+        None => false,
+    }
+}
+
+// 在返回用户态之前处理当前进程收到的信号。信号的默认动作都是结束进程，退出码为 128 + 信号编号
+pub fn handle_signals() {
+    let signals = match current_process() {
+        Some(process) => process.lock().signals,
+        None => return,
+    };
+    if signals != 0 {
+        exit_group(128 + signals.trailing_zeros() as usize);
+    }
+}
+
+// 当前正在运行的线程的 tid
+pub fn current_tid() -> Tid {
+    CPU.current_tid()
+}
+
+// 设置当前线程的优先级，数值越大得到的 CPU 时间越多
+pub fn set_priority(priority: usize) {
+    CPU.set_priority(current_tid(), priority);
+}
+
+// 主动让出 CPU ，切换回 idle 线程重新调度
+pub fn yield_now() {
+    CPU.yield_now();
+}
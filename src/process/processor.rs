@@ -42,8 +42,12 @@ impl Processor {
             .expect("Processor is not initialized...")
     }
 
-    pub fn add_thread(&self, thread: Box<Thread>) {
-        self.inner().pool.add(thread);
+    // 加入一个新线程，线程数已经达到上限时返回 None ，thread 随之被释放
+    pub fn add_thread(&self, thread: Box<Thread>) -> Option<Tid> {
+        let flags = disable_and_store();
+        let tid = self.inner().pool.add(thread);
+        restore(flags);
+        tid
     }
 
     // 这是整个调度过程最核心的函数，由 idle 线程调用。
@@ -60,7 +64,6 @@ impl Processor {
                 inner.idle.switch_to(&mut *inner.current.as_mut().unwrap().1);
                 // 上一个线程已经结束或时间片用完，切换回 idle 线程
                 let (tid, thread) = inner.current.take().unwrap();
-                println!("thread {} ran just now", tid);
                 // 将上一个线程放回线程池中
                 inner.pool.retrieve(tid, thread);
             } else {
@@ -91,6 +94,82 @@ impl Processor {
         }
     }
 
+    pub fn current_tid(&self) -> Tid {
+        self.inner().current.as_ref().expect("no thread is running").0
+    }
+
+    pub fn current_thread(&self) -> &Thread {
+        &self.inner().current.as_ref().expect("no thread is running").1
+    }
+
+    // 当前线程进入睡眠，切换至 idle 线程，直到被其他线程或中断处理函数 wakeup
+    pub fn sleep(&self) {
+        self.sleep_with(|| {});
+    }
+
+    // 先将当前线程标记为睡眠，再执行 f （通常是释放一把锁），最后切换至 idle 线程。
+    // 在 f 之后到达的 wakeup 会使线程重新就绪，不会丢失
+    pub fn sleep_with<F: FnOnce()>(&self, f: F) {
+        let inner = self.inner();
+        let flags = disable_and_store();
+        let tid = inner.current.as_ref().unwrap().0;
+        inner.pool.sleep(tid);
+        f();
+        // f 可能释放了一把关中断的锁，从而恢复了加锁之前的中断状态，返回时应当以它为准
+        let flags = flags | disable_and_store();
+        inner
+            .current
+            .as_mut()
+            .unwrap()
+            .1
+            .switch_to(&mut inner.idle);
+        restore(flags);
+    }
+
+    // 唤醒一个睡眠的线程，返回它是否确实在睡眠
+    pub fn wakeup(&self, tid: Tid) -> bool {
+        let flags = disable_and_store();
+        let woken = self.inner().pool.wakeup(tid);
+        restore(flags);
+        woken
+    }
+
+    // 杀死另一个线程：标记并唤醒它，它在返回用户态之前自行退出
+    pub fn kill(&self, tid: Tid, code: usize) {
+        let flags = disable_and_store();
+        self.inner().pool.kill(tid, code);
+        restore(flags);
+    }
+
+    // 当前线程是否已被杀死，返回它应当使用的退出码
+    pub fn killed(&self) -> Option<usize> {
+        let flags = disable_and_store();
+        let killed = self.inner().pool.killed(self.current_tid());
+        restore(flags);
+        killed
+    }
+
+    pub fn set_priority(&self, tid: Tid, priority: usize) {
+        let flags = disable_and_store();
+        self.inner().pool.set_priority(tid, priority);
+        restore(flags);
+    }
+
+    // 当前线程主动放弃剩余的时间片，切换至 idle 线程，由 idle 将其放回线程池重新调度
+    pub fn yield_now(&self) {
+        let inner = self.inner();
+        if !inner.current.is_none() {
+            let flags = disable_and_store();
+            inner
+                .current
+                .as_mut()
+                .unwrap()
+                .1
+                .switch_to(&mut inner.idle);
+            restore(flags);
+        }
+    }
+
     // 当线程任务完成之后，就可以通过 Processor.exit 结束自己（结束当前线程）
     pub fn exit(&self, code: usize) -> ! {
         let inner = self.inner();
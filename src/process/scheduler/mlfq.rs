@@ -0,0 +1,114 @@
+extern crate alloc;
+use alloc::vec::Vec;
+use alloc::collections::VecDeque;
+use super::Scheduler;
+
+// 队列的级数，第 0 级优先级最高
+const LEVELS: usize = 4;
+// 每隔这么多个时钟周期，所有线程都回到最高级，避免 CPU 密集型的线程饿死
+const BOOST_INTERVAL: usize = 100;
+
+#[derive(Default, Clone)]
+struct MLFQInfo {
+    level: usize,
+    time: usize, // time left in this level
+}
+
+// 多级反馈队列：新线程从最高级开始，用完一级的时间片就降一级，
+// 低一级的时间片是高一级的两倍。交互式的线程往往在时间片用完之前就进入睡眠，
+// 因此会一直留在高优先级的队列中，得到更快的响应；批处理的线程沉到底层，每次运行更久。
+pub struct MLFQScheduler {
+    queues: [VecDeque<usize>; LEVELS],
+    threads: Vec<MLFQInfo>,
+    max_time: usize, // time slice of the first level
+    current: Option<usize>, // tid of current running thread
+    ticks: usize, // ticks since last boost
+}
+
+impl MLFQScheduler {
+    pub fn new(max_time_slice: usize) -> Self {
+        MLFQScheduler {
+            queues: Default::default(),
+            threads: Vec::new(),
+            max_time: max_time_slice,
+            current: None,
+            ticks: 0,
+        }
+    }
+
+    fn time_slice(&self, level: usize) -> usize {
+        self.max_time << level
+    }
+
+    fn info(&mut self, tid: usize) -> &mut MLFQInfo {
+        if tid >= self.threads.len() {
+            self.threads.resize_with(tid + 1, Default::default);
+        }
+        &mut self.threads[tid]
+    }
+
+    // 所有线程回到最高级
+    fn boost(&mut self) {
+        for level in 1..LEVELS {
+            while let Some(tid) = self.queues[level].pop_front() {
+                self.queues[0].push_back(tid);
+            }
+        }
+        let time = self.time_slice(0);
+        for info in self.threads.iter_mut() {
+            info.level = 0;
+            info.time = time;
+        }
+    }
+}
+
+impl Scheduler for MLFQScheduler {
+    fn push(&mut self, tid: usize) {
+        let level = self.info(tid).level;
+        let time = self.time_slice(level);
+        let info = self.info(tid);
+        if info.time == 0 {
+            // 新线程，或者刚用完了上一级的时间片
+            info.time = time;
+        }
+        self.queues[level].push_back(tid);
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        let tid = self.queues.iter_mut().find_map(|queue| queue.pop_front())?;
+        self.current = Some(tid);
+        Some(tid)
+    }
+
+    // return true if current thread runs out of time slice
+    fn tick(&mut self) -> bool {
+        self.ticks += 1;
+        if self.ticks >= BOOST_INTERVAL {
+            self.ticks = 0;
+            self.boost();
+        }
+        let tid = match self.current {
+            Some(tid) => tid,
+            None => return true,
+        };
+        let info = self.info(tid);
+        info.time = info.time.saturating_sub(1);
+        if info.time == 0 {
+            // 用完了时间片，下次 push 时降一级
+            info.level = (info.level + 1).min(LEVELS - 1);
+            return true;
+        }
+        false
+    }
+
+    fn exit(&mut self, tid: usize) {
+        if self.current == Some(tid) {
+            self.current = None;
+        }
+        // tid 可能被新线程复用
+        *self.info(tid) = MLFQInfo::default();
+    }
+
+    // 优先级由线程的运行情况决定，不支持手动设置
+    fn set_priority(&mut self, _tid: usize, _priority: usize) {}
+}
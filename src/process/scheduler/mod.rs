@@ -1,33 +1,42 @@
 mod round_robin;
+mod stride;
+mod mlfq;
 
 use crate::process::Tid;
-use round_robin::RRScheduler;
+use alloc::boxed::Box;
+pub use round_robin::RRScheduler;
+pub use stride::StrideScheduler;
+pub use mlfq::MLFQScheduler;
 
-pub struct Scheduler {
-    scheduler: RRScheduler,
+// 调度算法只管理处于就绪状态的线程：
+// 线程就绪时被 push 进来，被选中运行时 pop 出去，运行结束或者时间片用完后再次 push 。
+pub trait Scheduler {
+    // 加入一个就绪的线程
+    fn push(&mut self, tid: Tid);
+    // 选出下一个要运行的线程，它成为当前线程
+    fn pop(&mut self) -> Option<Tid>;
+    // 当前线程又运行了一个时钟周期，返回是否需要切换到其他线程
+    fn tick(&mut self) -> bool;
+    // 线程退出
+    fn exit(&mut self, tid: Tid);
+    // 设置线程的优先级，数值越大得到的 CPU 时间越多，不支持优先级的调度算法可以忽略
+    fn set_priority(&mut self, tid: Tid, priority: usize);
 }
 
-impl Scheduler {
-    pub fn new(max_time_slice: usize) -> Scheduler {
-        let s = Scheduler {
-            scheduler: RRScheduler::new(max_time_slice),
-        };
-        s
-    }
-
-    pub fn push(&mut self, tid: Tid) {
-        self.scheduler.push(tid);
-    }
-
-    pub fn pop(&mut self) -> Option<Tid> {
-        self.scheduler.pop()
-    }
-
-    pub fn tick(&mut self) -> bool {
-        self.scheduler.tick()
-    }
+// 在 process::init 时选择使用哪种调度算法
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SchedulerKind {
+    RoundRobin,
+    Stride,
+    MLFQ,
+}
 
-    pub fn exit(&mut self, tid: Tid) {
-        self.scheduler.exit(tid);
+impl SchedulerKind {
+    pub fn create(self, max_time_slice: usize) -> Box<dyn Scheduler> {
+        match self {
+            SchedulerKind::RoundRobin => Box::new(RRScheduler::new(max_time_slice)),
+            SchedulerKind::Stride => Box::new(StrideScheduler::new(max_time_slice)),
+            SchedulerKind::MLFQ => Box::new(MLFQScheduler::new(max_time_slice)),
+        }
     }
-}
\ No newline at end of file
+}
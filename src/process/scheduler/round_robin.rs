@@ -1,5 +1,6 @@
 extern crate alloc;
 use alloc::vec::Vec;
+use super::Scheduler;
 
 #[derive(Default)]
 struct RRInfo {
@@ -31,9 +32,11 @@ impl RRScheduler {
         });
         rr
     }
+}
 
+impl Scheduler for RRScheduler {
     // add a thread
-    pub fn push(&mut self, tid : usize) {
+    fn push(&mut self, tid : usize) {
         let tid = tid + 1; // here add one because we have a dummy head
         if tid + 1 > self.threads.len() { // a new thread first added
             self.threads.resize_with(tid + 1, Default::default);
@@ -51,7 +54,7 @@ impl RRScheduler {
         self.threads[tid].next = 0;
     }
 
-    pub fn pop(&mut self) -> Option<usize> {
+    fn pop(&mut self) -> Option<usize> {
         let ret = self.threads[0].next;
         if ret != 0 {
             let next = self.threads[ret].next;
@@ -70,7 +73,7 @@ impl RRScheduler {
     }
 
     // return true if current thread runs out of time slice
-    pub fn tick(&mut self) -> bool {
+    fn tick(&mut self) -> bool {
         let tid = self.current;
         //println!("tick in scheduler, tid : {}", tid -1);
         if tid != 0 {
@@ -86,10 +89,13 @@ impl RRScheduler {
         return true;
     }
 
-    pub fn exit(&mut self, tid : usize) {
+    fn exit(&mut self, tid : usize) {
         let tid = tid + 1; // here add one because we have a dummy head
         if self.current == tid {
             self.current = 0;
         }
     }
-}
\ No newline at end of file
+
+    // 所有线程轮流运行，没有优先级
+    fn set_priority(&mut self, _tid: usize, _priority: usize) {}
+}
@@ -0,0 +1,110 @@
+extern crate alloc;
+use alloc::vec::Vec;
+use super::Scheduler;
+
+// 所有线程的步长都由它除以优先级得到
+const BIG_STRIDE: usize = 1 << 20;
+
+#[derive(Default)]
+struct StrideInfo {
+    ready: bool,
+    priority: usize,
+    pass: usize, // 已经走过的总路程
+}
+
+impl StrideInfo {
+    fn stride(&self) -> usize {
+        BIG_STRIDE / self.priority
+    }
+}
+
+// 步长调度：每次选择 pass 最小的线程运行，运行之后 pass 增加 BIG_STRIDE / priority ，
+// 因此线程得到的 CPU 时间与优先级成正比。
+pub struct StrideScheduler {
+    threads: Vec<StrideInfo>,
+    max_time: usize, // max_time_slice
+    time: usize, // time left of current thread
+    current: Option<usize>, // tid of current running thread
+}
+
+impl StrideScheduler {
+    pub fn new(max_time_slice: usize) -> Self {
+        StrideScheduler {
+            threads: Vec::new(),
+            max_time: max_time_slice,
+            time: 0,
+            current: None,
+        }
+    }
+
+    fn info(&mut self, tid: usize) -> &mut StrideInfo {
+        if tid >= self.threads.len() {
+            self.threads.resize_with(tid + 1, Default::default);
+        }
+        &mut self.threads[tid]
+    }
+
+    // 就绪线程中最小的 pass ，新加入的线程从这里开始，以免长期占用 CPU
+    fn min_pass(&self) -> Option<usize> {
+        self.threads
+            .iter()
+            .filter(|info| info.ready)
+            .map(|info| info.pass)
+            .min_by(|&a, &b| pass_cmp(a, b))
+    }
+}
+
+// pass 会溢出回绕，只要任意两个线程的 pass 之差不超过 BIG_STRIDE ，按有符号数比较差值就是正确的
+fn pass_cmp(a: usize, b: usize) -> core::cmp::Ordering {
+    (a.wrapping_sub(b) as isize).cmp(&0)
+}
+
+impl Scheduler for StrideScheduler {
+    fn push(&mut self, tid: usize) {
+        let min_pass = self.min_pass();
+        let info = self.info(tid);
+        if info.priority == 0 {
+            // 新线程
+            info.priority = 1;
+            info.pass = min_pass.unwrap_or(0);
+        }
+        info.ready = true;
+    }
+
+    fn pop(&mut self) -> Option<usize> {
+        let tid = self.threads
+            .iter()
+            .enumerate()
+            .filter(|(_, info)| info.ready)
+            .min_by(|(_, a), (_, b)| pass_cmp(a.pass, b.pass))
+            .map(|(tid, _)| tid)?;
+        let info = &mut self.threads[tid];
+        info.ready = false;
+        info.pass = info.pass.wrapping_add(info.stride());
+        self.current = Some(tid);
+        self.time = self.max_time;
+        Some(tid)
+    }
+
+    // return true if current thread runs out of time slice
+    fn tick(&mut self) -> bool {
+        if self.current.is_none() {
+            return true;
+        }
+        self.time -= 1;
+        self.time == 0
+    }
+
+    fn exit(&mut self, tid: usize) {
+        if self.current == Some(tid) {
+            self.current = None;
+        }
+        // tid 可能被新线程复用
+        *self.info(tid) = StrideInfo::default();
+    }
+
+    fn set_priority(&mut self, tid: usize, priority: usize) {
+        let info = self.info(tid);
+        info.priority = priority.max(1);
+    }
+}
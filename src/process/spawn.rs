@@ -0,0 +1,66 @@
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use crate::sync::SpinNoIrqLock;
+use crate::process::{Tid, CPU, WaitQueue, exit};
+use crate::process::structs::Thread;
+use crate::interrupt::restore;
+
+// 线程运行结束后把返回值放在这里，等待 join 取走
+struct Packet<T> {
+    result: SpinNoIrqLock<Option<T>>,
+    finished: WaitQueue,
+}
+
+// spawn 返回的句柄，通过 join 等待线程结束并取得闭包的返回值
+pub struct JoinHandle<T> {
+    tid: Tid,
+    packet: Arc<Packet<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn tid(&self) -> Tid {
+        self.tid
+    }
+
+    // 睡眠直到线程结束，返回闭包的返回值
+    pub fn join(self) -> T {
+        loop {
+            let mut result = self.packet.result.lock();
+            if let Some(value) = result.take() {
+                return value;
+            }
+            self.packet.finished.wait_unlock(result);
+        }
+    }
+}
+
+// 创建一个运行闭包 f 的内核线程
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+    where F: FnOnce() -> T + Send + 'static,
+          T: Send + 'static
+{
+    let packet = Arc::new(Packet {
+        result: SpinNoIrqLock::new(None),
+        finished: WaitQueue::new(),
+    });
+    let their_packet = packet.clone();
+    let main: Box<dyn FnOnce()> = Box::new(move || {
+        let value = f();
+        *their_packet.result.lock() = Some(value);
+        their_packet.finished.notify_all();
+    });
+    // 闭包是胖指针，再装一层 Box 才能通过一个 usize 传给新线程
+    let arg = Box::into_raw(Box::new(main)) as usize;
+    let tid = CPU.add_thread(Thread::new_kernel(kernel_thread_entry, arg)).expect("too many threads");
+    JoinHandle { tid, packet }
+}
+
+// 所有通过 spawn 创建的线程都从这里开始运行
+extern "C" fn kernel_thread_entry(arg: usize) -> ! {
+    let main = unsafe { Box::from_raw(arg as *mut Box<dyn FnOnce()>) };
+    // 被调度时中断处于关闭状态，打开中断使线程可以被抢占
+    restore(1 << 1);
+    main();
+    exit(0)
+}
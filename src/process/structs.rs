@@ -4,19 +4,82 @@ use alloc::alloc::{alloc, dealloc, Layout};
 use alloc::boxed::Box;
 
 use crate::riscv::register::satp;
-use crate::consts::STACK_SIZE;
-use crate::process::{Tid, ExitCode};
+use crate::consts::{STACK_SIZE, USER_STACK_OFFSET, USER_STACK_SIZE, USER_MAP_LIMIT, MAX_FILE_NUM};
+use crate::process::{Tid, Pid, ExitCode, WaitQueue};
+use crate::context::TrapFrame;
 use alloc::sync::Arc;
-use crate::new_memory::paging::InactivePageTable;
+use alloc::vec::Vec;
+use alloc::string::String;
+use alloc::collections::BTreeMap;
+use crate::fs::{self, File};
+use crate::memory_set::{MemorySet, attr::MemoryAttr, handler::{ByFrame, Delay}, is_user_range, page_ceil, page_floor};
+use xmas_elf::{ElfFile, header, program::{Flags, Type}};
+use crate::sync::SpinNoIrqLock as Mutex;
 
+// 进程拥有独立的地址空间，并通过 parent 和 children 组成一棵进程树
 pub struct Process {
-    page_table: Arc<InactivePageTable>,
+    pub pid: Pid,
+    pub vm: MemorySet,
+    pub parent: Option<Pid>,
+    pub pgid: Pid, // 所在的进程组，终端的 ^C 发给前台进程组
+    pub children: Vec<Pid>,
+    pub exit_code: Option<ExitCode>, // 进程退出后、被父进程回收前保存退出码
+    pub child_exit: Arc<WaitQueue>, // 在 wait 中等待子进程退出的线程
+    pub brk_start: usize, // 堆的起始地址，紧跟在程序的最后一个段之后
+    pub brk: usize, // 当前的堆顶，[brk_start, brk) 所在的页属于堆区域
+    pub threads: Vec<Tid>, // 属于这个进程、尚未退出的线程
+    pub thread_exit: Arc<WaitQueue>, // 等待其他线程退出的线程，见 kill_other_threads
+    pub files: BTreeMap<usize, Arc<File>>, // 文件描述符表
+    pub cwd: String, // 当前目录的绝对路径
+    pub signals: usize, // 已经收到、尚未处理的信号，第 n 位对应信号 n
+}
+
+impl Process {
+    pub fn new(pid: Pid, parent: Option<Pid>, vm: MemorySet, brk_start: usize) -> Process {
+        Process {
+            pid,
+            vm,
+            parent,
+            pgid: pid,
+            children: Vec::new(),
+            exit_code: None,
+            child_exit: Arc::new(WaitQueue::new()),
+            brk_start,
+            brk: brk_start,
+            threads: Vec::new(),
+            thread_exit: Arc::new(WaitQueue::new()),
+            files: std_files(),
+            cwd: String::from("/"),
+            signals: 0,
+        }
+    }
+
+    // 占用最小的空闲文件描述符，描述符用完时返回 None
+    pub fn add_file(&mut self, file: Arc<File>) -> Option<usize> {
+        let fd = (0..MAX_FILE_NUM).find(|fd| !self.files.contains_key(fd))?;
+        self.files.insert(fd, file);
+        Some(fd)
+    }
+
+    pub fn get_file(&self, fd: usize) -> Option<Arc<File>> {
+        self.files.get(&fd).cloned()
+    }
+}
+
+// 标准输入、标准输出和标准错误，都指向控制台
+fn std_files() -> BTreeMap<usize, Arc<File>> {
+    let console = String::from("/dev/console");
+    let mut files = BTreeMap::new();
+    files.insert(0, Arc::new(File::new(fs::devfs::console(), console.clone(), true, false, false)));
+    files.insert(1, Arc::new(File::new(fs::devfs::console(), console.clone(), false, true, false)));
+    files.insert(2, Arc::new(File::new(fs::devfs::console(), console, false, true, false)));
+    files
 }
 
 pub struct Thread {
     pub context: Context, // 线程相关的上下文
     pub kstack: KernelStack, // 线程对应的内核栈
-    pub process: Option<Arc<Process>>,
+    pub process: Option<Arc<Mutex<Process>>>,
 }
 
 // 其实内核栈保存了该内核线程的各种数据以及上下文内容，本质上它就是一片固定大小的内存空间，因此我们只需要在 KernelStack 中记录栈的起始地址。
@@ -55,6 +118,49 @@ impl Thread {
         }
     }
 
+    // 用户线程：在 process 的地址空间中从 entry_addr 开始运行
+    pub fn new_user(entry_addr: usize, process: Arc<Mutex<Process>>) -> Box<Thread> {
+        let token = process.lock().vm.token();
+        unsafe {
+            let kstack_ = KernelStack::new();
+            Box::new(Thread {
+                context: Context::new_user_thread(
+                    entry_addr,
+                    USER_STACK_OFFSET + USER_STACK_SIZE,
+                    kstack_.top(),
+                    token),
+                kstack: kstack_,
+                process: Some(process),
+            })
+        }
+    }
+
+    // 用户线程：与 process 中已有的线程共享地址空间，从 entry_addr 开始运行，栈顶为 ustack_top
+    pub fn new_clone(entry_addr: usize, ustack_top: usize, arg: usize, process: Arc<Mutex<Process>>) -> Box<Thread> {
+        let token = process.lock().vm.token();
+        unsafe {
+            let kstack_ = KernelStack::new();
+            Box::new(Thread {
+                context: Context::new_clone(entry_addr, ustack_top, arg, kstack_.top(), token),
+                kstack: kstack_,
+                process: Some(process),
+            })
+        }
+    }
+
+    // fork 出的线程：从 tf 所保存的现场返回用户态，运行在 process 的地址空间中
+    pub fn new_fork(tf: &TrapFrame, process: Arc<Mutex<Process>>) -> Box<Thread> {
+        let token = process.lock().vm.token();
+        unsafe {
+            let kstack_ = KernelStack::new();
+            Box::new(Thread {
+                context: Context::new_fork(tf, kstack_.top(), token),
+                kstack: kstack_,
+                process: Some(process),
+            })
+        }
+    }
+
     // 创建好线程之后，则需要有办法能够在多个线程中相互切换。
     // 切换的过程需要两步：
     // 保存当前寄存器状态。
@@ -66,6 +172,116 @@ impl Thread {
     }
 }
 
+// 解析 ELF 文件，为 LOAD 段和用户栈建立内存区域，不合法的 ELF 文件返回错误而不是让内核崩溃
+// 由 ELF 文件建立用户地址空间，返回地址空间、程序入口和堆的起始地址。
+// 栈和堆都是延迟分配的，只有被访问到的页才会分配物理页帧
+pub fn new_user_vm(data: &[u8]) -> Result<(MemorySet, usize, usize), &'static str> {
+    let elf = ElfFile::new(data)?;
+    match elf.header.pt2.type_().as_type() {
+        header::Type::Executable => {},
+        header::Type::SharedObject => return Err("shared object is not supported"),
+        _ => return Err("unsupported elf type"),
+    }
+    let entry_addr = elf.header.pt2.entry_point() as usize;
+
+    let segments = load_segments(data, &elf)?;
+
+    // 共用一页的相邻段合并为一个区域，权限取它们的并集
+    let mut regions: Vec<(usize, usize, u32)> = Vec::new();
+    for seg in segments.iter() {
+        if let Some(last) = regions.last_mut() {
+            if page_floor(seg.start) < page_ceil(last.1) {
+                last.1 = seg.end;
+                last.2 |= seg.flags;
+                continue;
+            }
+        }
+        regions.push((seg.start, seg.end, seg.flags));
+    }
+    if regions.iter().any(|&(start, end, _)| !is_user_range(start, end)) {
+        return Err("segment in reserved address range");
+    }
+
+    let mut vm = MemorySet::new();
+    for &(start, end, flags) in regions.iter() {
+        vm.push(start, end, elf_flags_to_attr(Flags(flags)), ByFrame::new(), None);
+    }
+    for seg in segments.iter() {
+        vm.copy_to(seg.start, data.as_ptr() as usize + seg.offset, seg.file_size);
+    }
+    let brk_start = regions.last().map_or(0, |&(_, end, _)| page_ceil(end));
+    vm.push(
+        USER_STACK_OFFSET,
+        USER_STACK_OFFSET + USER_STACK_SIZE,
+        MemoryAttr::new().set_user().set_WR(),
+        Delay::new(),
+        None,
+    );
+    Ok((vm, entry_addr, brk_start))
+}
+
+// ELF 中一个需要加载的段，[start, end) 的开头 file_size 字节来自文件中 offset 处
+struct Segment {
+    start: usize,
+    end: usize,
+    offset: usize,
+    file_size: usize,
+    flags: u32,
+}
+
+// 检查所有 LOAD 段，按地址排序后返回。段的内容必须在文件之内，
+// 地址必须在栈可能增长到的范围之下，段之间不能重叠
+fn load_segments(data: &[u8], elf: &ElfFile) -> Result<Vec<Segment>, &'static str> {
+    let mut segments = Vec::new();
+    for ph in elf.program_iter() {
+        if ph.get_type() != Ok(Type::Load) || ph.mem_size() == 0 {
+            continue;
+        }
+        let offset = ph.offset() as usize;
+        let file_size = ph.file_size() as usize;
+        let start = ph.virtual_addr() as usize;
+        let mem_size = ph.mem_size() as usize;
+        if offset > data.len() || file_size > data.len() - offset {
+            return Err("segment out of file");
+        }
+        if file_size > mem_size {
+            return Err("segment file size exceeds memory size");
+        }
+        if start > USER_MAP_LIMIT || mem_size > USER_MAP_LIMIT - start {
+            return Err("segment out of user space");
+        }
+        segments.push(Segment {
+            start,
+            end: start + mem_size,
+            offset,
+            file_size,
+            flags: ph.flags().0,
+        });
+    }
+    segments.sort_by_key(|seg| seg.start);
+    for pair in segments.windows(2) {
+        if pair[1].start < pair[0].end {
+            return Err("overlapping segments");
+        }
+    }
+    Ok(segments)
+}
+
+// ELF 段的读写执行权限转换为用户态的内存属性
+fn elf_flags_to_attr(flags: Flags) -> MemoryAttr {
+    let mut attr = MemoryAttr::new().set_user();
+    if flags.is_read() {
+        attr = attr.set_readonly();
+    }
+    if flags.is_write() {
+        attr = attr.set_WR();
+    }
+    if flags.is_execute() {
+        attr = attr.set_execute();
+    }
+    attr
+}
+
 /// 为了实现简单，栈空间就直接从内核堆中分配了。
 /// 我们需要在它的构造函数（new）中分配内存，并在析构函数（Drop）中回收内存。
 /// 具体而言，我们使用 rust 的 alloc API 实现内存分配和回收
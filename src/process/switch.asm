@@ -31,6 +31,7 @@
     Load sp, 0(a1)
     Load s11, 1*XLENB(sp)
     csrw satp, s11
+    sfence.vma # 切换地址空间后刷新 TLB
     Load ra, 0*XLENB(sp)
     Load s0, 2*XLENB(sp)
     Load s1, 3*XLENB(sp)
@@ -3,17 +3,18 @@ extern crate alloc;
 use crate::process::scheduler::Scheduler;
 use crate::process::structs::{Status, Thread};
 use alloc::{vec::Vec, boxed::*};
-use crate::process::Tid;
+use crate::process::{Tid, ExitCode};
 
 struct ThreadInfo {
     status: Status,
     present: bool,
     thread: Option<Box<Thread>>,
+    killed: Option<ExitCode>, // 被其他线程杀死，返回用户态之前以这个退出码退出
 }
 
 pub struct ThreadPool {
     threads: Vec<Option<ThreadInfo>>, // 线程信号量的向量
-    scheduler: Box<Scheduler>, // 调度算法
+    scheduler: Box<dyn Scheduler>, // 调度算法
 }
 
 impl ThreadPool {
@@ -21,53 +22,64 @@ impl ThreadPool {
     // 由于线程数组是已经创建好的，但是默认内容为 None ，
     // 所以在添加线程的时候只需要将从 Vec 中找到一个未使用的位置，
     // 把新线程的信息传递过去就可以了。 同时，不要忘记为调度算法传入线程 id 。
-    pub fn new(size: usize, scheduler: Scheduler) -> ThreadPool {
+    pub fn new(size: usize, scheduler: Box<dyn Scheduler>) -> ThreadPool {
         ThreadPool {
             threads: {
                 let mut th = Vec::new();
                 th.resize_with(size, Default::default);
                 th
             },
-            scheduler: Box::new(scheduler),
+            scheduler,
         }
     }
 
-    fn alloc_tid(&self) -> Tid {
-        for (i, info) in self.threads.iter().enumerate() {
-            if info.is_none() {
-                return i;
-            }
-        }
-        panic!("alloc tid failed !");
+    // 线程数已经达到上限时返回 None
+    fn alloc_tid(&self) -> Option<Tid> {
+        self.threads.iter().position(|info| info.is_none())
     }
 
-    pub fn add(&mut self, _thread: Box<Thread>) {
-        let tid = self.alloc_tid();
+    pub fn add(&mut self, _thread: Box<Thread>) -> Option<Tid> {
+        let tid = self.alloc_tid()?;
         self.threads[tid] = Some(ThreadInfo{
             status: Status::Ready,
             present: true,
             thread: Some(_thread),
+            killed: None,
         });
         self.scheduler.push(tid);
-        println!("tid to alloc: {}", tid);
+        Some(tid)
     }
 
     pub fn acquire(&mut self) -> Option<(Tid, Box<Thread>)> {
-        if let Some(tid) = self.scheduler.pop() {
-            let mut thread_info = self.threads[tid].as_mut().expect("thread not exist !");
+        while let Some(tid) = self.scheduler.pop() {
+            let thread_info = self.threads[tid].as_mut().expect("thread not exist !");
+            if !thread_info.present {
+                // 在就绪状态下被杀死的线程，直到从调度器中取出时才能回收
+                self.scheduler.exit(tid);
+                self.threads[tid] = None;
+                continue;
+            }
             thread_info.status = Status::Running(tid);
             return Some((tid, thread_info.thread.take().expect("thread not exist ")));
-        } else {
-            return None;
         }
+        None
     }
 
     pub fn retrieve(&mut self, tid: Tid, thread: Box<Thread> ) {
-        let mut thread_info = self.threads[tid].as_mut().expect("thread not exist !");
+        let thread_info = self.threads[tid].as_mut().expect("thread not exist !");
         if thread_info.present {
             thread_info.thread = Some(thread);
-            thread_info.status = Status::Ready;
-            self.scheduler.push(tid);
+            match thread_info.status {
+                // 线程在等待某个事件，暂不放回调度器，等待被唤醒
+                Status::Sleeping => {},
+                _ => {
+                    thread_info.status = Status::Ready;
+                    self.scheduler.push(tid);
+                }
+            }
+        } else {
+            // 线程已经退出，回收其 tid ，线程本身随 thread 一同被释放
+            self.threads[tid] = None;
         }
     }
 
@@ -76,13 +88,52 @@ impl ThreadPool {
         self.scheduler.tick()
     }
 
+    pub fn set_priority(&mut self, tid: Tid, priority: usize) {
+        self.scheduler.set_priority(tid, priority);
+    }
+
     pub fn exit(&mut self, tid: Tid, code: usize) {
         self.threads[tid] = Some(ThreadInfo{
-            status: Status::Ready,
+            status: Status::Exited(code),
             present: false,
             thread: None,
+            killed: None,
         });
         self.scheduler.exit(tid);
-        println!("exit code: {}", code);
+    }
+
+    // 标记一个线程被杀死，并唤醒正在睡眠的它。线程可能睡在某个等待队列上，或者在系统调用中持有锁，
+    // 不能在这里直接回收，而是等它自己运行到返回用户态之前再退出
+    pub fn kill(&mut self, tid: Tid, code: usize) {
+        match self.threads.get_mut(tid) {
+            Some(Some(thread_info)) if thread_info.present => thread_info.killed = Some(code),
+            _ => return,
+        }
+        self.wakeup(tid);
+    }
+
+    pub fn killed(&self, tid: Tid) -> Option<ExitCode> {
+        self.threads[tid].as_ref().and_then(|thread_info| thread_info.killed)
+    }
+
+    // 标记线程进入睡眠状态，它被切换走之后不会再被调度，直到 wakeup
+    pub fn sleep(&mut self, tid: Tid) {
+        let thread_info = self.threads[tid].as_mut().expect("thread not exist !");
+        thread_info.status = Status::Sleeping;
+    }
+
+    // 唤醒一个睡眠的线程，返回它是否确实在睡眠
+    pub fn wakeup(&mut self, tid: Tid) -> bool {
+        if let Some(Some(thread_info)) = self.threads.get_mut(tid) {
+            if let Status::Sleeping = thread_info.status {
+                thread_info.status = Status::Ready;
+                // 若线程尚未被切换走，retrieve 时会将其放回调度器
+                if thread_info.thread.is_some() {
+                    self.scheduler.push(tid);
+                }
+                return true;
+            }
+        }
+        false
     }
 }
\ No newline at end of file
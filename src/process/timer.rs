@@ -0,0 +1,40 @@
+extern crate alloc;
+use alloc::vec::Vec;
+use crate::sync::SpinNoIrqLock;
+use lazy_static::*;
+use crate::process::{Tid, CPU};
+use crate::clock;
+
+// 按到期时间排序的睡眠线程，由时钟中断检查并唤醒到期的线程
+lazy_static! {
+    static ref TIMER: SpinNoIrqLock<Vec<(usize, Tid)>> = SpinNoIrqLock::new(Vec::new());
+}
+
+// 当前线程睡眠 ticks 个时钟周期
+pub fn sleep(ticks: usize) {
+    let deadline = clock::ticks().saturating_add(ticks.max(1));
+    let mut timer = TIMER.lock();
+    let index = timer
+        .iter()
+        .position(|&(time, _)| time > deadline)
+        .unwrap_or(timer.len());
+    timer.insert(index, (deadline, CPU.current_tid()));
+    // 时钟中断在释放锁之后才能检查到这个线程，此时它已经处于睡眠状态
+    let tid = CPU.current_tid();
+    CPU.sleep_with(move || drop(timer));
+    // 被杀死的线程会提前醒来，移除它的到期时间，以免之后误唤醒重新使用这个 tid 的线程
+    TIMER.lock().retain(|&(_, t)| t != tid);
+}
+
+// 唤醒所有到期的线程，在时钟中断中调用
+pub fn tick() {
+    let now = clock::ticks();
+    let mut timer = TIMER.lock();
+    let expired = timer
+        .iter()
+        .position(|&(time, _)| time > now)
+        .unwrap_or(timer.len());
+    for (_, tid) in timer.drain(..expired) {
+        CPU.wakeup(tid);
+    }
+}
@@ -0,0 +1,55 @@
+extern crate alloc;
+use alloc::collections::VecDeque;
+use crate::process::{Tid, CPU};
+use crate::sync::SpinNoIrqLock;
+
+// 等待队列：线程在队列上睡眠，不再参与调度，直到被其他线程或者中断处理函数唤醒。
+pub struct WaitQueue {
+    queue: SpinNoIrqLock<VecDeque<Tid>>,
+}
+
+impl WaitQueue {
+    pub fn new() -> WaitQueue {
+        WaitQueue {
+            queue: SpinNoIrqLock::new(VecDeque::new()),
+        }
+    }
+
+    // 当前线程在队列上睡眠，被唤醒后返回。被唤醒并不代表等待的条件已经满足，调用者需要重新检查
+    pub fn wait(&self) {
+        self.wait_unlock(());
+    }
+
+    // 释放 guard 并在队列上睡眠。guard 通常是保护等待条件的锁，
+    // 线程在释放锁之前就已经进入了睡眠状态，因此不会错过其他线程在条件满足后发出的唤醒。
+    // 线程也可能因为被杀死而被唤醒，返回前把自己从队列中移除，队列中不会留下已经退出的线程
+    pub fn wait_unlock<G>(&self, guard: G) {
+        let tid = CPU.current_tid();
+        self.queue.lock().push_back(tid);
+        CPU.sleep_with(move || drop(guard));
+        self.queue.lock().retain(|&t| t != tid);
+    }
+
+    // 唤醒最早等待的一个线程，返回是否有线程被唤醒。已经被其他原因唤醒的线程不算在内
+    pub fn notify_one(&self) -> bool {
+        loop {
+            let tid = match self.queue.lock().pop_front() {
+                Some(tid) => tid,
+                None => return false,
+            };
+            if CPU.wakeup(tid) {
+                return true;
+            }
+        }
+    }
+
+    // 唤醒所有等待的线程，返回被唤醒的线程数
+    pub fn notify_all(&self) -> usize {
+        let tids: VecDeque<Tid> = core::mem::replace(&mut *self.queue.lock(), VecDeque::new());
+        tids.into_iter().filter(|&tid| CPU.wakeup(tid)).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.lock().is_empty()
+    }
+}
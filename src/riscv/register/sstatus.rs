@@ -30,7 +30,7 @@ impl Sstatus {
     pub fn set_spp(&mut self, val: SPP) {
         match val {
             SPP::Supervisor => self.bits = self.bits | 1 << 8,
-            SPP::User => self.bits = self.bits & 0 << 8,
+            SPP::User => self.bits = self.bits & !(1 << 8),
         }
         // TODO: 不写回..？
 //        unsafe {
@@ -39,6 +39,26 @@ impl Sstatus {
 //            ::"volatile");
 //        }
     }
+
+    /// Supervisor Previous Interrupt Enable，sret 之后 sie 会被设为该值
+    #[inline]
+    pub fn set_spie(&mut self, val: bool) {
+        if val {
+            self.bits = self.bits | 1 << 5;
+        } else {
+            self.bits = self.bits & !(1 << 5);
+        }
+    }
+
+    /// Supervisor Interrupt Enable
+    #[inline]
+    pub fn set_sie(&mut self, val: bool) {
+        if val {
+            self.bits = self.bits | 1 << 1;
+        } else {
+            self.bits = self.bits & !(1 << 1);
+        }
+    }
 }
 /// Supervisor Previous Privilege Mode
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
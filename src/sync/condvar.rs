@@ -0,0 +1,31 @@
+use super::MutexGuard;
+use crate::process::WaitQueue;
+
+// 条件变量，与 Mutex 配合使用
+pub struct Condvar {
+    waiters: WaitQueue,
+}
+
+impl Condvar {
+    pub fn new() -> Condvar {
+        Condvar {
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    // 释放 guard 所持有的锁并睡眠，被唤醒后重新加锁。
+    // 释放锁和睡眠之间不会错过 notify ，但被唤醒时条件不一定成立，调用者需要在循环中检查
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        self.waiters.wait_unlock(guard);
+        mutex.lock()
+    }
+
+    pub fn notify_one(&self) {
+        self.waiters.notify_one();
+    }
+
+    pub fn notify_all(&self) {
+        self.waiters.notify_all();
+    }
+}
@@ -0,0 +1,17 @@
+//! 内核同步原语
+//!
+//! - `SpinNoIrqLock`：自旋锁，持有期间关闭中断，中断处理函数中也可以使用
+//! - `Mutex` 、`Semaphore` 、`RwLock` 、`Condvar`：拿不到资源时线程在等待队列上睡眠，
+//!   只能在线程上下文中使用，不能在中断处理函数中使用
+
+mod spin;
+mod mutex;
+mod semaphore;
+mod rwlock;
+mod condvar;
+
+pub use self::spin::{SpinNoIrqLock, SpinNoIrqLockGuard};
+pub use self::mutex::{Mutex, MutexGuard};
+pub use self::semaphore::{Semaphore, SemaphoreGuard};
+pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use self::condvar::Condvar;
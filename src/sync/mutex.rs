@@ -0,0 +1,76 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use super::SpinNoIrqLock;
+use crate::process::WaitQueue;
+
+// 睡眠锁：锁被占用时当前线程在等待队列上睡眠，让出 CPU 给其他线程，适合临界区较长的情况
+pub struct Mutex<T: ?Sized> {
+    locked: SpinNoIrqLock<bool>,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    pub(super) mutex: &'a Mutex<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub fn new(data: T) -> Mutex<T> {
+        Mutex {
+            locked: SpinNoIrqLock::new(false),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub fn lock(&self) -> MutexGuard<T> {
+        loop {
+            let mut locked = self.locked.lock();
+            if !*locked {
+                *locked = true;
+                return MutexGuard { mutex: self };
+            }
+            self.waiters.wait_unlock(locked);
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        let mut locked = self.locked.lock();
+        if *locked {
+            None
+        } else {
+            *locked = true;
+            Some(MutexGuard { mutex: self })
+        }
+    }
+
+    fn unlock(&self) {
+        *self.locked.lock() = false;
+        self.waiters.notify_one();
+    }
+}
+
+impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
@@ -0,0 +1,104 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use super::SpinNoIrqLock;
+use crate::process::WaitQueue;
+
+struct State {
+    readers: usize,
+    writer: bool,
+}
+
+// 读写锁：允许多个读者同时访问，写者独占；拿不到锁的线程睡眠等待
+pub struct RwLock<T: ?Sized> {
+    state: SpinNoIrqLock<State>,
+    waiters: WaitQueue,
+    data: UnsafeCell<T>,
+}
+
+pub struct RwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+pub struct RwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub fn new(data: T) -> RwLock<T> {
+        RwLock {
+            state: SpinNoIrqLock::new(State { readers: 0, writer: false }),
+            waiters: WaitQueue::new(),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        loop {
+            let mut state = self.state.lock();
+            if !state.writer {
+                state.readers += 1;
+                return RwLockReadGuard { lock: self };
+            }
+            self.waiters.wait_unlock(state);
+        }
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        loop {
+            let mut state = self.state.lock();
+            if !state.writer && state.readers == 0 {
+                state.writer = true;
+                return RwLockWriteGuard { lock: self };
+            }
+            self.waiters.wait_unlock(state);
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        let last = {
+            let mut state = self.lock.state.lock();
+            state.readers -= 1;
+            state.readers == 0
+        };
+        // 最后一个读者离开，等待中的写者可以进入了
+        if last {
+            self.lock.waiters.notify_all();
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RwLockWriteGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.state.lock().writer = false;
+        self.lock.waiters.notify_all();
+    }
+}
@@ -0,0 +1,51 @@
+use super::SpinNoIrqLock;
+use crate::process::WaitQueue;
+
+// 计数信号量：count 表示可用资源的数量，为 0 时 acquire 的线程睡眠等待
+pub struct Semaphore {
+    count: SpinNoIrqLock<isize>,
+    waiters: WaitQueue,
+}
+
+pub struct SemaphoreGuard<'a> {
+    sem: &'a Semaphore,
+}
+
+impl Semaphore {
+    pub fn new(count: isize) -> Semaphore {
+        Semaphore {
+            count: SpinNoIrqLock::new(count),
+            waiters: WaitQueue::new(),
+        }
+    }
+
+    // P 操作
+    pub fn acquire(&self) {
+        loop {
+            let mut count = self.count.lock();
+            if *count > 0 {
+                *count -= 1;
+                return;
+            }
+            self.waiters.wait_unlock(count);
+        }
+    }
+
+    // V 操作
+    pub fn release(&self) {
+        *self.count.lock() += 1;
+        self.waiters.notify_one();
+    }
+
+    // acquire 之后返回一个 guard ，离开作用域时自动 release
+    pub fn access(&self) -> SemaphoreGuard {
+        self.acquire();
+        SemaphoreGuard { sem: self }
+    }
+}
+
+impl<'a> Drop for SemaphoreGuard<'a> {
+    fn drop(&mut self) {
+        self.sem.release();
+    }
+}
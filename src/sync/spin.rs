@@ -0,0 +1,75 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering, spin_loop_hint};
+use crate::interrupt::{disable_and_store, restore};
+
+// 关中断的自旋锁。持有锁期间不会被时钟中断打断，
+// 因此中断处理函数和线程可以安全地使用同一把锁，不会出现线程持有锁时被中断、中断处理函数又去拿锁的死锁
+pub struct SpinNoIrqLock<T: ?Sized> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+pub struct SpinNoIrqLockGuard<'a, T: ?Sized> {
+    lock: &'a SpinNoIrqLock<T>,
+    flags: usize, // 加锁之前的中断状态，释放锁时恢复
+}
+
+unsafe impl<T: ?Sized + Send> Sync for SpinNoIrqLock<T> {}
+unsafe impl<T: ?Sized + Send> Send for SpinNoIrqLock<T> {}
+
+impl<T> SpinNoIrqLock<T> {
+    pub const fn new(data: T) -> SpinNoIrqLock<T> {
+        SpinNoIrqLock {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T: ?Sized> SpinNoIrqLock<T> {
+    pub fn lock(&self) -> SpinNoIrqLockGuard<T> {
+        let flags = disable_and_store();
+        while self.locked.compare_and_swap(false, true, Ordering::Acquire) {
+            spin_loop_hint();
+        }
+        SpinNoIrqLockGuard { lock: self, flags }
+    }
+
+    pub fn try_lock(&self) -> Option<SpinNoIrqLockGuard<T>> {
+        let flags = disable_and_store();
+        if self.locked.compare_and_swap(false, true, Ordering::Acquire) {
+            restore(flags);
+            None
+        } else {
+            Some(SpinNoIrqLockGuard { lock: self, flags })
+        }
+    }
+}
+
+impl<T: ?Sized + Default> Default for SpinNoIrqLock<T> {
+    fn default() -> SpinNoIrqLock<T> {
+        SpinNoIrqLock::new(T::default())
+    }
+}
+
+impl<'a, T: ?Sized> Deref for SpinNoIrqLockGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for SpinNoIrqLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for SpinNoIrqLockGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+        restore(self.flags);
+    }
+}
@@ -0,0 +1,454 @@
+//! 与文件相关的系统调用
+//!
+//! 路径参数都是以 '\0' 结尾的字符串，相对路径从 dirfd 所指的目录（AT_FDCWD 表示当前目录）开始解析。
+
+use super::{SysResult, SysError, check_user_buffer, copy_from_user, copy_to_user, copy_from_user_cstr};
+use crate::consts::PAGE_SIZE;
+use crate::fs::{self, File, FsError, FileType, SeekFrom};
+use crate::process;
+use crate::tty::TTY;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::{mem, slice};
+
+const AT_FDCWD: usize = -100isize as usize;
+
+const O_RDONLY: usize = 0;
+const O_WRONLY: usize = 1;
+const O_RDWR: usize = 2;
+const O_ACCMODE: usize = 3;
+const O_CREAT: usize = 0x40;
+const O_EXCL: usize = 0x80;
+const O_TRUNC: usize = 0x200;
+const O_APPEND: usize = 0x400;
+const O_DIRECTORY: usize = 0x1_0000;
+
+// unlinkat 删除的是目录
+const AT_REMOVEDIR: usize = 0x200;
+
+const SEEK_SET: usize = 0;
+const SEEK_CUR: usize = 1;
+const SEEK_END: usize = 2;
+
+const S_IFCHR: u32 = 0o020000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+// getdents64 返回的目录项类型
+const DT_UNKNOWN: u8 = 0;
+const DT_CHR: u8 = 2;
+const DT_DIR: u8 = 4;
+const DT_BLK: u8 = 6;
+const DT_REG: u8 = 8;
+const DT_LNK: u8 = 10;
+
+// ioctl 中终端相关的命令。输出是同步完成的，TCSETSW 与 TCSETS 相同，TCSETSF 还会丢弃尚未读走的输入
+const TCGETS: usize = 0x5401;
+const TCSETS: usize = 0x5402;
+const TCSETSW: usize = 0x5403;
+const TCSETSF: usize = 0x5404;
+// 读取和设置终端的前台进程组，参数指向一个 i32
+const TIOCGPGRP: usize = 0x540f;
+const TIOCSPGRP: usize = 0x5410;
+
+/// fstat 写回用户态的文件信息，布局与用户库中的定义一致
+#[repr(C)]
+pub struct Stat {
+    dev: u64,
+    ino: u64,
+    mode: u32,
+    nlink: u32,
+    size: u64,
+    blksize: u32,
+    blocks: u64,
+}
+
+impl From<FsError> for SysError {
+    fn from(err: FsError) -> SysError {
+        match err {
+            FsError::NotSupported => SysError::EPERM,
+            FsError::NotFile => SysError::EISDIR,
+            FsError::IsDir => SysError::EISDIR,
+            FsError::NotDir => SysError::ENOTDIR,
+            FsError::EntryNotFound => SysError::ENOENT,
+            FsError::EntryExist => SysError::EEXIST,
+            FsError::NotSameFs => SysError::EXDEV,
+            FsError::InvalidParam => SysError::EINVAL,
+            FsError::NoDeviceSpace => SysError::ENOSPC,
+            FsError::DirNotEmpty => SysError::ENOTEMPTY,
+            FsError::ReadOnly => SysError::EROFS,
+            FsError::SymLoop => SysError::ELOOP,
+            FsError::DeviceError => SysError::EIO,
+            FsError::Interrupted => SysError::EINTR,
+        }
+    }
+}
+
+/// 先读到内核的缓冲区中，不再持有文件的锁之后再复制到用户态，每次最多一页。
+/// 读到的数据不满一页时说明暂时没有更多数据，直接返回
+pub fn sys_read(fd: usize, base: *mut u8, len: usize) -> SysResult {
+    let file = get_file(fd)?;
+    if !file.readable() {
+        return Err(SysError::EBADF);
+    }
+    check_user_buffer(base as usize, len)?;
+    let mut buf = vec![0u8; len.min(PAGE_SIZE)];
+    let mut read = 0;
+    while read < len {
+        let chunk = (len - read).min(buf.len());
+        let n = match file.read(&mut buf[..chunk]) {
+            Ok(n) => n,
+            Err(_) if read > 0 => break,
+            Err(err) => return Err(err.into()),
+        };
+        copy_to_user(base as usize + read, &buf[..n])?;
+        read += n;
+        if n < chunk {
+            break;
+        }
+    }
+    Ok(read)
+}
+
+/// 与 sys_read 相同，用户态的数据每次复制一页到内核的缓冲区中再写入文件
+pub fn sys_write(fd: usize, base: *const u8, len: usize) -> SysResult {
+    let file = get_file(fd)?;
+    if !file.writable() {
+        return Err(SysError::EBADF);
+    }
+    check_user_buffer(base as usize, len)?;
+    let mut buf = vec![0u8; len.min(PAGE_SIZE)];
+    let mut written = 0;
+    while written < len {
+        let chunk = (len - written).min(buf.len());
+        copy_from_user(base as usize + written, &mut buf[..chunk])?;
+        let n = match file.write(&buf[..chunk]) {
+            Ok(n) => n,
+            Err(_) if written > 0 => break,
+            Err(err) => return Err(err.into()),
+        };
+        written += n;
+        if n < chunk {
+            break;
+        }
+    }
+    Ok(written)
+}
+
+/// 打开 path 所指的文件，返回新的文件描述符。
+/// 带有 O_CREAT 时文件不存在则新建，权限为 mode
+pub fn sys_openat(dirfd: usize, path: *const u8, flags: usize, mode: usize) -> SysResult {
+    let path = copy_from_user_cstr(path)?;
+    let path = resolve_path(dirfd, &path)?;
+    let (readable, writable) = match flags & O_ACCMODE {
+        O_RDONLY => (true, false),
+        O_WRONLY => (false, true),
+        O_RDWR => (true, true),
+        _ => return Err(SysError::EINVAL),
+    };
+    let inode = match fs::lookup("/", &path) {
+        Ok(inode) => {
+            if flags & (O_CREAT | O_EXCL) == O_CREAT | O_EXCL {
+                return Err(SysError::EEXIST);
+            }
+            inode
+        }
+        Err(FsError::EntryNotFound) if flags & O_CREAT != 0 => {
+            let (dir, name) = fs::lookup_parent("/", &path)?;
+            dir.create(&name, FileType::File, mode as u32)?
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let type_ = inode.metadata()?.type_;
+    if type_ == FileType::Dir && writable {
+        return Err(SysError::EISDIR);
+    }
+    if type_ != FileType::Dir && flags & O_DIRECTORY != 0 {
+        return Err(SysError::ENOTDIR);
+    }
+    if flags & O_TRUNC != 0 && writable && type_ == FileType::File {
+        inode.resize(0)?;
+    }
+    let file = File::new(inode, path, readable, writable, flags & O_APPEND != 0);
+    add_file(Arc::new(file))
+}
+
+pub fn sys_close(fd: usize) -> SysResult {
+    let process = process::current_process().ok_or(SysError::ESRCH)?;
+    let file = process.lock().files.remove(&fd).ok_or(SysError::EBADF)?;
+    // 在释放进程的锁之后再释放文件，关闭最后一个引用时可能需要写回数据
+    drop(file);
+    Ok(0)
+}
+
+/// 移动读写位置，返回新的位置
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> SysResult {
+    let pos = match whence {
+        SEEK_SET if offset >= 0 => SeekFrom::Start(offset as usize),
+        SEEK_CUR => SeekFrom::Current(offset),
+        SEEK_END => SeekFrom::End(offset),
+        _ => return Err(SysError::EINVAL),
+    };
+    Ok(get_file(fd)?.seek(pos)?)
+}
+
+/// 复制文件描述符，新旧描述符共享读写位置
+pub fn sys_dup(fd: usize) -> SysResult {
+    let file = get_file(fd)?;
+    add_file(file)
+}
+
+pub fn sys_fstat(fd: usize, stat: *mut Stat) -> SysResult {
+    check_user_buffer(stat as usize, mem::size_of::<Stat>())?;
+    let metadata = get_file(fd)?.metadata()?;
+    let type_bits = match metadata.type_ {
+        FileType::File => S_IFREG,
+        FileType::Dir => S_IFDIR,
+        FileType::CharDevice => S_IFCHR,
+        FileType::BlockDevice => S_IFBLK,
+        FileType::SymLink => S_IFLNK,
+    };
+    // 从全 0 开始逐项赋值，结构体中的空隙不会把内核栈上的数据带给用户态
+    let mut kstat: Stat = unsafe { mem::zeroed() };
+    kstat.dev = metadata.dev as u64;
+    kstat.ino = metadata.inode as u64;
+    kstat.mode = type_bits | metadata.mode as u32;
+    kstat.nlink = metadata.nlinks as u32;
+    kstat.size = metadata.size as u64;
+    kstat.blksize = metadata.blk_size as u32;
+    kstat.blocks = metadata.blocks as u64;
+    copy_to_user(stat as usize, as_bytes(&kstat))?;
+    Ok(0)
+}
+
+/// 设备相关的控制操作，目前只有终端支持，可以读取和修改 termios 以及前台进程组
+pub fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> SysResult {
+    if get_file(fd)?.inode().downcast_ref::<fs::devfs::Console>().is_none() {
+        return Err(SysError::ENOTTY);
+    }
+    match cmd {
+        TCGETS => {
+            copy_to_user(arg, as_bytes(&TTY.termios()))?;
+        }
+        TCSETS | TCSETSW | TCSETSF => {
+            let mut termios = TTY.termios();
+            copy_from_user(arg, as_bytes_mut(&mut termios))?;
+            if cmd == TCSETSF {
+                TTY.flush_input();
+            }
+            TTY.set_termios(termios);
+        }
+        TIOCGPGRP => {
+            copy_to_user(arg, &(TTY.foreground() as i32).to_ne_bytes())?;
+        }
+        TIOCSPGRP => {
+            let mut bytes = [0u8; 4];
+            copy_from_user(arg, &mut bytes)?;
+            let pgid = i32::from_ne_bytes(bytes);
+            if pgid <= 0 {
+                return Err(SysError::EINVAL);
+            }
+            TTY.set_foreground(pgid as usize);
+        }
+        _ => return Err(SysError::EINVAL),
+    }
+    Ok(0)
+}
+
+/// 修改文件的大小，扩展的部分填充 0
+pub fn sys_ftruncate(fd: usize, len: usize) -> SysResult {
+    let file = get_file(fd)?;
+    if !file.writable() {
+        return Err(SysError::EBADF);
+    }
+    file.inode().resize(len)?;
+    Ok(0)
+}
+
+/// 把所有文件系统的修改写回设备
+pub fn sys_sync() -> SysResult {
+    fs::sync_all()?;
+    Ok(0)
+}
+
+/// 把 fd 所在文件系统的修改写回设备
+pub fn sys_fsync(fd: usize) -> SysResult {
+    get_file(fd)?.inode().sync()?;
+    Ok(0)
+}
+
+/// 从目录 fd 中读取尽可能多的目录项，以 linux_dirent64 的格式写入 buf ，返回写入的字节数，
+/// 读完整个目录后返回 0
+pub fn sys_getdents64(fd: usize, base: *mut u8, len: usize) -> SysResult {
+    check_user_buffer(base as usize, len)?;
+    let file = get_file(fd)?;
+    let dir = file.inode();
+    if dir.metadata()?.type_ != FileType::Dir {
+        return Err(SysError::ENOTDIR);
+    }
+    // 目录项先写到内核的缓冲区中，最多一页，读完之后再复制到用户态
+    let mut buf = vec![0u8; len.min(PAGE_SIZE)];
+    let mut written = 0;
+    let mut too_small = false;
+    file.read_entries(|index, name| {
+        // d_ino: u64, d_off: i64, d_reclen: u16, d_type: u8, 以 '\0' 结尾的名字，整体按 8 字节对齐
+        let reclen = (19 + name.len() + 1 + 7) & !7;
+        if written + reclen > buf.len() {
+            // 还有目录项没有读，但 buf 连一项都放不下
+            too_small = written == 0;
+            return false;
+        }
+        let (ino, type_) = match name {
+            "." => (dir.metadata().map(|m| m.inode).unwrap_or(0), DT_DIR),
+            ".." => (0, DT_DIR),
+            name => match dir.lookup(name).and_then(|inode| inode.metadata()) {
+                Ok(metadata) => (metadata.inode, dirent_type(metadata.type_)),
+                Err(_) => (0, DT_UNKNOWN),
+            },
+        };
+        let entry = &mut buf[written..written + reclen];
+        entry[0..8].copy_from_slice(&(ino as u64).to_le_bytes());
+        entry[8..16].copy_from_slice(&(index as u64 + 1).to_le_bytes());
+        entry[16..18].copy_from_slice(&(reclen as u16).to_le_bytes());
+        entry[18] = type_;
+        entry[19..19 + name.len()].copy_from_slice(name.as_bytes());
+        for byte in entry[19 + name.len()..].iter_mut() {
+            *byte = 0;
+        }
+        written += reclen;
+        true
+    })?;
+    if too_small {
+        return Err(SysError::EINVAL);
+    }
+    copy_to_user(base as usize, &buf[..written])?;
+    Ok(written)
+}
+
+pub fn sys_mkdirat(dirfd: usize, path: *const u8, mode: usize) -> SysResult {
+    let path = copy_from_user_cstr(path)?;
+    let path = resolve_path(dirfd, &path)?;
+    let (dir, name) = fs::lookup_parent("/", &path)?;
+    dir.create(&name, FileType::Dir, mode as u32)?;
+    Ok(0)
+}
+
+/// 删除 path 所指的目录项，flags 中带有 AT_REMOVEDIR 时删除的是空目录
+pub fn sys_unlinkat(dirfd: usize, path: *const u8, flags: usize) -> SysResult {
+    let path = copy_from_user_cstr(path)?;
+    let path = resolve_path(dirfd, &path)?;
+    let (dir, name) = fs::lookup_parent("/", &path)?;
+    let is_dir = dir.lookup(&name)?.metadata()?.type_ == FileType::Dir;
+    match (is_dir, flags & AT_REMOVEDIR != 0) {
+        (true, false) => return Err(SysError::EISDIR),
+        (false, true) => return Err(SysError::ENOTDIR),
+        _ => {}
+    }
+    dir.unlink(&name)?;
+    Ok(0)
+}
+
+/// 为 old_path 所指的文件建立一个新的硬链接 new_path
+pub fn sys_linkat(old_dirfd: usize, old_path: *const u8, new_dirfd: usize, new_path: *const u8) -> SysResult {
+    let old_path = copy_from_user_cstr(old_path)?;
+    let new_path = copy_from_user_cstr(new_path)?;
+    let inode = fs::lookup("/", &resolve_path(old_dirfd, &old_path)?)?;
+    let (dir, name) = fs::lookup_parent("/", &resolve_path(new_dirfd, &new_path)?)?;
+    dir.link(&name, &inode)?;
+    Ok(0)
+}
+
+pub fn sys_renameat(old_dirfd: usize, old_path: *const u8, new_dirfd: usize, new_path: *const u8) -> SysResult {
+    let old_path = copy_from_user_cstr(old_path)?;
+    let new_path = copy_from_user_cstr(new_path)?;
+    let (old_dir, old_name) = fs::lookup_parent("/", &resolve_path(old_dirfd, &old_path)?)?;
+    let (new_dir, new_name) = fs::lookup_parent("/", &resolve_path(new_dirfd, &new_path)?)?;
+    old_dir.rename(&old_name, &new_dir, &new_name)?;
+    Ok(0)
+}
+
+/// 将符号链接 path 指向的路径写入 buf ，不以 '\0' 结尾，buf 不够长时截断，返回写入的长度
+pub fn sys_readlinkat(dirfd: usize, path: *const u8, buf: *mut u8, len: usize) -> SysResult {
+    check_user_buffer(buf as usize, len)?;
+    let path = copy_from_user_cstr(path)?;
+    let inode = fs::lookup_nofollow("/", &resolve_path(dirfd, &path)?)?;
+    let target = fs::read_link(&inode)?;
+    let n = len.min(target.len());
+    copy_to_user(buf as usize, &target.as_bytes()[..n])?;
+    Ok(n)
+}
+
+/// 将当前目录的绝对路径写入 buf ，返回写入的长度（包括结尾的 '\0'）
+pub fn sys_getcwd(buf: *mut u8, len: usize) -> SysResult {
+    check_user_buffer(buf as usize, len)?;
+    let process = process::current_process().ok_or(SysError::ESRCH)?;
+    let cwd = process.lock().cwd.clone();
+    if cwd.len() + 1 > len {
+        return Err(SysError::ERANGE);
+    }
+    copy_to_user(buf as usize, cwd.as_bytes())?;
+    copy_to_user(buf as usize + cwd.len(), &[0])?;
+    Ok(cwd.len() + 1)
+}
+
+pub fn sys_chdir(path: *const u8) -> SysResult {
+    let path = copy_from_user_cstr(path)?;
+    let path = resolve_path(AT_FDCWD, &path)?;
+    if fs::lookup("/", &path)?.metadata()?.type_ != FileType::Dir {
+        return Err(SysError::ENOTDIR);
+    }
+    let process = process::current_process().ok_or(SysError::ESRCH)?;
+    process.lock().cwd = path;
+    Ok(0)
+}
+
+// 按内存中的表示把 value 当作字节序列，用于和用户态交换 #[repr(C)] 的结构体
+fn as_bytes<T>(value: &T) -> &[u8] {
+    unsafe { slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) }
+}
+
+fn as_bytes_mut<T>(value: &mut T) -> &mut [u8] {
+    unsafe { slice::from_raw_parts_mut(value as *mut T as *mut u8, mem::size_of::<T>()) }
+}
+
+fn dirent_type(type_: FileType) -> u8 {
+    match type_ {
+        FileType::File => DT_REG,
+        FileType::Dir => DT_DIR,
+        FileType::CharDevice => DT_CHR,
+        FileType::BlockDevice => DT_BLK,
+        FileType::SymLink => DT_LNK,
+    }
+}
+
+fn get_file(fd: usize) -> Result<Arc<File>, SysError> {
+    let process = process::current_process().ok_or(SysError::ESRCH)?;
+    let file = process.lock().get_file(fd).ok_or(SysError::EBADF);
+    file
+}
+
+fn add_file(file: Arc<File>) -> SysResult {
+    let process = process::current_process().ok_or(SysError::ESRCH)?;
+    let fd = process.lock().add_file(file).ok_or(SysError::EMFILE);
+    fd
+}
+
+// 以 dirfd 所指的目录为起点，把 path 规范化为绝对路径
+fn resolve_path(dirfd: usize, path: &str) -> Result<String, SysError> {
+    let base = if path.starts_with('/') {
+        String::from("/")
+    } else if dirfd == AT_FDCWD {
+        let process = process::current_process().ok_or(SysError::ESRCH)?;
+        let cwd = process.lock().cwd.clone();
+        cwd
+    } else {
+        let file = get_file(dirfd)?;
+        if file.metadata()?.type_ != FileType::Dir {
+            return Err(SysError::ENOTDIR);
+        }
+        String::from(file.path())
+    };
+    Ok(fs::canonicalize(&base, path)?)
+}
@@ -0,0 +1,92 @@
+//! futex：用户态同步原语在没有竞争时只需要原子操作，需要等待时才通过 futex 进入内核睡眠
+//!
+//! 等待队列以用户地址对应的物理地址为键，映射到同一个页帧的地址共用一个队列。
+//! 有线程等待的页帧多持有一个引用：引用计数大于 1 的页不会被换出，页帧也不会在等待期间被回收给别人。
+//! fork 之后写时复制会把这一页复制到新的页帧，此时唤醒旧页帧上所有等待的线程，让它们按新的物理地址重新等待。
+
+use super::{SysResult, SysError, check_user_buffer};
+use crate::process::{self, WaitQueue};
+use crate::new_memory::{Frame, access_pa_via_va, frame_allocator};
+use crate::consts::PAGE_SIZE;
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicI32, Ordering};
+use lazy_static::*;
+
+const FUTEX_WAIT: usize = 0;
+const FUTEX_WAKE: usize = 1;
+// 进程私有的 futex ，私有的字同样按物理地址查找，这个标志被忽略
+const FUTEX_PRIVATE_FLAG: usize = 128;
+
+lazy_static! {
+    static ref FUTEX_QUEUES: Mutex<BTreeMap<usize, Arc<WaitQueue>>> = Mutex::new(BTreeMap::new());
+}
+
+/// FUTEX_WAIT ：如果 uaddr 处的值等于 val ，在这个字上睡眠直到被唤醒，否则返回 EAGAIN
+/// FUTEX_WAKE ：唤醒最多 val 个在 uaddr 上睡眠的线程，返回被唤醒的线程数
+pub fn sys_futex(uaddr: usize, op: usize, val: usize) -> SysResult {
+    check_user_buffer(uaddr, 4)?;
+    if uaddr % 4 != 0 {
+        return Err(SysError::EINVAL);
+    }
+    let process = process::current_process().ok_or(SysError::ESRCH)?;
+    let mut process = process.lock();
+    let paddr = process.vm.translate(uaddr).ok_or(SysError::EFAULT)?;
+    // 持有这把关闭中断的锁期间不会分配页帧，这一页也就不会被换出，可以直接通过物理地址访问
+    let mut queues = FUTEX_QUEUES.lock();
+    drop(process);
+    match op & !FUTEX_PRIVATE_FLAG {
+        FUTEX_WAIT => {
+            let word = unsafe { &*(access_pa_via_va(paddr) as *const AtomicI32) };
+            if word.load(Ordering::SeqCst) != val as i32 {
+                return Err(SysError::EAGAIN);
+            }
+            let queue = queues
+                .entry(paddr)
+                .or_insert_with(|| {
+                    frame_allocator::share_frame(Frame::containing_address(paddr));
+                    Arc::new(WaitQueue::new())
+                })
+                .clone();
+            // 在释放锁之前已经进入睡眠，不会错过修改这个字之后发出的唤醒
+            queue.wait_unlock(queues);
+            // 被杀死而醒来的线程不会被 FUTEX_WAKE 移出队列，由它自己删除空的队列
+            let mut queues = FUTEX_QUEUES.lock();
+            if queue.is_empty() && queues.get(&paddr).map_or(false, |q| Arc::ptr_eq(q, &queue)) {
+                remove_queue(&mut queues, paddr);
+            }
+            Ok(0)
+        }
+        FUTEX_WAKE => {
+            let mut count = 0;
+            if let Some(queue) = queues.get(&paddr).cloned() {
+                while count < val && queue.notify_one() {
+                    count += 1;
+                }
+                if queue.is_empty() {
+                    remove_queue(&mut queues, paddr);
+                }
+            }
+            Ok(count)
+        }
+        _ => Err(SysError::ENOSYS),
+    }
+}
+
+// 删除 paddr 上的等待队列，并释放等待期间对页帧的引用
+fn remove_queue(queues: &mut BTreeMap<usize, Arc<WaitQueue>>, paddr: usize) {
+    if queues.remove(&paddr).is_some() {
+        frame_allocator::dealloc_frame(Frame::containing_address(paddr));
+    }
+}
+
+/// 写时复制把 frame 的内容复制到了新的页帧，唤醒在 frame 上等待的所有线程。
+/// 它们醒来后回到用户态重新检查，再次等待时使用新的物理地址
+pub fn frame_copied(frame: Frame) {
+    let start = frame.start_address();
+    let queues = FUTEX_QUEUES.lock();
+    for (_, queue) in queues.range(start..start + PAGE_SIZE) {
+        queue.notify_all();
+    }
+}
@@ -0,0 +1,108 @@
+//! 与用户地址空间相关的系统调用，堆和匿名映射都是延迟分配的
+
+use super::{SysResult, SysError};
+use crate::process;
+use crate::memory_set::{attr::MemoryAttr, handler::Delay, is_user_range, page_ceil};
+use crate::consts::{PAGE_SIZE, USER_MMAP_BASE, USER_MAP_LIMIT};
+
+const PROT_READ: usize = 0x1;
+const PROT_WRITE: usize = 0x2;
+const PROT_EXEC: usize = 0x4;
+
+const MAP_SHARED: usize = 0x01;
+const MAP_FIXED: usize = 0x10;
+const MAP_ANONYMOUS: usize = 0x20;
+
+/// 将堆顶设置为 addr ，返回新的堆顶；addr 为 0 或者设置失败时返回当前的堆顶。
+/// 堆不能增长到栈可能占据的空间，也不能经过内核保留的地址
+pub fn sys_brk(addr: usize) -> SysResult {
+    let process = process::current_process().ok_or(SysError::ESRCH)?;
+    let mut process = process.lock();
+    if addr < process.brk_start || addr > USER_MAP_LIMIT {
+        return Ok(process.brk);
+    }
+    let start = process.brk_start;
+    if page_ceil(addr) > start && !is_user_range(start, page_ceil(addr)) {
+        return Ok(process.brk);
+    }
+    let old_end = page_ceil(process.brk);
+    let new_end = page_ceil(addr);
+    let ok = if new_end == old_end {
+        true
+    } else if old_end == start {
+        // 堆原来是空的
+        if process.vm.test_free_area(start, new_end) {
+            process.vm.push(start, new_end, user_attr(PROT_READ | PROT_WRITE), Delay::new(), None);
+            true
+        } else {
+            false
+        }
+    } else if new_end == start {
+        process.vm.pop(start, old_end);
+        true
+    } else {
+        process.vm.resize_area(start, start, new_end)
+    };
+    if ok {
+        process.brk = addr;
+    }
+    Ok(process.brk)
+}
+
+/// 目前只支持私有的匿名映射，fd 和 offset 被忽略
+pub fn sys_mmap(addr: usize, len: usize, prot: usize, flags: usize, _fd: usize, _offset: usize) -> SysResult {
+    if len == 0 || addr % PAGE_SIZE != 0 {
+        return Err(SysError::EINVAL);
+    }
+    if flags & MAP_ANONYMOUS == 0 || flags & MAP_SHARED != 0 {
+        return Err(SysError::ENOSYS);
+    }
+    if len > USER_MAP_LIMIT {
+        return Err(SysError::ENOMEM);
+    }
+    let len = page_ceil(len);
+    let process = process::current_process().ok_or(SysError::ESRCH)?;
+    let mut process = process.lock();
+    let start = if flags & MAP_FIXED != 0 {
+        if addr == 0 || !in_map_range(addr, len) {
+            return Err(SysError::EINVAL);
+        }
+        process.vm.pop(addr, addr + len);
+        addr
+    } else {
+        let hint = if addr == 0 || !in_map_range(addr, len) { USER_MMAP_BASE } else { addr };
+        process.vm
+            .find_free_area(hint, len, USER_MAP_LIMIT)
+            .ok_or(SysError::ENOMEM)?
+    };
+    process.vm.push(start, start + len, user_attr(prot), Delay::new(), None);
+    Ok(start)
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> SysResult {
+    if len == 0 || addr % PAGE_SIZE != 0 || !in_map_range(addr, len) {
+        return Err(SysError::EINVAL);
+    }
+    let process = process::current_process().ok_or(SysError::ESRCH)?;
+    process.lock().vm.pop(addr, addr + page_ceil(len));
+    Ok(0)
+}
+
+// [addr, addr + len) 是否位于栈可能占据的空间之下，并且不经过内核保留的地址
+fn in_map_range(addr: usize, len: usize) -> bool {
+    addr < USER_MAP_LIMIT && len <= USER_MAP_LIMIT - addr && is_user_range(addr, addr + len)
+}
+
+fn user_attr(prot: usize) -> MemoryAttr {
+    let mut attr = MemoryAttr::new().set_user();
+    if prot & PROT_READ != 0 {
+        attr = attr.set_readonly();
+    }
+    if prot & PROT_WRITE != 0 {
+        attr = attr.set_WR();
+    }
+    if prot & PROT_EXEC != 0 {
+        attr = attr.set_execute();
+    }
+    attr
+}
@@ -0,0 +1,212 @@
+//! 系统调用分发
+//!
+//! 用户态执行 ecall 后进入 rust_trap ，由 `interrupt::syscall` 把 x17 中的调用号和
+//! x10 ~ x15 中的参数交给这里的 `syscall` 进行分发，返回值会被写回 x10 。
+//! 新的系统调用只需要在这里登记调用号，并在对应的子模块中实现即可。
+
+mod fs;
+pub mod futex;
+mod mem;
+mod proc;
+
+use crate::context::TrapFrame;
+use crate::consts::PAGE_SIZE;
+use crate::new_memory::access_pa_via_va;
+use crate::process;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ptr;
+use self::fs::*;
+use self::futex::*;
+use self::mem::*;
+use self::proc::*;
+
+pub const SYS_GETCWD: usize = 17;
+pub const SYS_DUP: usize = 23;
+pub const SYS_IOCTL: usize = 29;
+pub const SYS_MKDIRAT: usize = 34;
+pub const SYS_UNLINKAT: usize = 35;
+pub const SYS_LINKAT: usize = 37;
+pub const SYS_RENAMEAT: usize = 38;
+pub const SYS_FTRUNCATE: usize = 46;
+pub const SYS_CHDIR: usize = 49;
+pub const SYS_OPENAT: usize = 56;
+pub const SYS_CLOSE: usize = 57;
+pub const SYS_GETDENTS64: usize = 61;
+pub const SYS_LSEEK: usize = 62;
+pub const SYS_READ: usize = 63;
+pub const SYS_WRITE: usize = 64;
+pub const SYS_READLINKAT: usize = 78;
+pub const SYS_FSTAT: usize = 80;
+pub const SYS_SYNC: usize = 81;
+pub const SYS_FSYNC: usize = 82;
+pub const SYS_EXIT: usize = 93;
+pub const SYS_EXIT_GROUP: usize = 94;
+pub const SYS_FUTEX: usize = 98;
+pub const SYS_SLEEP: usize = 101;
+pub const SYS_CLONE: usize = 120;
+pub const SYS_YIELD: usize = 124;
+pub const SYS_SETPRIORITY: usize = 140;
+pub const SYS_SETPGID: usize = 154;
+pub const SYS_GETPGID: usize = 155;
+pub const SYS_GETPID: usize = 172;
+pub const SYS_GETPPID: usize = 173;
+pub const SYS_GETTID: usize = 178;
+pub const SYS_BRK: usize = 214;
+pub const SYS_MUNMAP: usize = 215;
+pub const SYS_FORK: usize = 220;
+pub const SYS_EXEC: usize = 221;
+pub const SYS_MMAP: usize = 222;
+pub const SYS_WAIT: usize = 260;
+
+/// 系统调用的返回值：成功时为一个非负数，失败时为错误码
+pub type SysResult = Result<usize, SysError>;
+
+/// 错误码，取值与 Linux 保持一致，返回给用户态时取负
+#[allow(dead_code)]
+#[repr(isize)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SysError {
+    EPERM = 1,
+    ENOENT = 2,
+    ESRCH = 3,
+    EINTR = 4,
+    EIO = 5,
+    ENOEXEC = 8,
+    EBADF = 9,
+    ECHILD = 10,
+    EAGAIN = 11,
+    ENOMEM = 12,
+    EACCES = 13,
+    EFAULT = 14,
+    EEXIST = 17,
+    EXDEV = 18,
+    ENOTDIR = 20,
+    EISDIR = 21,
+    EINVAL = 22,
+    EMFILE = 24,
+    ENOTTY = 25,
+    ENOSPC = 28,
+    ESPIPE = 29,
+    EROFS = 30,
+    ERANGE = 34,
+    ENOSYS = 38,
+    ENOTEMPTY = 39,
+    ELOOP = 40,
+}
+
+/// 根据调用号分发系统调用，args 依次对应 x10 ~ x15
+pub fn syscall(id: usize, args: [usize; 6], tf: &mut TrapFrame) -> isize {
+    let ret = match id {
+        SYS_GETCWD => sys_getcwd(args[0] as *mut u8, args[1]),
+        SYS_DUP => sys_dup(args[0]),
+        SYS_IOCTL => sys_ioctl(args[0], args[1], args[2]),
+        SYS_MKDIRAT => sys_mkdirat(args[0], args[1] as *const u8, args[2]),
+        SYS_UNLINKAT => sys_unlinkat(args[0], args[1] as *const u8, args[2]),
+        SYS_LINKAT => sys_linkat(args[0], args[1] as *const u8, args[2], args[3] as *const u8),
+        SYS_RENAMEAT => sys_renameat(args[0], args[1] as *const u8, args[2], args[3] as *const u8),
+        SYS_FTRUNCATE => sys_ftruncate(args[0], args[1]),
+        SYS_CHDIR => sys_chdir(args[0] as *const u8),
+        SYS_OPENAT => sys_openat(args[0], args[1] as *const u8, args[2], args[3]),
+        SYS_CLOSE => sys_close(args[0]),
+        SYS_GETDENTS64 => sys_getdents64(args[0], args[1] as *mut u8, args[2]),
+        SYS_LSEEK => sys_lseek(args[0], args[1] as isize, args[2]),
+        SYS_READ => sys_read(args[0], args[1] as *mut u8, args[2]),
+        SYS_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
+        SYS_READLINKAT => sys_readlinkat(args[0], args[1] as *const u8, args[2] as *mut u8, args[3]),
+        SYS_FSTAT => sys_fstat(args[0], args[1] as *mut Stat),
+        SYS_SYNC => sys_sync(),
+        SYS_FSYNC => sys_fsync(args[0]),
+        SYS_EXIT => sys_exit(args[0]),
+        SYS_EXIT_GROUP => sys_exit_group(args[0]),
+        SYS_FUTEX => sys_futex(args[0], args[1], args[2]),
+        SYS_SLEEP => sys_sleep(args[0]),
+        SYS_CLONE => sys_clone(args[0], args[1], args[2], args[3]),
+        SYS_YIELD => sys_yield(),
+        SYS_SETPRIORITY => sys_set_priority(args[0]),
+        SYS_SETPGID => sys_setpgid(args[0], args[1]),
+        SYS_GETPGID => sys_getpgid(args[0]),
+        SYS_GETPID => sys_getpid(),
+        SYS_GETPPID => sys_getppid(),
+        SYS_GETTID => sys_gettid(),
+        SYS_BRK => sys_brk(args[0]),
+        SYS_MUNMAP => sys_munmap(args[0], args[1]),
+        SYS_FORK => sys_fork(tf),
+        SYS_EXEC => sys_exec(args[0] as *const u8, tf),
+        SYS_MMAP => sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5]),
+        SYS_WAIT => sys_wait(args[0] as isize, args[1] as *mut i32),
+        _ => {
+            println!("unknown syscall id: {}, args: {:x?}", id, args);
+            Err(SysError::ENOSYS)
+        }
+    };
+    match ret {
+        Ok(code) => code as isize,
+        Err(err) => -(err as isize),
+    }
+}
+
+/// 检查用户传入的缓冲区是否位于用户地址空间内
+fn check_user_buffer(base: usize, len: usize) -> Result<(), SysError> {
+    use crate::consts::KERNEL_OFFSET;
+    if base == 0 {
+        return Err(SysError::EFAULT);
+    }
+    match base.checked_add(len) {
+        Some(end) if end <= KERNEL_OFFSET => Ok(()),
+        _ => Err(SysError::EFAULT),
+    }
+}
+
+/// 逐页找到用户地址 [base, base + len) 所在的物理页帧，对每一段调用 f(内核中的地址, 在缓冲区中的偏移, 长度)。
+/// 不在任何区域中的地址返回 EFAULT ，write 为 true 时不可写的区域同样返回 EFAULT 。
+/// 访问期间持有进程的锁，内核不会在访问用户内存时缺页，也不会在持有文件系统的锁时访问用户内存
+fn access_user<F: FnMut(usize, usize, usize)>(base: usize, len: usize, write: bool, mut f: F) -> Result<(), SysError> {
+    check_user_buffer(base, len)?;
+    let process = process::current_process().ok_or(SysError::ESRCH)?;
+    let mut process = process.lock();
+    let mut offset = 0;
+    while offset < len {
+        let addr = base + offset;
+        let paddr = process.vm.translate(addr).ok_or(SysError::EFAULT)?;
+        if write && !process.vm.find_area(addr).map_or(false, |area| area.attr.is_writable()) {
+            return Err(SysError::EFAULT);
+        }
+        let n = (PAGE_SIZE - addr % PAGE_SIZE).min(len - offset);
+        f(access_pa_via_va(paddr), offset, n);
+        offset += n;
+    }
+    Ok(())
+}
+
+/// 把 data 复制到用户地址 base 处
+fn copy_to_user(base: usize, data: &[u8]) -> Result<(), SysError> {
+    access_user(base, data.len(), true, |dst, offset, n| unsafe {
+        ptr::copy_nonoverlapping(data[offset..].as_ptr(), dst as *mut u8, n);
+    })
+}
+
+/// 从用户地址 base 处复制 buf.len() 个字节到 buf
+fn copy_from_user(base: usize, buf: &mut [u8]) -> Result<(), SysError> {
+    access_user(base, buf.len(), false, |src, offset, n| unsafe {
+        ptr::copy_nonoverlapping(src as *const u8, buf[offset..].as_mut_ptr(), n);
+    })
+}
+
+/// 从用户态复制一个以 '\0' 结尾的字符串
+fn copy_from_user_cstr(ptr: *const u8) -> Result<String, SysError> {
+    const MAX_LEN: usize = 256;
+    let mut bytes = Vec::new();
+    loop {
+        let mut byte = [0u8];
+        copy_from_user(ptr as usize + bytes.len(), &mut byte)?;
+        if byte[0] == 0 {
+            break;
+        }
+        bytes.push(byte[0]);
+        if bytes.len() >= MAX_LEN {
+            return Err(SysError::EINVAL);
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| SysError::EINVAL)
+}
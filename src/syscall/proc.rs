@@ -0,0 +1,117 @@
+//! 与线程调度相关的系统调用
+
+use super::{SysResult, SysError, check_user_buffer, copy_from_user_cstr};
+use crate::process;
+use crate::fs::{self, FileType};
+use crate::clock;
+use crate::context::TrapFrame;
+
+// clone 的 flags ，目前只支持创建与调用者共享地址空间的线程，创建新进程使用 fork
+const CLONE_VM: usize = 0x100;
+const CLONE_THREAD: usize = 0x10000;
+
+/// 结束当前线程，进程的最后一个线程退出时进程随之退出
+pub fn sys_exit(code: usize) -> SysResult {
+    process::exit(code)
+}
+
+/// 结束当前进程的所有线程
+pub fn sys_exit_group(code: usize) -> SysResult {
+    process::exit_group(code)
+}
+
+pub fn sys_getpid() -> SysResult {
+    Ok(process::current_pid())
+}
+
+pub fn sys_gettid() -> SysResult {
+    Ok(process::current_tid())
+}
+
+pub fn sys_getppid() -> SysResult {
+    let process = process::current_process().ok_or(SysError::ESRCH)?;
+    let parent = process.lock().parent;
+    Ok(parent.unwrap_or(0))
+}
+
+/// 把进程 pid 移到进程组 pgid 中，pid 为 0 表示当前进程，pgid 为 0 表示与 pid 相同
+pub fn sys_setpgid(pid: usize, pgid: usize) -> SysResult {
+    let pid = if pid == 0 { process::current_pid() } else { pid };
+    let pgid = if pgid == 0 { pid } else { pgid };
+    if !process::set_pgid(pid, pgid) {
+        return Err(SysError::ESRCH);
+    }
+    Ok(0)
+}
+
+/// 进程 pid 所在的进程组，pid 为 0 表示当前进程
+pub fn sys_getpgid(pid: usize) -> SysResult {
+    let pid = if pid == 0 { process::current_pid() } else { pid };
+    process::get_pgid(pid).ok_or(SysError::ESRCH)
+}
+
+pub fn sys_fork(tf: &mut TrapFrame) -> SysResult {
+    process::fork(tf).ok_or(SysError::EAGAIN)
+}
+
+/// 在当前进程中创建一个线程，从 entry(arg) 开始运行，stack 为它的用户栈栈顶，返回新线程的 tid
+pub fn sys_clone(entry: usize, stack: usize, arg: usize, flags: usize) -> SysResult {
+    if flags & (CLONE_VM | CLONE_THREAD) != CLONE_VM | CLONE_THREAD {
+        return Err(SysError::EINVAL);
+    }
+    check_user_buffer(entry, 1)?;
+    check_user_buffer(stack, 0)?;
+    if process::current_process().is_none() {
+        return Err(SysError::ESRCH);
+    }
+    process::clone(entry, stack, arg).ok_or(SysError::EAGAIN)
+}
+
+/// 用 path 所指的 ELF 文件替换当前进程，相对路径从当前目录开始解析
+pub fn sys_exec(path: *const u8, tf: &mut TrapFrame) -> SysResult {
+    let path = copy_from_user_cstr(path)?;
+    let process = process::current_process().ok_or(SysError::ESRCH)?;
+    let cwd = process.lock().cwd.clone();
+    let inode = fs::lookup(&cwd, &path)?;
+    if inode.metadata()?.type_ != FileType::File {
+        return Err(SysError::EACCES);
+    }
+    let data = inode.read_as_vec()?;
+    process::exec(&data, tf).map_err(|_| SysError::ENOEXEC)?;
+    Ok(0)
+}
+
+/// 等待子进程退出，pid 为 -1 时等待任意子进程，退出码写入 code 所指的位置
+pub fn sys_wait(pid: isize, code: *mut i32) -> SysResult {
+    let target = if pid == -1 { None } else { Some(pid as usize) };
+    if !code.is_null() {
+        check_user_buffer(code as usize, core::mem::size_of::<i32>())?;
+    }
+    let (pid, exit_code) = process::wait(target).map_err(|err| match err {
+        process::WaitError::NoChild => SysError::ECHILD,
+        process::WaitError::Interrupted => SysError::EINTR,
+    })?;
+    if !code.is_null() {
+        unsafe {
+            *code = exit_code as i32;
+        }
+    }
+    Ok(pid)
+}
+
+pub fn sys_yield() -> SysResult {
+    process::yield_now();
+    Ok(0)
+}
+
+/// 设置当前线程的优先级，只有部分调度算法（如 stride）会使用它
+pub fn sys_set_priority(priority: usize) -> SysResult {
+    process::set_priority(priority);
+    Ok(0)
+}
+
+/// 睡眠 ms 毫秒，期间线程不参与调度，由时钟中断唤醒。ms 由用户给出，换算时不能溢出
+pub fn sys_sleep(ms: usize) -> SysResult {
+    process::sleep(ms.saturating_mul(clock::TICKS_PER_SEC).saturating_add(999) / 1000);
+    Ok(0)
+}
@@ -0,0 +1,343 @@
+//! 终端
+//!
+//! 串口的接收中断和键盘把收到的字符交给 `Tty::input` ，字符先放进一个环形缓冲区，
+//! 再由终端线程按行规程处理，结果放进另一个环形缓冲区，由读取 /dev/console 的线程取走。
+//! 规范模式下按行编辑输入：退格删除前一个字符，^U 删除整行，输入回车之后整行才能被读到；
+//! ^D 让已经输入的部分立即可读，在行首输入时表示文件结束；^C 丢弃当前行，并向前台进程组发送 SIGINT 。
+//! 非规范模式（原始模式）下字符到达后立即可读。回显可以单独打开或关闭。
+//! 用户程序通过 ioctl 的 TCGETS 和 TCSETS 读取和修改这些设置，通过 TIOCGPGRP 和 TIOCSPGRP 读取和设置前台进程组。
+//! 只有这一个终端，所有进程都以它为控制终端；前台进程组默认是 init 所在的组，shell 运行后台任务时
+//! 应当把它放进单独的进程组，否则 ^C 也会结束它。
+
+use crate::fs::{FsError, Result};
+use crate::process::{self, Pid, WaitQueue, INIT_PID, SIGINT};
+use crate::riscv::sbi;
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::mem;
+use lazy_static::*;
+
+// 每个环形缓冲区的容量，缓冲区满时丢弃新收到的字符
+const BUFFER_SIZE: usize = 1024;
+// 一次读操作最多读出的字符数
+const READ_CHUNK: usize = 256;
+
+// termios 中用到的标志位和控制字符的下标，与 Linux 相同
+pub const ICRNL: u32 = 0o400;
+pub const ISIG: u32 = 0o1;
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+pub const NCCS: usize = 19;
+pub const VINTR: usize = 0;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+
+const BACKSPACE: u8 = 0x08;
+
+/// 终端的设置，布局与 Linux 的 struct termios 相同。只使用 iflag 中的 ICRNL 、lflag 和 cc
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Termios {
+    pub iflag: u32,
+    pub oflag: u32,
+    pub cflag: u32,
+    pub lflag: u32,
+    pub line: u8,
+    pub cc: [u8; NCCS],
+}
+
+impl Default for Termios {
+    fn default() -> Termios {
+        let mut cc = [0; NCCS];
+        cc[VINTR] = 0x03;  // ^C
+        cc[VERASE] = 0x7f; // 串口终端的退格键发送 DEL
+        cc[VKILL] = 0x15;  // ^U
+        cc[VEOF] = 0x04;   // ^D
+        Termios { iflag: ICRNL, oflag: 0, cflag: 0, lflag: ISIG | ICANON | ECHO, line: 0, cc }
+    }
+}
+
+struct RingBuffer {
+    buf: [u8; BUFFER_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl RingBuffer {
+    fn new() -> RingBuffer {
+        RingBuffer { buf: [0; BUFFER_SIZE], head: 0, len: 0 }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    // 缓冲区已满时丢弃 ch ，返回 false
+    fn push(&mut self, ch: u8) -> bool {
+        if self.len == BUFFER_SIZE {
+            return false;
+        }
+        self.buf[(self.head + self.len) % BUFFER_SIZE] = ch;
+        self.len += 1;
+        true
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let ch = self.buf[self.head];
+        self.head = (self.head + 1) % BUFFER_SIZE;
+        self.len -= 1;
+        Some(ch)
+    }
+}
+
+struct Inner {
+    termios: Termios,
+    line: Vec<u8>,     // 规范模式下正在编辑的行
+    ready: RingBuffer, // 可以被读到的字符
+    // 规范模式下 ready 中依次每一段的长度，一次读操作不会跨越两段，长度为 0 的段表示文件结束
+    segments: VecDeque<usize>,
+    interrupts: usize, // 收到 ^C 的次数
+    foreground: Pid,   // 前台进程组
+}
+
+impl Inner {
+    fn canonical(&self) -> bool {
+        self.termios.lflag & ICANON != 0
+    }
+
+    // 正在编辑的行成为可读的一段
+    fn finish_line(&mut self) {
+        let line = mem::replace(&mut self.line, Vec::new());
+        for &ch in line.iter() {
+            self.ready.push(ch);
+        }
+        self.segments.push_back(line.len());
+    }
+
+    // 从 ready 中读出至多 buf.len() 个字符，没有可读的内容时返回 None
+    fn take(&mut self, buf: &mut [u8]) -> Option<usize> {
+        let available = if self.canonical() {
+            *self.segments.front()?
+        } else if self.ready.len() > 0 {
+            self.ready.len()
+        } else {
+            return None;
+        };
+        let count = available.min(buf.len());
+        for ch in buf[..count].iter_mut() {
+            *ch = self.ready.pop().unwrap();
+        }
+        if self.canonical() {
+            if count == available {
+                self.segments.pop_front();
+            } else {
+                self.segments[0] -= count;
+            }
+        }
+        Some(count)
+    }
+}
+
+pub struct Tty {
+    received: Mutex<RingBuffer>, // 收到、还没有经过行规程处理的字符
+    receiving: WaitQueue,        // 终端线程在这里等待新的字符
+    inner: Mutex<Inner>,
+    readable: WaitQueue,         // 读取终端的线程在这里等待
+}
+
+lazy_static! {
+    pub static ref TTY: Tty = Tty {
+        received: Mutex::new(RingBuffer::new()),
+        receiving: WaitQueue::new(),
+        inner: Mutex::new(Inner {
+            termios: Termios::default(),
+            line: Vec::new(),
+            ready: RingBuffer::new(),
+            segments: VecDeque::new(),
+            interrupts: 0,
+            foreground: INIT_PID,
+        }),
+        readable: WaitQueue::new(),
+    };
+}
+
+/// 启动终端线程，处理收到的字符
+pub fn init() {
+    process::spawn(|| loop {
+        let ch = TTY.receive();
+        TTY.process(ch);
+        // 回显的字符没有换行也要立即显示
+        crate::device::console::flush();
+    });
+}
+
+impl Tty {
+    /// 收到一个字符，在中断处理函数中调用
+    pub fn input(&self, ch: u8) {
+        self.received.lock().push(ch);
+        self.receiving.notify_one();
+    }
+
+    /// 输出到串口和屏幕
+    pub fn write(&self, buf: &[u8]) {
+        for &ch in buf {
+            sbi::console_putchar(ch as usize);
+        }
+        crate::device::console::write(buf);
+    }
+
+    pub fn termios(&self) -> Termios {
+        self.inner.lock().termios
+    }
+
+    /// 修改终端的设置。离开规范模式时正在编辑的行立即可读，进入规范模式时已有的字符成为一段
+    pub fn set_termios(&self, termios: Termios) {
+        let mut inner = self.inner.lock();
+        let was_canonical = inner.canonical();
+        inner.termios = termios;
+        if was_canonical && !inner.canonical() {
+            inner.finish_line();
+            inner.segments.clear();
+        } else if !was_canonical && inner.canonical() {
+            let len = inner.ready.len();
+            inner.segments.clear();
+            if len > 0 {
+                inner.segments.push_back(len);
+            }
+        }
+        self.readable.notify_all();
+    }
+
+    pub fn foreground(&self) -> Pid {
+        self.inner.lock().foreground
+    }
+
+    pub fn set_foreground(&self, pgid: Pid) {
+        self.inner.lock().foreground = pgid;
+    }
+
+    /// 丢弃已经收到、还没有被读走的输入
+    pub fn flush_input(&self) {
+        self.received.lock().clear();
+        let mut inner = self.inner.lock();
+        inner.line.clear();
+        inner.ready.clear();
+        inner.segments.clear();
+    }
+
+    /// 规范模式下至多读到一行的末尾，非规范模式下读出已经收到的字符，一次最多 READ_CHUNK 个，没有可读的内容时睡眠等待。
+    /// 等待期间当前进程收到信号时返回 Interrupted
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // 等待输入之前显示还没有刷新的输出，例如不以换行结尾的提示符
+        crate::device::console::flush();
+        // 检查信号需要获取进程的锁，不能在持有终端的锁时进行。
+        // 信号总是在 ^C 的次数增加之前发出，次数变化之后重新检查就不会错过；
+        // 被杀死的线程由 kill 直接唤醒，醒来之后同样会检查到
+        let mut seen = self.inner.lock().interrupts;
+        loop {
+            if process::signal_pending() {
+                return Err(FsError::Interrupted);
+            }
+            let mut inner = self.inner.lock();
+            // buf 是用户的缓冲区，写入时可能发生缺页，不能在持有关闭中断的锁时写入。先读到栈上
+            let mut local = [0u8; READ_CHUNK];
+            let len = buf.len().min(local.len());
+            if let Some(count) = inner.take(&mut local[..len]) {
+                drop(inner);
+                buf[..count].copy_from_slice(&local[..count]);
+                return Ok(count);
+            }
+            if inner.interrupts != seen {
+                seen = inner.interrupts;
+                continue;
+            }
+            self.readable.wait_unlock(inner);
+        }
+    }
+
+    // 取出一个收到的字符，没有时睡眠等待
+    fn receive(&self) -> u8 {
+        loop {
+            let mut received = self.received.lock();
+            if let Some(ch) = received.pop() {
+                return ch;
+            }
+            self.receiving.wait_unlock(received);
+        }
+    }
+
+    // 按行规程处理一个收到的字符
+    fn process(&self, ch: u8) {
+        let mut inner = self.inner.lock();
+        let termios = inner.termios;
+        let echo = termios.lflag & ECHO != 0;
+        let ch = if ch == b'\r' && termios.iflag & ICRNL != 0 { b'\n' } else { ch };
+
+        if termios.lflag & ISIG != 0 && ch == termios.cc[VINTR] {
+            inner.line.clear();
+            let foreground = inner.foreground;
+            drop(inner);
+            if echo {
+                self.write(b"^C\n");
+            }
+            process::signal_group(foreground, SIGINT);
+            // 唤醒正在读终端的线程，让它们检查自己是否收到了信号
+            self.inner.lock().interrupts += 1;
+            self.readable.notify_all();
+            return;
+        }
+
+        if !inner.canonical() {
+            if inner.ready.push(ch) {
+                if echo {
+                    self.write(&[ch]);
+                }
+                self.readable.notify_all();
+            }
+            return;
+        }
+
+        // 正在编辑的行加上还没有读走的字符不能超过缓冲区的容量，并给换行符留出一个位置
+        let used = inner.line.len() + inner.ready.len();
+        if ch == termios.cc[VERASE] || ch == BACKSPACE {
+            if inner.line.pop().is_some() && echo {
+                self.write(b"\x08 \x08");
+            }
+        } else if ch == termios.cc[VKILL] {
+            while inner.line.pop().is_some() {
+                if echo {
+                    self.write(b"\x08 \x08");
+                }
+            }
+        } else if ch == termios.cc[VEOF] {
+            inner.finish_line();
+            self.readable.notify_all();
+        } else if ch == b'\n' {
+            if used < BUFFER_SIZE {
+                inner.line.push(ch);
+                if echo {
+                    self.write(b"\n");
+                }
+                inner.finish_line();
+                self.readable.notify_all();
+            }
+        } else if used + 1 < BUFFER_SIZE {
+            inner.line.push(ch);
+            if echo {
+                self.write(&[ch]);
+            }
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! mksfs：把宿主机上的一个目录打包成 SFS 磁盘镜像
+//!
+//! 用法：mksfs <目录> <镜像> [大小(KiB)]
+//! 不指定大小时，镜像在放下所有文件之后再留出 FREE_SPACE 字节的空闲空间。
+//! 磁盘布局见内核中的 src/fs/sfs.rs ，这里的常量必须与之一致。
+
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+const MAGIC: u32 = 0x2f8d_be2a;
+const BLKSIZE: usize = 4096;
+const BLKN_ROOT: usize = 1;
+const BLKN_FREEMAP: usize = 2;
+const NDIRECT: usize = 12;
+const NINDIRECT: usize = BLKSIZE / 4;
+const MAX_NAME_LEN: usize = 59;
+const ENTRY_SIZE: usize = 64;
+const TYPE_FILE: u16 = 1;
+const TYPE_DIR: u16 = 2;
+
+const FREE_SPACE: usize = 0x10_0000;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 || args.len() > 4 {
+        eprintln!("usage: {} <dir> <image> [size in KiB]", args[0]);
+        process::exit(1);
+    }
+    let size = match args.get(3).map(|size| size.parse::<usize>()) {
+        Some(Ok(kib)) => Some(kib * 1024),
+        Some(Err(_)) => {
+            eprintln!("mksfs: invalid size {}", args[3]);
+            process::exit(1);
+        }
+        None => None,
+    };
+    if let Err(err) = make_image(Path::new(&args[1]), Path::new(&args[2]), size) {
+        eprintln!("mksfs: {}", err);
+        process::exit(1);
+    }
+}
+
+fn make_image(dir: &Path, image: &Path, size: Option<usize>) -> io::Result<()> {
+    // 根目录的 inode 固定在 1 号块，不计入 used
+    let used = count_blocks(dir)? - 1;
+    let blocks = match size {
+        Some(size) => size / BLKSIZE,
+        None => {
+            let blocks = BLKN_FREEMAP + used + FREE_SPACE / BLKSIZE;
+            blocks + freemap_blocks(blocks)
+        }
+    };
+    let data_start = BLKN_FREEMAP + freemap_blocks(blocks);
+    if data_start + used > blocks {
+        return Err(error(format!("{} KiB is too small, need {} KiB", blocks * BLKSIZE / 1024,
+                                 (data_start + used) * BLKSIZE / 1024)));
+    }
+    let mut builder = Builder {
+        data: vec![0; blocks * BLKSIZE],
+        next_block: data_start,
+    };
+    builder.write_dir(dir, BLKN_ROOT)?;
+
+    // 超级块和空闲块位图，[0, next_block) 都已经使用
+    let used_blocks = builder.next_block;
+    builder.put_u32(0, MAGIC);
+    builder.put_u32(4, blocks as u32);
+    builder.put_u32(8, (blocks - used_blocks) as u32);
+    builder.put_u32(12, freemap_blocks(blocks) as u32);
+    for id in 0..used_blocks {
+        builder.data[BLKN_FREEMAP * BLKSIZE + id / 8] |= 1 << (id % 8);
+    }
+    fs::write(image, &builder.data)?;
+    println!("mksfs: {} blocks, {} used", blocks, used_blocks);
+    Ok(())
+}
+
+fn freemap_blocks(blocks: usize) -> usize {
+    (blocks + BLKSIZE * 8 - 1) / (BLKSIZE * 8)
+}
+
+fn error(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, msg)
+}
+
+struct Child {
+    name: String,
+    path: PathBuf,
+    is_dir: bool,
+}
+
+// 目录中要打包的文件和子目录，按名字排序。其他类型的文件被忽略
+fn children(dir: &Path) -> io::Result<Vec<Child>> {
+    let mut children = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let type_ = entry.file_type()?;
+        if !type_.is_dir() && !type_.is_file() {
+            eprintln!("mksfs: skipping {}", entry.path().display());
+            continue;
+        }
+        let name = entry.file_name().into_string()
+            .map_err(|name| error(format!("invalid file name {:?}", name)))?;
+        if name.len() > MAX_NAME_LEN {
+            return Err(error(format!("file name {} is too long", name)));
+        }
+        children.push(Child { name, path: entry.path(), is_dir: type_.is_dir() });
+    }
+    children.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(children)
+}
+
+// 保存 size 字节的内容需要的数据块和间接块数
+fn content_blocks(size: usize) -> io::Result<usize> {
+    let blocks = (size + BLKSIZE - 1) / BLKSIZE;
+    if blocks > NDIRECT + NINDIRECT {
+        return Err(error(format!("file of {} bytes is too large", size)));
+    }
+    Ok(if blocks > NDIRECT { blocks + 1 } else { blocks })
+}
+
+// 打包 path 需要的块数，包括 inode 所在的块
+fn count_blocks(path: &Path) -> io::Result<usize> {
+    if !fs::metadata(path)?.is_dir() {
+        return Ok(1 + content_blocks(fs::metadata(path)?.len() as usize)?);
+    }
+    let children = children(path)?;
+    let mut blocks = 1 + content_blocks(children.len() * ENTRY_SIZE)?;
+    for child in children.iter() {
+        blocks += count_blocks(&child.path)?;
+    }
+    Ok(blocks)
+}
+
+struct Builder {
+    data: Vec<u8>,
+    next_block: usize,
+}
+
+impl Builder {
+    fn alloc(&mut self) -> usize {
+        self.next_block += 1;
+        self.next_block - 1
+    }
+
+    fn put_u16(&mut self, offset: usize, value: u16) {
+        self.data[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u32(&mut self, offset: usize, value: u32) {
+        self.data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn write_dir(&mut self, dir: &Path, id: usize) -> io::Result<()> {
+        let mut content = Vec::new();
+        let mut subdirs = 0;
+        for child in children(dir)? {
+            let child_id = self.alloc();
+            if child.is_dir {
+                subdirs += 1;
+                self.write_dir(&child.path, child_id)?;
+            } else {
+                let mode = fs::metadata(&child.path)?.permissions().mode();
+                self.write_inode(child_id, TYPE_FILE, mode, 1, &fs::read(&child.path)?);
+            }
+            let mut entry = [0u8; ENTRY_SIZE];
+            entry[..4].copy_from_slice(&(child_id as u32).to_le_bytes());
+            entry[4..4 + child.name.len()].copy_from_slice(child.name.as_bytes());
+            content.extend_from_slice(&entry);
+        }
+        let mode = fs::metadata(dir)?.permissions().mode();
+        // 父目录中的目录项、自己的 "." 和每个子目录中的 ".."
+        self.write_inode(id, TYPE_DIR, mode, 2 + subdirs, &content);
+        Ok(())
+    }
+
+    // 在 id 号块写入 inode ，并为 content 分配数据块
+    fn write_inode(&mut self, id: usize, type_: u16, mode: u32, nlinks: u16, content: &[u8]) {
+        let blocks = (content.len() + BLKSIZE - 1) / BLKSIZE;
+        let indirect = if blocks > NDIRECT { self.alloc() } else { 0 };
+        let base = id * BLKSIZE;
+        for (index, chunk) in content.chunks(BLKSIZE).enumerate() {
+            let block = self.alloc();
+            self.data[block * BLKSIZE..block * BLKSIZE + chunk.len()].copy_from_slice(chunk);
+            if index < NDIRECT {
+                self.put_u32(base + 16 + index * 4, block as u32);
+            } else {
+                self.put_u32(indirect * BLKSIZE + (index - NDIRECT) * 4, block as u32);
+            }
+        }
+        self.put_u32(base, content.len() as u32);
+        self.put_u16(base + 4, type_);
+        self.put_u16(base + 6, nlinks);
+        self.put_u32(base + 8, blocks as u32);
+        self.put_u16(base + 12, (mode & 0o7777) as u16);
+        self.put_u32(base + 16 + NDIRECT * 4, indirect as u32);
+    }
+}
@@ -0,0 +1,75 @@
+//! 文件读写测试：分别在 /tmp （tmpfs）和 /mnt （virtio-blk 磁盘）上创建、读写、截断、重命名和删除文件
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate rust;
+
+use rust::syscall::*;
+
+const DATA: &[u8] = b"hello, sericaOS\n";
+
+fn test_dir(dir: &str) {
+    let mut path = [0u8; 64];
+    let path = join(dir, "filetest.txt", &mut path);
+    let mut new_path = [0u8; 64];
+    let new_path = join(dir, "filetest.new", &mut new_path);
+
+    let fd = sys_open(path, O_RDWR | O_CREAT | O_TRUNC);
+    assert!(fd >= 0, "{}: open failed: {}", path, fd);
+    let fd = fd as usize;
+    // 写两遍，第二次追加在后面
+    for _ in 0..2 {
+        assert_eq!(sys_write(fd, DATA.as_ptr(), DATA.len()), DATA.len() as i32);
+    }
+    let mut stat = Stat::default();
+    assert_eq!(sys_fstat(fd, &mut stat), 0);
+    assert_eq!(stat.mode & S_IFMT, S_IFREG);
+    assert_eq!(stat.size, 2 * DATA.len() as u64);
+
+    // 从第二份数据开始读，读到文件末尾时返回 0
+    assert_eq!(sys_lseek(fd, DATA.len() as isize, SEEK_SET), DATA.len() as i32);
+    let mut buf = [0u8; 64];
+    assert_eq!(sys_read(fd, buf.as_mut_ptr(), buf.len()), DATA.len() as i32);
+    assert_eq!(&buf[..DATA.len()], DATA);
+    assert_eq!(sys_read(fd, buf.as_mut_ptr(), buf.len()), 0);
+
+    assert_eq!(sys_ftruncate(fd, 5), 0);
+    assert_eq!(sys_fstat(fd, &mut stat), 0);
+    assert_eq!(stat.size, 5);
+    assert_eq!(sys_fsync(fd), 0);
+    assert_eq!(sys_close(fd), 0);
+
+    // 已经存在时 O_EXCL 应当失败
+    assert_eq!(sys_open(path, O_RDWR | O_CREAT | O_EXCL), -17);
+
+    assert_eq!(sys_rename(path, new_path), 0);
+    assert_eq!(sys_open(path, O_RDONLY), -2);
+    let fd = sys_open(new_path, O_RDONLY);
+    assert!(fd >= 0, "{}: open failed: {}", new_path, fd);
+    let fd = fd as usize;
+    assert_eq!(sys_read(fd, buf.as_mut_ptr(), buf.len()), 5);
+    assert_eq!(&buf[..5], &DATA[..5]);
+    assert_eq!(sys_close(fd), 0);
+
+    assert_eq!(sys_unlink(new_path), 0);
+    assert_eq!(sys_open(new_path, O_RDONLY), -2);
+}
+
+// 把 dir 和 name 拼接成路径，结果放在 buf 中
+fn join<'a>(dir: &str, name: &str, buf: &'a mut [u8; 64]) -> &'a str {
+    let len = dir.len() + 1 + name.len();
+    buf[..dir.len()].copy_from_slice(dir.as_bytes());
+    buf[dir.len()] = b'/';
+    buf[dir.len() + 1..len].copy_from_slice(name.as_bytes());
+    core::str::from_utf8(&buf[..len]).unwrap()
+}
+
+#[no_mangle]
+pub fn main() {
+    test_dir("/tmp");
+    test_dir("/mnt");
+    assert_eq!(sys_sync(), 0);
+    println!("filetest passed");
+}
@@ -0,0 +1,48 @@
+//! fork / wait 测试：创建若干子进程，每个子进程以不同的退出码退出，父进程逐个回收并检查退出码
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate rust;
+
+use rust::syscall::*;
+
+const CHILDREN: usize = 4;
+
+#[no_mangle]
+pub fn main() {
+    let ppid = sys_getpid();
+    let mut pids = [0i32; CHILDREN];
+    for i in 0..CHILDREN {
+        let pid = sys_fork();
+        assert!(pid >= 0, "fork failed: {}", pid);
+        if pid == 0 {
+            // 子进程
+            assert_eq!(sys_getppid(), ppid);
+            sys_exit_group(i + 1);
+        }
+        pids[i] = pid;
+    }
+    // 按创建的相反顺序等待指定的子进程
+    for i in (0..CHILDREN).rev() {
+        let mut code = 0;
+        assert_eq!(sys_waitpid(pids[i] as isize, &mut code), pids[i]);
+        assert_eq!(code, i as i32 + 1);
+    }
+    // 已经没有子进程了，wait 应当返回 -ECHILD
+    let mut code = 0;
+    assert_eq!(sys_wait(&mut code), -10);
+
+    // 父子进程的地址空间相互独立
+    let mut value: usize = 1;
+    let pid = sys_fork();
+    if pid == 0 {
+        value = 2;
+        sys_exit_group(value);
+    }
+    assert_eq!(sys_wait(&mut code), pid);
+    assert_eq!(code, 2);
+    assert_eq!(value, 1);
+    println!("forktest passed");
+}
@@ -0,0 +1,104 @@
+//! futex 测试：几个线程用基于 futex 的 Mutex 保护同一个计数器，主线程在 futex 上等待它们全部结束。
+//! 另外检查 fork 之后写时复制把等待中的字复制到新的页帧时，等待的线程仍然能被唤醒
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate rust;
+
+use core::sync::atomic::{AtomicI32, Ordering};
+use rust::sync::Mutex;
+use rust::syscall::*;
+
+const THREADS: usize = 4;
+const ROUNDS: usize = 1000;
+const STACK_SIZE: usize = 0x1000;
+
+// 新线程的栈由调用者分配，栈顶需要 16 字节对齐
+#[repr(C, align(16))]
+struct Stack([u8; STACK_SIZE]);
+
+// 最后一个栈留给 waiter
+static mut STACKS: [Stack; THREADS + 1] = [
+    Stack([0; STACK_SIZE]),
+    Stack([0; STACK_SIZE]),
+    Stack([0; STACK_SIZE]),
+    Stack([0; STACK_SIZE]),
+    Stack([0; STACK_SIZE]),
+];
+
+static COUNTER: Mutex<usize> = Mutex::new(0);
+// 已经结束的线程数，主线程在这个字上等待
+static DONE: AtomicI32 = AtomicI32::new(0);
+// waiter 在这个字变为非 0 之前一直等待
+static FLAG: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn worker(_arg: usize) -> ! {
+    for _ in 0..ROUNDS {
+        let mut counter = COUNTER.lock();
+        // 在持有锁时让出 CPU ，制造竞争
+        let value = *counter;
+        sys_yield();
+        *counter = value + 1;
+    }
+    DONE.fetch_add(1, Ordering::Release);
+    sys_futex(&DONE, FUTEX_WAKE, i32::max_value());
+    sys_exit(0)
+}
+
+extern "C" fn waiter(_arg: usize) -> ! {
+    while FLAG.load(Ordering::Acquire) == 0 {
+        sys_futex(&FLAG, FUTEX_WAIT, 0);
+    }
+    DONE.fetch_add(1, Ordering::Release);
+    sys_futex(&DONE, FUTEX_WAKE, i32::max_value());
+    sys_exit(0)
+}
+
+fn spawn(entry: extern "C" fn(usize) -> !, i: usize) {
+    let stack_top = unsafe { STACKS[i].0.as_ptr() as usize + STACK_SIZE };
+    let tid = sys_clone(entry, stack_top, i);
+    assert!(tid > 0, "clone failed: {}", tid);
+}
+
+// 等待 DONE 达到 count
+fn wait_done(count: usize) {
+    loop {
+        let done = DONE.load(Ordering::Acquire);
+        if done == count as i32 {
+            break;
+        }
+        sys_futex(&DONE, FUTEX_WAIT, done);
+    }
+}
+
+#[no_mangle]
+pub fn main() {
+    // *uaddr 不等于 val 时 FUTEX_WAIT 立即返回 -EAGAIN
+    let word = AtomicI32::new(1);
+    assert_eq!(sys_futex(&word, FUTEX_WAIT, 0), -11);
+    // 没有线程在等待时 FUTEX_WAKE 返回 0
+    assert_eq!(sys_futex(&word, FUTEX_WAKE, 1), 0);
+
+    for i in 0..THREADS {
+        spawn(worker, i);
+    }
+    wait_done(THREADS);
+    assert_eq!(*COUNTER.lock(), THREADS * ROUNDS);
+
+    // waiter 睡眠之后 fork ，FLAG 所在的页变成写时复制，下面的写入会把它复制到新的页帧
+    spawn(waiter, THREADS);
+    sys_sleep(10);
+    let pid = sys_fork();
+    assert!(pid >= 0, "fork failed: {}", pid);
+    if pid == 0 {
+        sys_exit_group(0);
+    }
+    FLAG.store(1, Ordering::Release);
+    sys_futex(&FLAG, FUTEX_WAKE, 1);
+    wait_done(THREADS + 1);
+    let mut code = 0;
+    assert_eq!(sys_waitpid(pid as isize, &mut code), pid);
+    println!("futextest passed");
+}
@@ -1,14 +1,55 @@
+//! init 进程：依次运行 /bin 下的测试程序，等待每个程序退出并报告结果
+
 #![no_std]
 #![no_main]
 
 #[macro_use]
 extern crate rust;
 
+use rust::io::STDIN;
+use rust::syscall::*;
+
+const TESTS: &[&str] = &[
+    "/bin/forktest",
+    "/bin/filetest",
+    "/bin/futextest",
+    "/bin/threadtest",
+    "/bin/ttytest",
+];
+
+// exec 失败时子进程的退出码
+const EXEC_FAILED: usize = 127;
+
 #[no_mangle]
 pub fn main() {
-    println!("Hello, world!");
-    for j in 0..1000 {
-    }
+    let mut termios = Termios::default();
+    sys_tcgetattr(STDIN, &mut termios);
+    let pgid = sys_getpgid(0) as usize;
 
-    println!("end user main");
-}
\ No newline at end of file
+    let mut failed = 0;
+    for &path in TESTS {
+        let pid = sys_fork();
+        if pid < 0 {
+            println!("{}: fork failed: {}", path, pid);
+            failed += 1;
+            continue;
+        }
+        if pid == 0 {
+            let ret = sys_exec(path);
+            println!("{}: exec failed: {}", path, ret);
+            sys_exit_group(EXEC_FAILED);
+        }
+        let mut code = 0;
+        sys_waitpid(pid as isize, &mut code);
+        if code == 0 {
+            println!("{}: ok", path);
+        } else {
+            println!("{}: FAILED with exit code {}", path, code);
+            failed += 1;
+        }
+        // 测试程序可能中途失败，恢复它修改过的终端设置和前台进程组
+        sys_tcsetattr(STDIN, &termios);
+        sys_tcsetpgrp(STDIN, pgid);
+    }
+    println!("{}/{} tests passed", TESTS.len() - failed, TESTS.len());
+}
@@ -0,0 +1,78 @@
+//! 线程测试：clone 出的线程共享地址空间、有各自的 tid ，sys_exit 只结束一个线程，
+//! 而任何一个线程调用 sys_exit_group 都会结束整个进程，包括正在睡眠的线程
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate rust;
+
+use core::sync::atomic::{AtomicI32, Ordering};
+use rust::syscall::*;
+
+const THREADS: usize = 3;
+const STACK_SIZE: usize = 0x1000;
+
+// 新线程的栈由调用者分配，栈顶需要 16 字节对齐
+#[repr(C, align(16))]
+struct Stack([u8; STACK_SIZE]);
+
+static mut STACKS: [Stack; THREADS] = [
+    Stack([0; STACK_SIZE]),
+    Stack([0; STACK_SIZE]),
+    Stack([0; STACK_SIZE]),
+];
+
+static TID: AtomicI32 = AtomicI32::new(0);
+// 永远不会被唤醒的字，线程在上面睡眠直到被 exit_group 结束
+static FOREVER: AtomicI32 = AtomicI32::new(0);
+
+fn spawn(entry: extern "C" fn(usize) -> !, i: usize) {
+    let stack_top = unsafe { STACKS[i].0.as_ptr() as usize + STACK_SIZE };
+    let tid = sys_clone(entry, stack_top, i);
+    assert!(tid > 0, "clone failed: {}", tid);
+}
+
+// 记下自己的 tid 后只结束自己
+extern "C" fn report_tid(_arg: usize) -> ! {
+    TID.store(sys_gettid(), Ordering::Release);
+    sys_futex(&TID, FUTEX_WAKE, 1);
+    sys_exit(0)
+}
+
+extern "C" fn sleep_forever(_arg: usize) -> ! {
+    loop {
+        sys_futex(&FOREVER, FUTEX_WAIT, 0);
+    }
+}
+
+extern "C" fn exit_group(_arg: usize) -> ! {
+    sys_sleep(10);
+    sys_exit_group(42)
+}
+
+#[no_mangle]
+pub fn main() {
+    // 线程写入的内存对主线程可见，并且 tid 与主线程不同
+    spawn(report_tid, 0);
+    while TID.load(Ordering::Acquire) == 0 {
+        sys_futex(&TID, FUTEX_WAIT, 0);
+    }
+    let tid = TID.load(Ordering::Acquire);
+    assert!(tid != sys_gettid());
+
+    // 子进程中一个线程调用 exit_group ，其他线程都在 futex 上睡眠
+    let pid = sys_fork();
+    assert!(pid >= 0, "fork failed: {}", pid);
+    if pid == 0 {
+        spawn(sleep_forever, 1);
+        spawn(exit_group, 2);
+        loop {
+            sys_futex(&FOREVER, FUTEX_WAIT, 0);
+        }
+    }
+    let mut code = 0;
+    assert_eq!(sys_waitpid(pid as isize, &mut code), pid);
+    assert_eq!(code, 42);
+    println!("threadtest passed");
+}
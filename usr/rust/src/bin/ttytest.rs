@@ -0,0 +1,56 @@
+//! 终端 ioctl 测试：读取终端设置，切换到原始模式再恢复，并检查非终端文件上的 ioctl 返回 -ENOTTY
+
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate rust;
+
+use rust::io::STDIN;
+use rust::syscall::*;
+
+#[no_mangle]
+pub fn main() {
+    let mut old = Termios::default();
+    assert_eq!(sys_tcgetattr(STDIN, &mut old), 0);
+    // 终端默认处于规范模式并且打开回显
+    assert!(old.lflag & ICANON != 0);
+    assert!(old.lflag & ECHO != 0);
+
+    let mut raw = old;
+    raw.lflag &= !(ICANON | ECHO | ISIG);
+    raw.iflag &= !ICRNL;
+    assert_eq!(sys_tcsetattr(STDIN, &raw), 0);
+    let mut now = Termios::default();
+    assert_eq!(sys_tcgetattr(STDIN, &mut now), 0);
+    assert_eq!(now.lflag & (ICANON | ECHO | ISIG), 0);
+    assert_eq!(now.iflag & ICRNL, 0);
+
+    assert_eq!(sys_tcsetattr(STDIN, &old), 0);
+    assert_eq!(sys_tcgetattr(STDIN, &mut now), 0);
+    assert_eq!(now.lflag, old.lflag);
+    assert_eq!(now.iflag, old.iflag);
+
+    // 进程组：把自己放进新的进程组，设为终端的前台进程组，再把前台还给原来的进程组
+    let old_pgrp = read_pgrp();
+    let pid = sys_getpid();
+    assert_eq!(sys_setpgid(0, 0), 0);
+    assert_eq!(sys_getpgid(0), pid);
+    assert_eq!(sys_tcsetpgrp(STDIN, pid as usize), 0);
+    assert_eq!(read_pgrp(), pid);
+    assert_eq!(sys_tcsetpgrp(STDIN, old_pgrp as usize), 0);
+
+    // 普通文件不是终端
+    let fd = sys_open("/tmp/ttytest", O_RDWR | O_CREAT);
+    assert!(fd >= 0, "open failed: {}", fd);
+    assert_eq!(sys_tcgetattr(fd as usize, &mut now), -25);
+    assert_eq!(sys_close(fd as usize), 0);
+    assert_eq!(sys_unlink("/tmp/ttytest"), 0);
+    println!("ttytest passed");
+}
+
+fn read_pgrp() -> i32 {
+    let mut pgrp = 0i32;
+    assert_eq!(sys_ioctl(STDIN, TIOCGPGRP, &mut pgrp as *mut i32 as usize), 0);
+    pgrp
+}
@@ -27,12 +27,21 @@ macro_rules! println {
     ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
 }
 
+pub const STDIN: usize = 0;
+pub const STDOUT: usize = 1;
+
 pub fn putchar(ch: char) {
-    syscall::sys_write(ch as u8);
+    let mut buf = [0u8; 4];
+    puts(ch.encode_utf8(&mut buf));
 }
 
 pub fn puts(s: &str) {
-    for ch in s.chars() {
-        putchar(ch);
-    }
+    syscall::sys_write(STDOUT, s.as_ptr(), s.len());
+}
+
+// 阻塞读入一个字符
+pub fn getc() -> u8 {
+    let mut ch = 0u8;
+    syscall::sys_read(STDIN, &mut ch, 1);
+    ch
 }
@@ -29,15 +29,15 @@ fn panic(info: &PanicInfo) -> ! {
         location.line(),
         message
     );
-
-    loop {}
+    // 结束整个进程，父进程可以从退出码得知失败
+    sys_exit_group(1)
 }
 
 #[no_mangle]
 pub extern "C" fn _start(_argc: isize, _argv: *const *const u8) -> ! {
     init_heap();
     main();
-    sys_exit(0)
+    sys_exit_group(0)
 }
 
 #[no_mangle]
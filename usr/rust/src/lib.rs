@@ -16,6 +16,7 @@ pub mod io;
 
 pub mod lang_item;
 pub mod syscall;
+pub mod sync;
 pub mod linked_list_allocator;
 
 use crate::linked_list_allocator::LockedHeap;
@@ -0,0 +1,161 @@
+//! 基于 futex 的同步原语
+//!
+//! 没有竞争时只需要原子操作，不会进入内核；需要等待时通过 futex 在对应的字上睡眠。
+
+use crate::syscall::{sys_futex, FUTEX_WAIT, FUTEX_WAKE};
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicI32, Ordering};
+
+fn futex_wait(word: &AtomicI32, val: i32) {
+    sys_futex(word, FUTEX_WAIT, val);
+}
+
+fn futex_wake(word: &AtomicI32, count: i32) {
+    sys_futex(word, FUTEX_WAKE, count);
+}
+
+// Mutex 的状态
+const UNLOCKED: i32 = 0;
+const LOCKED: i32 = 1;
+// 已被锁住，并且可能有线程在等待，解锁时需要唤醒
+const CONTENDED: i32 = 2;
+
+pub struct Mutex<T: ?Sized> {
+    state: AtomicI32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Mutex<T> {
+        Mutex {
+            state: AtomicI32::new(UNLOCKED),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub fn lock(&self) -> MutexGuard<T> {
+        if self.state.compare_and_swap(UNLOCKED, LOCKED, Ordering::Acquire) != UNLOCKED {
+            // 进入等待之前把状态改为 CONTENDED ，持有锁的线程解锁时就知道需要唤醒
+            while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+                futex_wait(&self.state, CONTENDED);
+            }
+        }
+        MutexGuard { mutex: self }
+    }
+
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        if self.state.compare_and_swap(UNLOCKED, LOCKED, Ordering::Acquire) == UNLOCKED {
+            Some(MutexGuard { mutex: self })
+        } else {
+            None
+        }
+    }
+
+    fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex_wake(&self.state, 1);
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+/// 条件变量，每次通知都会改变序号，等待的线程在旧的序号上睡眠，因此不会错过释放锁之后发出的通知
+pub struct Condvar {
+    seq: AtomicI32,
+}
+
+impl Condvar {
+    pub const fn new() -> Condvar {
+        Condvar {
+            seq: AtomicI32::new(0),
+        }
+    }
+
+    /// 释放 guard 对应的锁并等待通知，返回前重新获得锁。可能被虚假唤醒，调用者需要重新检查条件
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex;
+        let seq = self.seq.load(Ordering::Relaxed);
+        drop(guard);
+        futex_wait(&self.seq, seq);
+        mutex.lock()
+    }
+
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        futex_wake(&self.seq, 1);
+    }
+
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        futex_wake(&self.seq, i32::max_value());
+    }
+}
+
+// Once 的状态
+const INCOMPLETE: i32 = 0;
+const RUNNING: i32 = 1;
+const COMPLETE: i32 = 2;
+
+/// 保证一段初始化代码只执行一次，其他线程等待它执行完毕
+pub struct Once {
+    state: AtomicI32,
+}
+
+impl Once {
+    pub const fn new() -> Once {
+        Once {
+            state: AtomicI32::new(INCOMPLETE),
+        }
+    }
+
+    pub fn call_once<F: FnOnce()>(&self, f: F) {
+        if self.state.load(Ordering::Acquire) == COMPLETE {
+            return;
+        }
+        if self.state.compare_and_swap(INCOMPLETE, RUNNING, Ordering::Acquire) == INCOMPLETE {
+            f();
+            self.state.store(COMPLETE, Ordering::Release);
+            futex_wake(&self.state, i32::max_value());
+        } else {
+            while self.state.load(Ordering::Acquire) != COMPLETE {
+                futex_wait(&self.state, RUNNING);
+            }
+        }
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.state.load(Ordering::Acquire) == COMPLETE
+    }
+}
@@ -1,4 +1,6 @@
-/// x17 寄存器保存了用户产生的系统调用的编号，x10~x13 寄存器保存了系统调用的参数。
+use core::sync::atomic::AtomicI32;
+
+/// x17 寄存器保存了用户产生的系统调用的编号，x10~x15 寄存器保存了系统调用的参数。
 /// 用户态产生系统调用后会进入内核态，在内核态中对产生的 syscall 进行处理
 #[inline(always)]
 fn sys_call(
@@ -7,29 +9,385 @@ fn sys_call(
     arg1: usize,
     arg2: usize,
     arg3: usize,
+    arg4: usize,
+    arg5: usize,
 ) -> i32 {
     let id = syscall_id as usize;
     let mut ret: i32;
     unsafe {
         asm!("ecall"
             : "={x10}" (ret)
-            : "{x17}" (id), "{x10}" (arg0), "{x11}" (arg1), "{x12}" (arg2), "{x13}" (arg3)
+            : "{x17}" (id), "{x10}" (arg0), "{x11}" (arg1), "{x12}" (arg2), "{x13}" (arg3), "{x14}" (arg4), "{x15}" (arg5)
             : "memory"
             : "volatile");
     }
     ret
 }
 
-pub fn sys_write(ch : u8) -> i32 {
-    sys_call(SyscallId::Write, ch as usize, 0, 0, 0)
+pub fn sys_read(fd: usize, base: *mut u8, len: usize) -> i32 {
+    sys_call(SyscallId::Read, fd, base as usize, len, 0, 0, 0)
+}
+
+pub fn sys_write(fd: usize, base: *const u8, len: usize) -> i32 {
+    sys_call(SyscallId::Write, fd, base as usize, len, 0, 0, 0)
 }
 
+/// 结束当前线程，进程的最后一个线程退出时进程也随之退出
 pub fn sys_exit(code: usize) -> ! {
-    sys_call(SyscallId::Exit, code, 0, 0, 0);
+    sys_call(SyscallId::Exit, code, 0, 0, 0, 0, 0);
     loop{}
 }
 
+/// 结束整个进程，包括其中所有的线程
+pub fn sys_exit_group(code: usize) -> ! {
+    sys_call(SyscallId::ExitGroup, code, 0, 0, 0, 0, 0);
+    loop{}
+}
+
+const CLONE_VM: usize = 0x100;
+const CLONE_THREAD: usize = 0x10000;
+
+/// 创建一个与当前线程共享地址空间的线程，在栈顶为 stack_top 的栈上运行 entry(arg) ，
+/// 返回新线程的 tid 。新线程的栈需要由调用者分配，entry 结束时应当调用 sys_exit
+pub fn sys_clone(entry: extern "C" fn(usize) -> !, stack_top: usize, arg: usize) -> i32 {
+    sys_call(SyscallId::Clone, entry as usize, stack_top, arg, CLONE_VM | CLONE_THREAD, 0, 0)
+}
+
+pub fn sys_gettid() -> i32 {
+    sys_call(SyscallId::GetTid, 0, 0, 0, 0, 0, 0)
+}
+
+pub const FUTEX_WAIT: usize = 0;
+pub const FUTEX_WAKE: usize = 1;
+
+/// FUTEX_WAIT 在 *uaddr == val 时睡眠，否则立即返回 -EAGAIN ；
+/// FUTEX_WAKE 唤醒最多 val 个在 uaddr 上睡眠的线程，返回被唤醒的线程数
+pub fn sys_futex(uaddr: &AtomicI32, op: usize, val: i32) -> i32 {
+    sys_call(SyscallId::Futex, uaddr as *const AtomicI32 as usize, op, val as usize, 0, 0, 0)
+}
+
+pub fn sys_sleep(ms: usize) -> i32 {
+    sys_call(SyscallId::Sleep, ms, 0, 0, 0, 0, 0)
+}
+
+pub fn sys_yield() -> i32 {
+    sys_call(SyscallId::Yield, 0, 0, 0, 0, 0, 0)
+}
+
+/// 优先级越高得到的 CPU 时间越多，具体效果取决于内核使用的调度算法
+pub fn sys_set_priority(priority: usize) -> i32 {
+    sys_call(SyscallId::SetPriority, priority, 0, 0, 0, 0, 0)
+}
+
+pub fn sys_getpid() -> i32 {
+    sys_call(SyscallId::GetPid, 0, 0, 0, 0, 0, 0)
+}
+
+pub fn sys_getppid() -> i32 {
+    sys_call(SyscallId::GetPpid, 0, 0, 0, 0, 0, 0)
+}
+
+/// 父进程中返回子进程的 pid ，子进程中返回 0
+pub fn sys_fork() -> i32 {
+    sys_call(SyscallId::Fork, 0, 0, 0, 0, 0, 0)
+}
+
+/// 成功时不会返回
+pub fn sys_exec(path: &str) -> i32 {
+    let mut buf = [0u8; PATH_MAX];
+    match to_cstr(path, &mut buf) {
+        Some(path) => sys_call(SyscallId::Exec, path, 0, 0, 0, 0, 0),
+        None => -1,
+    }
+}
+
+const PATH_MAX: usize = 256;
+
+// 内核要求路径以 '\0' 结尾，把 s 复制到 buf 中并补上 '\0' ，返回 buf 的地址
+fn to_cstr(s: &str, buf: &mut [u8; PATH_MAX]) -> Option<usize> {
+    if s.len() >= buf.len() {
+        return None;
+    }
+    buf[..s.len()].copy_from_slice(s.as_bytes());
+    buf[s.len()] = 0;
+    Some(buf.as_ptr() as usize)
+}
+
+const AT_FDCWD: usize = -100isize as usize;
+
+pub const O_RDONLY: usize = 0;
+pub const O_WRONLY: usize = 1;
+pub const O_RDWR: usize = 2;
+pub const O_CREAT: usize = 0x40;
+pub const O_EXCL: usize = 0x80;
+pub const O_TRUNC: usize = 0x200;
+pub const O_APPEND: usize = 0x400;
+pub const O_DIRECTORY: usize = 0x1_0000;
+
+pub const SEEK_SET: usize = 0;
+pub const SEEK_CUR: usize = 1;
+pub const SEEK_END: usize = 2;
+
+/// 打开 path 所指的文件，相对路径从当前目录开始解析，返回文件描述符
+pub fn sys_open(path: &str, flags: usize) -> i32 {
+    let mut buf = [0u8; PATH_MAX];
+    match to_cstr(path, &mut buf) {
+        Some(path) => sys_call(SyscallId::OpenAt, AT_FDCWD, path, flags, 0o644, 0, 0),
+        None => -1,
+    }
+}
+
+pub fn sys_close(fd: usize) -> i32 {
+    sys_call(SyscallId::Close, fd, 0, 0, 0, 0, 0)
+}
+
+/// 移动读写位置，whence 为 SEEK_SET 、SEEK_CUR 或 SEEK_END ，返回新的位置
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> i32 {
+    sys_call(SyscallId::Lseek, fd, offset as usize, whence, 0, 0, 0)
+}
+
+/// 返回一个新的文件描述符，它与 fd 指向同一个打开的文件
+pub fn sys_dup(fd: usize) -> i32 {
+    sys_call(SyscallId::Dup, fd, 0, 0, 0, 0, 0)
+}
+
+pub fn sys_mkdir(path: &str) -> i32 {
+    let mut buf = [0u8; PATH_MAX];
+    match to_cstr(path, &mut buf) {
+        Some(path) => sys_call(SyscallId::MkdirAt, AT_FDCWD, path, 0o755, 0, 0, 0),
+        None => -1,
+    }
+}
+
+const AT_REMOVEDIR: usize = 0x200;
+
+/// 删除一个文件，文件被删除后仍然可以通过已经打开的描述符访问
+pub fn sys_unlink(path: &str) -> i32 {
+    let mut buf = [0u8; PATH_MAX];
+    match to_cstr(path, &mut buf) {
+        Some(path) => sys_call(SyscallId::UnlinkAt, AT_FDCWD, path, 0, 0, 0, 0),
+        None => -1,
+    }
+}
+
+/// 删除一个空目录
+pub fn sys_rmdir(path: &str) -> i32 {
+    let mut buf = [0u8; PATH_MAX];
+    match to_cstr(path, &mut buf) {
+        Some(path) => sys_call(SyscallId::UnlinkAt, AT_FDCWD, path, AT_REMOVEDIR, 0, 0, 0),
+        None => -1,
+    }
+}
+
+/// 为 old_path 建立一个硬链接 new_path
+pub fn sys_link(old_path: &str, new_path: &str) -> i32 {
+    let mut old_buf = [0u8; PATH_MAX];
+    let mut new_buf = [0u8; PATH_MAX];
+    match (to_cstr(old_path, &mut old_buf), to_cstr(new_path, &mut new_buf)) {
+        (Some(old), Some(new)) => sys_call(SyscallId::LinkAt, AT_FDCWD, old, AT_FDCWD, new, 0, 0),
+        _ => -1,
+    }
+}
+
+/// 重命名，new_path 已经存在时将其替换
+pub fn sys_rename(old_path: &str, new_path: &str) -> i32 {
+    let mut old_buf = [0u8; PATH_MAX];
+    let mut new_buf = [0u8; PATH_MAX];
+    match (to_cstr(old_path, &mut old_buf), to_cstr(new_path, &mut new_buf)) {
+        (Some(old), Some(new)) => sys_call(SyscallId::RenameAt, AT_FDCWD, old, AT_FDCWD, new, 0, 0),
+        _ => -1,
+    }
+}
+
+pub fn sys_ftruncate(fd: usize, len: usize) -> i32 {
+    sys_call(SyscallId::Ftruncate, fd, len, 0, 0, 0, 0)
+}
+
+/// 读取目录中的若干项，以 linux_dirent64 的格式写入 buf ，返回写入的字节数，读完时返回 0
+pub fn sys_getdents64(fd: usize, buf: &mut [u8]) -> i32 {
+    sys_call(SyscallId::GetDents64, fd, buf.as_mut_ptr() as usize, buf.len(), 0, 0, 0)
+}
+
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFBLK: u32 = 0o060000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFLNK: u32 = 0o120000;
+
+/// fstat 返回的文件信息，布局与内核中的定义一致
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct Stat {
+    pub dev: u64,
+    pub ino: u64,
+    pub mode: u32,
+    pub nlink: u32,
+    pub size: u64,
+    pub blksize: u32,
+    pub blocks: u64,
+}
+
+pub fn sys_fstat(fd: usize, stat: &mut Stat) -> i32 {
+    sys_call(SyscallId::Fstat, fd, stat as *mut Stat as usize, 0, 0, 0, 0)
+}
+
+pub const TCGETS: usize = 0x5401;
+pub const TCSETS: usize = 0x5402;
+pub const TCSETSF: usize = 0x5404;
+pub const TIOCGPGRP: usize = 0x540f;
+pub const TIOCSPGRP: usize = 0x5410;
+
+// termios 中的标志位和控制字符的下标
+pub const ICRNL: u32 = 0o400;
+pub const ISIG: u32 = 0o1;
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+pub const VINTR: usize = 0;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+
+/// 终端的设置，布局与内核中的定义一致
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Termios {
+    pub iflag: u32,
+    pub oflag: u32,
+    pub cflag: u32,
+    pub lflag: u32,
+    pub line: u8,
+    pub cc: [u8; 19],
+}
+
+/// 设备相关的控制操作，fd 不是终端时返回 -ENOTTY
+pub fn sys_ioctl(fd: usize, cmd: usize, arg: usize) -> i32 {
+    sys_call(SyscallId::Ioctl, fd, cmd, arg, 0, 0, 0)
+}
+
+pub fn sys_tcgetattr(fd: usize, termios: &mut Termios) -> i32 {
+    sys_ioctl(fd, TCGETS, termios as *mut Termios as usize)
+}
+
+/// 修改终端的设置，例如清除 ICANON 进入原始模式、清除 ECHO 关闭回显
+pub fn sys_tcsetattr(fd: usize, termios: &Termios) -> i32 {
+    sys_ioctl(fd, TCSETS, termios as *const Termios as usize)
+}
+
+/// 设置终端的前台进程组，终端中输入的 ^C 只发给这个进程组
+pub fn sys_tcsetpgrp(fd: usize, pgid: usize) -> i32 {
+    let pgid = pgid as i32;
+    sys_ioctl(fd, TIOCSPGRP, &pgid as *const i32 as usize)
+}
+
+/// 把进程 pid 移到进程组 pgid 中，pid 为 0 表示当前进程，pgid 为 0 表示与 pid 相同
+pub fn sys_setpgid(pid: usize, pgid: usize) -> i32 {
+    sys_call(SyscallId::SetPgid, pid, pgid, 0, 0, 0, 0)
+}
+
+pub fn sys_getpgid(pid: usize) -> i32 {
+    sys_call(SyscallId::GetPgid, pid, 0, 0, 0, 0, 0)
+}
+
+/// 将符号链接 path 指向的路径写入 buf ，返回写入的长度，结尾没有 '\0'
+pub fn sys_readlink(path: &str, buf: &mut [u8]) -> i32 {
+    let mut path_buf = [0u8; PATH_MAX];
+    match to_cstr(path, &mut path_buf) {
+        Some(path) => sys_call(SyscallId::ReadLinkAt, AT_FDCWD, path, buf.as_mut_ptr() as usize, buf.len(), 0, 0),
+        None => -1,
+    }
+}
+
+/// 把所有文件系统的修改写回磁盘
+pub fn sys_sync() -> i32 {
+    sys_call(SyscallId::Sync, 0, 0, 0, 0, 0, 0)
+}
+
+/// 把 fd 所在文件系统的修改写回磁盘
+pub fn sys_fsync(fd: usize) -> i32 {
+    sys_call(SyscallId::Fsync, fd, 0, 0, 0, 0, 0)
+}
+
+/// 将当前目录的绝对路径写入 buf ，以 '\0' 结尾
+pub fn sys_getcwd(buf: &mut [u8]) -> i32 {
+    sys_call(SyscallId::GetCwd, buf.as_mut_ptr() as usize, buf.len(), 0, 0, 0, 0)
+}
+
+pub fn sys_chdir(path: &str) -> i32 {
+    let mut buf = [0u8; PATH_MAX];
+    match to_cstr(path, &mut buf) {
+        Some(path) => sys_call(SyscallId::Chdir, path, 0, 0, 0, 0, 0),
+        None => -1,
+    }
+}
+
+/// 等待任意一个子进程退出，返回其 pid
+pub fn sys_wait(code: &mut i32) -> i32 {
+    sys_waitpid(-1, code)
+}
+
+/// 等待指定的子进程退出，pid 为 -1 时等待任意子进程
+pub fn sys_waitpid(pid: isize, code: &mut i32) -> i32 {
+    sys_call(SyscallId::Wait, pid as usize, code as *mut i32 as usize, 0, 0, 0, 0)
+}
+
+/// 将堆顶设置为 addr ，返回新的堆顶，addr 为 0 时只查询当前的堆顶
+pub fn sys_brk(addr: usize) -> i32 {
+    sys_call(SyscallId::Brk, addr, 0, 0, 0, 0, 0)
+}
+
+pub const PROT_READ: usize = 0x1;
+pub const PROT_WRITE: usize = 0x2;
+pub const PROT_EXEC: usize = 0x4;
+pub const MAP_PRIVATE: usize = 0x02;
+pub const MAP_FIXED: usize = 0x10;
+pub const MAP_ANONYMOUS: usize = 0x20;
+
+/// 只支持匿名映射，返回映射的起始地址，失败时返回负的错误码
+pub fn sys_mmap(addr: usize, len: usize, prot: usize, flags: usize) -> i32 {
+    sys_call(SyscallId::Mmap, addr, len, prot, flags, usize::max_value(), 0)
+}
+
+pub fn sys_munmap(addr: usize, len: usize) -> i32 {
+    sys_call(SyscallId::Munmap, addr, len, 0, 0, 0, 0)
+}
+
 enum SyscallId {
+    GetCwd = 17,
+    Dup = 23,
+    Ioctl = 29,
+    MkdirAt = 34,
+    UnlinkAt = 35,
+    LinkAt = 37,
+    RenameAt = 38,
+    Ftruncate = 46,
+    Chdir = 49,
+    OpenAt = 56,
+    Close = 57,
+    GetDents64 = 61,
+    Lseek = 62,
+    Read = 63,
     Write = 64,
+    ReadLinkAt = 78,
+    Fstat = 80,
+    Sync = 81,
+    Fsync = 82,
     Exit = 93,
-}
\ No newline at end of file
+    ExitGroup = 94,
+    Futex = 98,
+    Sleep = 101,
+    Clone = 120,
+    Yield = 124,
+    SetPriority = 140,
+    SetPgid = 154,
+    GetPgid = 155,
+    GetPid = 172,
+    GetPpid = 173,
+    GetTid = 178,
+    Brk = 214,
+    Munmap = 215,
+    Fork = 220,
+    Exec = 221,
+    Mmap = 222,
+    Wait = 260,
+}